@@ -3,9 +3,9 @@
 
 use aptos_metrics_core::{
     exponential_buckets, register_histogram, register_histogram_vec, register_int_counter,
-    Histogram, HistogramVec, IntCounter,
+    register_int_gauge, Histogram, HistogramVec, IntCounter, IntGauge,
 };
-use once_cell::sync::Lazy;
+use once_cell::sync::{Lazy, OnceCell};
 
 pub struct GasType;
 
@@ -148,13 +148,75 @@ pub static TASK_EXECUTE_SECONDS: Lazy<Histogram> = Lazy::new(|| {
     .unwrap()
 });
 
+/// Buckets of GET_NEXT_TASK_SECONDS, see set_get_next_task_buckets.
+static GET_NEXT_TASK_BUCKETS: OnceCell<Vec<f64>> = OnceCell::new();
+
+/// Overrides the buckets of GET_NEXT_TASK_SECONDS, e.g. with finer sub-microsecond buckets.
+/// Must be called at init, before GET_NEXT_TASK_SECONDS is registered (on first use). Returns
+/// false if the buckets have already been set or the default buckets have been used.
+pub fn set_get_next_task_buckets(buckets: Vec<f64>) -> bool {
+    GET_NEXT_TASK_BUCKETS.set(buckets).is_ok()
+}
+
 pub static GET_NEXT_TASK_SECONDS: Lazy<Histogram> = Lazy::new(|| {
     register_histogram!(
         // metric name
         "aptos_execution_get_next_task_seconds",
         // metric description
         "The time spent in seconds for getting next task from the scheduler",
-        exponential_buckets(/*start=*/ 1e-6, /*factor=*/ 2.0, /*count=*/ 30).unwrap(),
+        GET_NEXT_TASK_BUCKETS
+            .get_or_init(|| {
+                exponential_buckets(
+                    /*start=*/ 1e-6, /*factor=*/ 2.0, /*count=*/ 30,
+                )
+                .unwrap()
+            })
+            .clone(),
+    )
+    .unwrap()
+});
+
+/// The wave of the commit state in the Block STM scheduler, i.e. the lower bound on the wave
+/// of a successful validation required to commit the next transaction. Shows the intensity of
+/// re-validations over a block.
+pub static COMMIT_WAVE: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "aptos_execution_commit_wave",
+        "The commit wave in the Block STM scheduler"
+    )
+    .unwrap()
+});
+
+/// Approximate memory used by the Block STM scheduler's bookkeeping, observed at the end of
+/// every parallel execution. Grows with the conflict density of the block.
+pub static SCHEDULER_MEMORY_BYTES: Lazy<Histogram> = Lazy::new(|| {
+    register_histogram!(
+        "aptos_execution_scheduler_memory_bytes",
+        "The approximate memory used by the Block STM scheduler per block, in bytes",
+        exponential_buckets(/*start=*/ 1024.0, /*factor=*/ 2.0, /*count=*/ 24).unwrap(),
+    )
+    .unwrap()
+});
+
+/// Number of tasks of each kind (execution or validation) the Block STM scheduler dispatched to
+/// a worker, observed for every worker at the end of every parallel execution. A wide spread
+/// shows an uneven utilization of the workers.
+pub static WORKER_TASK_COUNT: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "aptos_execution_worker_task_count",
+        "The per-block number of Block STM tasks dispatched to a worker, by kind of task",
+        &["task"],
+        exponential_buckets(/*start=*/ 1.0, /*factor=*/ 2.0, /*count=*/ 30).unwrap(),
+    )
+    .unwrap()
+});
+
+/// Count of the spin loop iterations of Block STM workers that found no available task, a
+/// cheap proxy for the oversubscription of the worker threads.
+pub static WORKER_SPIN_COUNT: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "aptos_execution_worker_spin_count",
+        "Number of times Block STM workers spun because no task was available"
     )
     .unwrap()
 });
@@ -9,7 +9,10 @@ use crate::{
         TASK_VALIDATE_SECONDS, VM_INIT_SECONDS, WORK_WITH_TASK_SECONDS,
     },
     errors::*,
-    scheduler::{DependencyStatus, ExecutionTaskType, Scheduler, SchedulerTask, Wave},
+    scheduler::{
+        CommitGasCheck, Completion, DependencyStatus, ExecutionTaskType, HaltReason, Scheduler,
+        SchedulerConfig, SchedulerTask, Wave,
+    },
     task::{ExecutionStatus, ExecutorTask, Transaction, TransactionOutput},
     txn_last_input_output::TxnLastInputOutput,
     view::{LatestView, MVHashMapView},
@@ -63,6 +66,18 @@ impl<'a> Drop for CommitGuard<'a> {
     }
 }
 
+/// Stops committing once the accumulated execution and io gas of the committed txns reaches
+/// PER_BLOCK_GAS_LIMIT. Storage gas does not count towards the per block gas limit, as we
+/// measure execution related cost here (see the gas recorded in execute).
+#[derive(Debug)]
+struct BlockGasLimit(u64);
+
+impl CommitGasCheck for BlockGasLimit {
+    fn should_stop_commit(&self, committed_gas: u128) -> bool {
+        committed_gas >= self.0 as u128
+    }
+}
+
 #[derive(Debug)]
 enum CommitRole {
     Coordinator(Vec<Sender<TxnIndex>>),
@@ -291,13 +306,24 @@ where
             versioned_cache.delete(&k, idx_to_execute);
         }
 
+        // Record the execution and io gas (as counted towards the per-block gas limit), so that
+        // the scheduler can account for the gas of the committed incarnation.
+        let non_storage_gas = match &result {
+            ExecutionStatus::Success(output) => {
+                let fee_statement = output.fee_statement();
+                fee_statement.execution_gas_used() + fee_statement.io_gas_used()
+            },
+            _ => 0,
+        };
+        scheduler.record_gas(idx_to_execute, non_storage_gas);
+
         if last_input_output
             .record(idx_to_execute, speculative_view.take_reads(), result)
             .is_err()
         {
             // When there is module publishing r/w intersection, can early halt BlockSTM to
             // fallback to sequential execution.
-            scheduler.halt();
+            scheduler.halt_with_reason(HaltReason::ModuleConflict);
             return SchedulerTask::NoTask;
         }
         scheduler.finish_execution(idx_to_execute, incarnation, updates_outside)
@@ -311,10 +337,82 @@ where
         versioned_cache: &MVHashMap<T::Key, T::Value, X>,
         scheduler: &Scheduler,
     ) -> SchedulerTask {
+        let _timer = TASK_VALIDATE_SECONDS.start_timer();
+        let (idx_to_validate, incarnation) = version_to_validate;
+
+        if self.validate_and_try_abort(
+            version_to_validate,
+            last_input_output,
+            versioned_cache,
+            scheduler,
+        ) {
+            scheduler.finish_abort(idx_to_validate, incarnation)
+        } else {
+            scheduler.finish_validation(idx_to_validate, validation_wave);
+            SchedulerTask::NoTask
+        }
+    }
+
+    /// Validates all executed transactions in [start, end), reporting the successful validations
+    /// to the scheduler. Stops at the first transaction that gets aborted, since finish_abort
+    /// schedules all higher transactions (including the rest of the range) for re-validation.
+    fn validate_range(
+        &self,
+        start: TxnIndex,
+        end: TxnIndex,
+        validation_wave: Wave,
+        last_input_output: &TxnLastInputOutput<T::Key, E::Output, E::Error>,
+        versioned_cache: &MVHashMap<T::Key, T::Value, X>,
+        scheduler: &Scheduler,
+    ) -> SchedulerTask {
+        let _timer = TASK_VALIDATE_SECONDS.start_timer();
+
+        // Start of the current run of consecutive validated transactions.
+        let mut validated_start = start;
+        for idx_to_validate in start..end {
+            match scheduler.executed_incarnation(idx_to_validate) {
+                Some(incarnation) => {
+                    if self.validate_and_try_abort(
+                        (idx_to_validate, incarnation),
+                        last_input_output,
+                        versioned_cache,
+                        scheduler,
+                    ) {
+                        scheduler.finish_validation_range(
+                            validated_start,
+                            idx_to_validate,
+                            validation_wave,
+                        );
+                        return scheduler.finish_abort(idx_to_validate, incarnation);
+                    }
+                },
+                None => {
+                    // No longer executed (or already committed), nothing to validate.
+                    scheduler.finish_validation_range(
+                        validated_start,
+                        idx_to_validate,
+                        validation_wave,
+                    );
+                    validated_start = idx_to_validate + 1;
+                },
+            }
+        }
+        scheduler.finish_validation_range(validated_start, end, validation_wave);
+        SchedulerTask::NoTask
+    }
+
+    /// Validates the read-set of the version, and if the validation fails, tries to abort it.
+    /// Returns true iff the version was aborted, in which case the caller must finish_abort.
+    fn validate_and_try_abort(
+        &self,
+        version_to_validate: Version,
+        last_input_output: &TxnLastInputOutput<T::Key, E::Output, E::Error>,
+        versioned_cache: &MVHashMap<T::Key, T::Value, X>,
+        scheduler: &Scheduler,
+    ) -> bool {
         use MVDataError::*;
         use MVDataOutput::*;
 
-        let _timer = TASK_VALIDATE_SECONDS.start_timer();
         let (idx_to_validate, incarnation) = version_to_validate;
         let read_set = last_input_output
             .read_set(idx_to_validate)
@@ -351,12 +449,8 @@ where
             for k in last_input_output.modified_keys(idx_to_validate) {
                 versioned_cache.mark_estimate(&k, idx_to_validate);
             }
-
-            scheduler.finish_abort(idx_to_validate, incarnation)
-        } else {
-            scheduler.finish_validation(idx_to_validate, validation_wave);
-            SchedulerTask::NoTask
         }
+        aborted
     }
 
     fn coordinator_commit_hook(
@@ -399,7 +493,12 @@ where
                     self.update_parallel_txn_gas_counters(&fee_statement);
                 },
                 None => {
-                    scheduler.halt();
+                    let halt_reason = if last_input_output.is_skip_rest(txn_idx) {
+                        HaltReason::SkipRest
+                    } else {
+                        HaltReason::VmAbort
+                    };
+                    scheduler.halt_with_reason(halt_reason);
 
                     self.update_parallel_block_gas_counters(
                         accumulated_fee_statement,
@@ -410,24 +509,23 @@ where
                 },
             };
 
-            if let Some(per_block_gas_limit) = maybe_block_gas_limit {
-                // When the accumulated execution and io gas of the committed txns exceeds PER_BLOCK_GAS_LIMIT, early halt BlockSTM.
-                // Storage gas does not count towards the per block gas limit, as we measure execution related cost here.
+            // When the accumulated execution and io gas of the committed txns exceeds
+            // PER_BLOCK_GAS_LIMIT, try_commit early halts BlockSTM (see BlockGasLimit).
+            if let (Some(per_block_gas_limit), Some(HaltReason::GasLimit)) =
+                (maybe_block_gas_limit, scheduler.halt_reason())
+            {
+                // Set the execution output status to be SkipRest, to skip the rest of the txns.
+                last_input_output.update_to_skip_rest(txn_idx);
+
+                self.update_parallel_block_gas_counters(
+                    accumulated_fee_statement,
+                    (txn_idx + 1) as usize,
+                );
+                counters::PARALLEL_EXCEED_PER_BLOCK_GAS_LIMIT_COUNT.inc();
                 let accumulated_non_storage_gas = accumulated_fee_statement.execution_gas_used()
                     + accumulated_fee_statement.io_gas_used();
-                if accumulated_non_storage_gas >= per_block_gas_limit {
-                    // Set the execution output status to be SkipRest, to skip the rest of the txns.
-                    last_input_output.update_to_skip_rest(txn_idx);
-                    scheduler.halt();
-
-                    self.update_parallel_block_gas_counters(
-                        accumulated_fee_statement,
-                        (txn_idx + 1) as usize,
-                    );
-                    counters::PARALLEL_EXCEED_PER_BLOCK_GAS_LIMIT_COUNT.inc();
-                    info!("[BlockSTM]: Parallel execution early halted due to accumulated_non_storage_gas {} >= PER_BLOCK_GAS_LIMIT {}, {} txns committed", accumulated_non_storage_gas, per_block_gas_limit, txn_idx);
-                    break;
-                }
+                info!("[BlockSTM]: Parallel execution early halted due to accumulated_non_storage_gas {} >= PER_BLOCK_GAS_LIMIT {}, {} txns committed", accumulated_non_storage_gas, per_block_gas_limit, txn_idx);
+                break;
             }
 
             // Remark: When early halting the BlockSTM, we have to make sure the current / new tasks
@@ -499,6 +597,7 @@ where
         drop(init_timer);
 
         let committing = matches!(role, CommitRole::Coordinator(_));
+        let worker_id = rayon::current_thread_index().unwrap_or(0);
 
         let _timer = WORK_WITH_TASK_SECONDS.start_timer();
         let mut scheduler_task = SchedulerTask::NoTask;
@@ -539,6 +638,14 @@ where
                     versioned_cache,
                     scheduler,
                 ),
+                SchedulerTask::ValidationRange(start, end, wave) => self.validate_range(
+                    start,
+                    end,
+                    wave,
+                    last_input_output,
+                    versioned_cache,
+                    scheduler,
+                ),
                 SchedulerTask::ExecutionTask(version_to_execute, ExecutionTaskType::Execution) => {
                     self.execute(
                         version_to_execute,
@@ -559,7 +666,7 @@ where
 
                     SchedulerTask::NoTask
                 },
-                SchedulerTask::NoTask => scheduler.next_task(committing),
+                SchedulerTask::NoTask => scheduler.next_task(committing, worker_id),
                 SchedulerTask::Done => {
                     // Make sure to drain any remaining commit tasks assigned by the coordinator.
                     if let CommitRole::Worker(rx) = &role {
@@ -600,7 +707,13 @@ where
 
         let num_txns = signature_verified_block.len() as u32;
         let last_input_output = TxnLastInputOutput::new(num_txns);
-        let scheduler = Scheduler::new(num_txns);
+        let scheduler = Scheduler::with_config(num_txns, SchedulerConfig {
+            num_workers: self.executor_thread_pool.current_num_threads(),
+            commit_gas_check: self
+                .maybe_block_gas_limit
+                .map(|limit| Arc::new(BlockGasLimit(limit)) as Arc<dyn CommitGasCheck>),
+            ..SchedulerConfig::default()
+        });
 
         let mut roles: Vec<CommitRole> = vec![];
         let mut senders: Vec<Sender<u32>> = Vec::with_capacity(self.concurrency_level - 1);
@@ -635,16 +748,43 @@ where
         });
         drop(timer);
 
+        // Workers only return once the scheduler is done.
+        debug_assert!(scheduler.is_done());
+        counters::SCHEDULER_MEMORY_BYTES.observe(scheduler.approx_memory_bytes() as f64);
+        counters::WORKER_SPIN_COUNT.inc_by(scheduler.spin_iterations());
+        for (num_executions, num_validations) in scheduler.per_worker_counts() {
+            counters::WORKER_TASK_COUNT
+                .with_label_values(&["execution"])
+                .observe(num_executions as f64);
+            counters::WORKER_TASK_COUNT
+                .with_label_values(&["validation"])
+                .observe(num_validations as f64);
+        }
+        let completion = scheduler.completion();
+        #[cfg(feature = "event-log")]
+        if let Completion::Halted(reason) = completion {
+            info!(
+                "[BlockSTM]: Scheduler events before the halt ({:?}): {:?}",
+                reason,
+                scheduler.recent_events()
+            );
+        }
         let num_txns = num_txns as usize;
+        // Only the committed prefix has final outputs, the rest of the block is skipped.
+        let num_committed = scheduler.committed_prefix_len();
         // TODO: for large block sizes and many cores, extract outputs in parallel.
         let mut final_results = Vec::with_capacity(num_txns);
 
-        let maybe_err = if last_input_output.module_publishing_may_race() {
+        // Fall back to the sequential execution on a module r/w intersection, whether the
+        // scheduler halted on it, or it was recorded after a halt for another reason.
+        let maybe_err = if scheduler.halt_reason() == Some(HaltReason::ModuleConflict)
+            || last_input_output.module_publishing_may_race()
+        {
             counters::MODULE_PUBLISHING_FALLBACK_COUNT.inc();
             Some(Error::ModulePathReadWrite)
         } else {
             let mut ret = None;
-            for idx in 0..num_txns {
+            for idx in 0..num_committed {
                 match last_input_output.take_output(idx as TxnIndex) {
                     ExecutionStatus::Success(t) => final_results.push(t),
                     ExecutionStatus::SkipRest(t) => {
@@ -671,6 +811,15 @@ where
         match maybe_err {
             Some(err) => Err(err),
             None => {
+                match completion {
+                    Completion::CommittedAll => debug_assert_eq!(final_results.len(), num_txns),
+                    Completion::Halted(reason) => info!(
+                        "[BlockSTM]: Parallel execution halted ({:?}), skipping the last {} txns.",
+                        reason,
+                        num_txns - final_results.len()
+                    ),
+                    Completion::Running => unreachable!("The scheduler is done"),
+                }
                 final_results.resize_with(num_txns, E::Output::skip_output);
                 Ok(final_results)
             },
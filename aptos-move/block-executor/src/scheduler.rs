@@ -2,25 +2,87 @@
 // Parts of the project are originally copyright © Meta Platforms, Inc.
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::counters::GET_NEXT_TASK_SECONDS;
+use crate::counters::{COMMIT_WAVE, GET_NEXT_TASK_SECONDS};
 use aptos_infallible::Mutex;
+use aptos_metrics_core::IntGauge;
 use aptos_mvhashmap::types::{Incarnation, TxnIndex, Version};
 use crossbeam::utils::CachePadded;
-use parking_lot::{RwLock, RwLockUpgradableReadGuard};
+use once_cell::sync::OnceCell;
+use parking_lot::{RwLock, RwLockReadGuard, RwLockUpgradableReadGuard};
+#[cfg(test)]
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
 use std::{
     cmp::{max, min},
+    fmt::Debug,
     hint,
+    mem::size_of,
     ops::DerefMut,
     sync::{
         atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
         Arc, Condvar,
     },
+    thread::{self, Thread},
+    time::{Duration, Instant},
 };
 
 const TXN_IDX_MASK: u64 = (1 << 32) - 1;
 
+/// Number of attempts try_commit makes to acquire a contended validation status read lock
+/// of an executed transaction, before falling back to a blocking read.
+const COMMIT_VALIDATION_LOCK_SPINS: usize = 32;
+
 pub type Wave = u32;
 
+/// Source of time for the time-based decisions of the scheduler (e.g. timeouts), which should
+/// never call Instant::now() directly, so that they can be tested deterministically.
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> Instant;
+}
+
+/// The default clock, based on the system's monotonic clock.
+#[derive(Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Predicate identifying transactions whose module reads / writes intersect with those of other
+/// transactions in the block (scenario 1 in the documentation of halt_with_reason). When such a
+/// transaction finishes execution, the scheduler halts with HaltReason::ModuleConflict, so that
+/// the block falls back to the sequential execution.
+pub trait ModuleConflictCheck: Debug + Send + Sync {
+    fn is_module_conflict(&self, txn_idx: TxnIndex) -> bool;
+}
+
+/// Decides when to stop committing due to the per-block gas limit (scenario 4 in the
+/// documentation of halt_with_reason), based on the accumulated gas of the committed
+/// transactions, as recorded by record_gas. Consulted by try_commit after every commit. The gas
+/// accounting itself remains in the VM.
+pub trait CommitGasCheck: Debug + Send + Sync {
+    fn should_stop_commit(&self, committed_gas: u128) -> bool;
+}
+
+/// Decides the new wave when the validation index is decreased (see decrease_validation_idx).
+/// The returned wave is raised to at least wave + 1 if needed, as waves must strictly increase
+/// for try_commit to not rely on validations that happened before the decrease.
+pub trait WaveStrategy: Debug + Send + Sync {
+    fn next_wave(&self, wave: Wave) -> Wave;
+}
+
+/// The default strategy, incrementing the wave by one.
+#[derive(Debug, Default)]
+pub struct IncrementWave;
+
+impl WaveStrategy for IncrementWave {
+    fn next_wave(&self, wave: Wave) -> Wave {
+        wave + 1
+    }
+}
+
 #[derive(Debug)]
 pub enum DependencyStatus {
     // The dependency is not resolved yet.
@@ -48,18 +110,135 @@ pub enum ExecutionTaskType {
     Wakeup(DependencyCondvar),
 }
 
+/// A state transition of a transaction in the Scheduler, recorded (with the event-log feature)
+/// in a bounded log of the most recent events, for post-mortem debugging.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SchedulerEvent {
+    /// The version started (or resumed) executing.
+    Execute(Version),
+    /// The version was claimed for validation in the given wave.
+    Validate(Version, Wave),
+    /// The version was aborted after a failed validation.
+    Abort(Version),
+    /// The version was committed, with the given commit wave.
+    Commit(Version, Wave),
+}
+
+/// Bounded log of the most recent SchedulerEvents. Each event takes a sequence number and
+/// overwrites the slot of the event recorded capacity events earlier, locking only that slot.
+#[cfg(feature = "event-log")]
+struct EventLog {
+    next_seq: AtomicU64,
+    slots: Vec<EventSlot>,
+}
+
+/// An event with its sequence number, if any was recorded in the slot.
+#[cfg(feature = "event-log")]
+type EventSlot = CachePadded<Mutex<Option<(u64, SchedulerEvent)>>>;
+
+#[cfg(feature = "event-log")]
+impl EventLog {
+    fn new(capacity: usize) -> Self {
+        Self {
+            next_seq: AtomicU64::new(0),
+            slots: (0..capacity)
+                .map(|_| CachePadded::new(Mutex::new(None)))
+                .collect(),
+        }
+    }
+
+    fn record(&self, event: SchedulerEvent) {
+        if self.slots.is_empty() {
+            return;
+        }
+        let seq = self.next_seq.fetch_add(1, Ordering::Relaxed);
+        let mut slot = self.slots[(seq % self.slots.len() as u64) as usize].lock();
+        // A slower thread may still hold an older sequence number for the same slot.
+        if slot.map_or(true, |(slot_seq, _)| slot_seq < seq) {
+            *slot = Some((seq, event));
+        }
+    }
+
+    fn recent_events(&self) -> Vec<SchedulerEvent> {
+        let mut events: Vec<_> = self.slots.iter().filter_map(|slot| *slot.lock()).collect();
+        events.sort_unstable_by_key(|(seq, _)| *seq);
+        events.into_iter().map(|(_, event)| event).collect()
+    }
+}
+
+/// The reason for early halting the parallel execution (see halt_with_reason).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HaltReason {
+    /// A module publishing txn has read/write intersection with other txns.
+    ModuleConflict,
+    /// A committed txn has VM execution status Abort.
+    VmAbort,
+    /// A committed txn has VM execution status SkipRest.
+    SkipRest,
+    /// The committed txns have exceeded the per-block gas limit.
+    GasLimit,
+}
+
+/// The state of the Scheduler w.r.t. finishing the parallel execution.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Completion {
+    /// Transactions are still being executed, validated or committed.
+    Running,
+    /// All transactions have been committed.
+    CommittedAll,
+    /// The execution was halted early, with the given reason.
+    Halted(HaltReason),
+}
+
 /// A holder for potential task returned from the Scheduler. ExecutionTask and ValidationTask
 /// each contain a version of transaction that must be executed or validated, respectively.
+/// ValidationRange(start, end, wave) is returned instead of validation tasks for consecutive
+/// executed transactions in [start, end), if enabled by SchedulerConfig::max_validation_range.
 /// NoTask holds no task (similar None if we wrapped tasks in Option), and Done implies that
 /// there are no more tasks and the scheduler is done.
 #[derive(Debug)]
 pub enum SchedulerTask {
     ExecutionTask(Version, ExecutionTaskType),
     ValidationTask(Version, Wave),
+    ValidationRange(TxnIndex, TxnIndex, Wave),
     NoTask,
     Done,
 }
 
+/// A serializable projection of a SchedulerTask, e.g. for logging scheduler decisions and
+/// replaying them. Captures the variant and version / wave, but not the condition variable
+/// of a Wakeup task (that can't be serialized).
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SchedulerTaskRecord {
+    ExecutionTask(Version),
+    WakeupTask(Version),
+    ValidationTask(Version, Wave),
+    ValidationRange(TxnIndex, TxnIndex, Wave),
+    NoTask,
+    Done,
+}
+
+impl From<&SchedulerTask> for SchedulerTaskRecord {
+    fn from(task: &SchedulerTask) -> Self {
+        match task {
+            SchedulerTask::ExecutionTask(version, ExecutionTaskType::Execution) => {
+                SchedulerTaskRecord::ExecutionTask(*version)
+            },
+            SchedulerTask::ExecutionTask(version, ExecutionTaskType::Wakeup(_)) => {
+                SchedulerTaskRecord::WakeupTask(*version)
+            },
+            SchedulerTask::ValidationTask(version, wave) => {
+                SchedulerTaskRecord::ValidationTask(*version, *wave)
+            },
+            SchedulerTask::ValidationRange(start, end, wave) => {
+                SchedulerTaskRecord::ValidationRange(*start, *end, *wave)
+            },
+            SchedulerTask::NoTask => SchedulerTaskRecord::NoTask,
+            SchedulerTask::Done => SchedulerTaskRecord::Done,
+        }
+    }
+}
+
 /////////////////////////////// Explanation for ExecutionStatus ///////////////////////////////
 /// All possible execution status for each transaction. In the explanation below, we abbreviate
 /// 'execution status' as 'status'. Each status contains the latest incarnation number,
@@ -105,17 +284,67 @@ pub enum SchedulerTask {
 ///    ↓                finish_abort                                                         |
 /// Aborting(i) ---------------------------------------------------------> Ready(i+1)      ---
 ///
-#[derive(Debug)]
-enum ExecutionStatus {
-    Ready(Incarnation, ExecutionTaskType),
+/// When serialized (e.g. for logging), the condition variables are skipped.
+#[derive(Debug, Serialize)]
+pub enum ExecutionStatus {
+    Ready(Incarnation, #[serde(skip)] ExecutionTaskType),
     Executing(Incarnation),
-    Suspended(Incarnation, DependencyCondvar),
+    Suspended(Incarnation, #[serde(skip)] DependencyCondvar),
     Executed(Incarnation),
     Committed(Incarnation),
     Aborting(Incarnation),
     ExecutionHalted,
 }
 
+/// Payload-free counterpart of ExecutionStatus, convenient for matching on the status.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ExecutionStatusKind {
+    Ready,
+    Executing,
+    Suspended,
+    Executed,
+    Committed,
+    Aborting,
+    ExecutionHalted,
+}
+
+impl ExecutionStatus {
+    /// Returns the incarnation of the status, or None for ExecutionHalted.
+    pub fn incarnation(&self) -> Option<Incarnation> {
+        use ExecutionStatus::*;
+        match self {
+            Ready(incarnation, _)
+            | Executing(incarnation)
+            | Suspended(incarnation, _)
+            | Executed(incarnation)
+            | Committed(incarnation)
+            | Aborting(incarnation) => Some(*incarnation),
+            ExecutionHalted => None,
+        }
+    }
+
+    /// Returns the kind of the status, i.e. the variant without the payload.
+    pub fn kind(&self) -> ExecutionStatusKind {
+        match self {
+            ExecutionStatus::Ready(_, _) => ExecutionStatusKind::Ready,
+            ExecutionStatus::Executing(_) => ExecutionStatusKind::Executing,
+            ExecutionStatus::Suspended(_, _) => ExecutionStatusKind::Suspended,
+            ExecutionStatus::Executed(_) => ExecutionStatusKind::Executed,
+            ExecutionStatus::Committed(_) => ExecutionStatusKind::Committed,
+            ExecutionStatus::Aborting(_) => ExecutionStatusKind::Aborting,
+            ExecutionStatus::ExecutionHalted => ExecutionStatusKind::ExecutionHalted,
+        }
+    }
+}
+
+/// Returned by try_abort_detailed when the version to abort is not in the Executed status,
+/// recording the observed status of the transaction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AbortRejection {
+    pub kind: ExecutionStatusKind,
+    pub incarnation: Option<Incarnation>,
+}
+
 impl PartialEq for ExecutionStatus {
     fn eq(&self, other: &Self) -> bool {
         use ExecutionStatus::*;
@@ -157,11 +386,12 @@ impl PartialEq for ExecutionStatus {
 /// validation, in particular, after aborts and executions that write outside of the write set of
 /// the same transaction's previous incarnation.
 ///
-/// In 'commit_state', the first element records the next transaction to commit, and the
-/// second element records the lower bound on the wave of a validation that must be successful
-/// in order to commit the next transaction. The wave is updated in try_commit, upon seeing an
-/// executed txn with higher max_triggered_wave. Note that the wave is *not* updated with the
-/// required_wave of the txn that is being committed.
+/// In 'commit_state', the first element records the next transaction to commit, the second
+/// element records the lower bound on the wave of a validation that must be successful in order
+/// to commit the next transaction, and the third element the accumulated gas of the committed
+/// transactions. The wave is updated in try_commit, upon seeing an executed txn with higher
+/// max_triggered_wave. Note that the wave is *not* updated with the required_wave of the txn
+/// that is being committed.
 ///
 ///
 /////////////////////////////// Algorithm Description for Updating Waves ///////////////////////////////
@@ -208,9 +438,9 @@ pub struct Scheduler {
     /// An index i maps to the most up-to-date status of transaction i.
     txn_status: Vec<CachePadded<(RwLock<ExecutionStatus>, RwLock<ValidationStatus>)>>,
 
-    /// Next transaction to commit, and sweeping lower bound on the wave of a validation that must
-    /// be successful in order to commit the next transaction.
-    commit_state: CachePadded<Mutex<(TxnIndex, Wave)>>,
+    /// Next transaction to commit, sweeping lower bound on the wave of a validation that must
+    /// be successful in order to commit the next transaction, and the gas of committed txns.
+    commit_state: CachePadded<Mutex<(TxnIndex, Wave, u128)>>,
 
     // Note: with each thread reading both counters when deciding the next task, and being able
     // to choose either execution or validation task, separately padding these indices may increase
@@ -238,13 +468,110 @@ pub struct Scheduler {
 
     /// Shared marker that is set when a thread detects that all txns can be committed.
     done_marker: CachePadded<AtomicBool>,
+    /// The reason of the first call to halt the execution early, if any.
+    halt_reason: OnceCell<HaltReason>,
+    /// A worker_id maps to the number of execution and validation tasks dispatched to it.
+    /// Padded to avoid false sharing between the workers.
+    worker_task_counts: Vec<CachePadded<(AtomicU64, AtomicU64)>>,
+    /// Number of times next_task found no available tasks and spun (with WaitStrategy::Spin),
+    /// a cheap proxy for the oversubscription of the worker threads.
+    spin_iterations: CachePadded<AtomicU64>,
+    /// Threads currently parked in next_task (with WaitStrategy::Park), to be woken up
+    /// when the scheduler is done.
+    parked_workers: Mutex<Vec<Thread>>,
+
+    /// An index i maps to the length of the chain of suspended transactions, starting with
+    /// transaction i, when i is suspended (and to 0 otherwise). Updated on suspend and resume,
+    /// and only used (approximately, hence relaxed accesses) to bound the dependency depth.
+    dependency_depth: Vec<AtomicU32>,
+    /// An index i maps to the gas used by the latest execution of transaction i, as recorded
+    /// by record_gas, and accumulated when the transaction is committed.
+    txn_gas: Vec<AtomicU64>,
+
+    /// The most recent state transitions of the transactions.
+    #[cfg(feature = "event-log")]
+    event_log: EventLog,
+
+    /// Configuration, immutable.
+    config: SchedulerConfig,
+
+    /// If set (by set_seed), decides between available execution and validation tasks.
+    #[cfg(test)]
+    rng: Mutex<Option<StdRng>>,
+}
+
+/// How a (non-committing) thread waits when next_task finds no available task.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WaitStrategy {
+    /// Issue a spin loop hint and return NoTask right away.
+    Spin,
+    /// Park the thread for the given duration (according to the configured clock) before
+    /// returning NoTask. Parked threads are woken up when the execution is halted or all
+    /// transactions are committed, while newly available tasks are picked up after the timeout.
+    /// Not selected by the executor yet.
+    #[allow(dead_code)]
+    Park(Duration),
+}
+
+/// Configuration knobs of the Scheduler. The default values preserve the original behavior of
+/// the scheduler, and are also the values used on the hot path.
+#[derive(Clone, Debug)]
+pub struct SchedulerConfig {
+    /// Maximum length of a dependency chain of suspended transactions. A transaction that would
+    /// exceed it when waiting on a dependency is not suspended, and instead has to repeat the
+    /// read (busy-retry), trading CPU for not stalling a deep tail behind a single transaction.
+    pub max_dependency_depth: u32,
+    /// Clock consulted by all time-based decisions.
+    pub clock: Arc<dyn Clock>,
+    /// How threads wait when there are no available tasks.
+    pub wait_strategy: WaitStrategy,
+    /// Maximum number of consecutive executed transactions returned in a single ValidationRange
+    /// task. With 1 (default), only single ValidationTasks are returned.
+    pub max_validation_range: u32,
+    /// Gauge set to the commit wave whenever try_commit increases it.
+    pub commit_wave_gauge: IntGauge,
+    /// Number of worker threads calling next_task, for the per-worker counts of tasks.
+    pub num_workers: usize,
+    /// If set, consulted by finish_execution to halt on module r/w intersections.
+    pub module_conflict_check: Option<Arc<dyn ModuleConflictCheck>>,
+    /// If set, consulted by try_commit to stop committing at the per-block gas limit.
+    pub commit_gas_check: Option<Arc<dyn CommitGasCheck>>,
+    /// Decides the new wave whenever the validation index is decreased.
+    pub wave_strategy: Arc<dyn WaveStrategy>,
+    /// Number of the most recent SchedulerEvents retained (0 disables the recording).
+    #[cfg(feature = "event-log")]
+    pub event_log_capacity: usize,
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        Self {
+            max_dependency_depth: u32::MAX,
+            clock: Arc::new(SystemClock),
+            wait_strategy: WaitStrategy::Spin,
+            max_validation_range: 1,
+            commit_wave_gauge: COMMIT_WAVE.clone(),
+            num_workers: num_cpus::get(),
+            module_conflict_check: None,
+            commit_gas_check: None,
+            wave_strategy: Arc::new(IncrementWave),
+            #[cfg(feature = "event-log")]
+            event_log_capacity: 1024,
+        }
+    }
 }
 
 /// Public Interfaces for the Scheduler
 impl Scheduler {
-    pub fn new(num_txns: TxnIndex) -> Self {
+    pub fn with_config(num_txns: TxnIndex, config: SchedulerConfig) -> Self {
         // Empty block should early return and not create a scheduler.
         assert!(num_txns > 0, "No scheduler needed for 0 transactions");
+        assert!(
+            config.max_dependency_depth > 0,
+            "Dependency depth must allow at least one suspended transaction"
+        );
+        // The commit wave starts from 0 for every block.
+        config.commit_wave_gauge.set(0);
 
         Self {
             num_txns,
@@ -259,10 +586,23 @@ impl Scheduler {
                     ))
                 })
                 .collect(),
-            commit_state: CachePadded::new(Mutex::new((0, 0))),
+            commit_state: CachePadded::new(Mutex::new((0, 0, 0))),
             execution_idx: AtomicU32::new(0),
             validation_idx: AtomicU64::new(0),
             done_marker: CachePadded::new(AtomicBool::new(false)),
+            halt_reason: OnceCell::new(),
+            worker_task_counts: (0..config.num_workers)
+                .map(|_| CachePadded::new((AtomicU64::new(0), AtomicU64::new(0))))
+                .collect(),
+            spin_iterations: CachePadded::new(AtomicU64::new(0)),
+            parked_workers: Mutex::new(Vec::new()),
+            dependency_depth: (0..num_txns).map(|_| AtomicU32::new(0)).collect(),
+            txn_gas: (0..num_txns).map(|_| AtomicU64::new(0)).collect(),
+            #[cfg(feature = "event-log")]
+            event_log: EventLog::new(config.event_log_capacity),
+            config,
+            #[cfg(test)]
+            rng: Mutex::new(None),
         }
     }
 
@@ -270,15 +610,55 @@ impl Scheduler {
         self.num_txns
     }
 
+    /// Returns an approximation of the memory used by the scheduler's bookkeeping, in bytes:
+    /// the fixed fields, the per-transaction statuses, and the dependency vectors, whose
+    /// size depends on the conflict density of the block. Read-only, O(num_txns).
+    pub fn approx_memory_bytes(&self) -> usize {
+        let dependents_bytes: usize = self
+            .txn_dependency
+            .iter()
+            .map(|deps| deps.lock().capacity() * size_of::<TxnIndex>())
+            .sum();
+
+        size_of::<Self>()
+            + self.txn_dependency.capacity() * size_of::<CachePadded<Mutex<Vec<TxnIndex>>>>()
+            + dependents_bytes
+            + self.txn_status.capacity()
+                * size_of::<CachePadded<(RwLock<ExecutionStatus>, RwLock<ValidationStatus>)>>()
+            + self.dependency_depth.capacity() * size_of::<AtomicU32>()
+            + self.txn_gas.capacity() * size_of::<AtomicU64>()
+    }
+
+    /// Returns the current time, according to the configured clock.
+    pub fn now(&self) -> Instant {
+        self.config.clock.now()
+    }
+
+    /// Returns true iff at least timeout has elapsed since start, according to the configured
+    /// clock. Building block for timeouts.
+    pub fn timed_out(&self, start: Instant, timeout: Duration) -> bool {
+        self.now().saturating_duration_since(start) >= timeout
+    }
+
+    /// Records the gas used by the latest execution of txn_idx. Should be called before
+    /// finish_execution, so that the gas of the committed incarnation is accounted for.
+    pub fn record_gas(&self, txn_idx: TxnIndex, gas: u64) {
+        self.txn_gas[txn_idx as usize].store(gas, Ordering::Relaxed);
+    }
+
     /// If successful, returns Some(TxnIndex), the index of committed transaction.
     /// The current implementation has one dedicated thread to try_commit.
     /// Should not be called after the last transaction is committed.
     pub fn try_commit(&self) -> Option<TxnIndex> {
         let mut commit_state_mutex = self.commit_state.lock();
         let commit_state = commit_state_mutex.deref_mut();
-        let (commit_idx, commit_wave) = (&mut commit_state.0, &mut commit_state.1);
+        let (commit_idx, commit_wave, committed_gas) = (
+            &mut commit_state.0,
+            &mut commit_state.1,
+            &mut commit_state.2,
+        );
 
-        if let Some(validation_status) = self.txn_status[*commit_idx as usize].1.try_read() {
+        if let Some(validation_status) = self.try_read_validation_status_for_commit(*commit_idx) {
             // Acquired the validation status read lock.
             if let Some(status) = self.txn_status[*commit_idx as usize]
                 .0
@@ -292,18 +672,39 @@ impl Scheduler {
                     // since max_triggered_wave records the new wave when validation index is
                     // decreased thus affecting all later txns as well,
                     // while required_wave only records the new wave for one single txn.
-                    *commit_wave = max(*commit_wave, validation_status.max_triggered_wave);
+                    if validation_status.max_triggered_wave > *commit_wave {
+                        *commit_wave = validation_status.max_triggered_wave;
+                        self.config.commit_wave_gauge.set(*commit_wave as i64);
+                    }
                     if let Some(validated_wave) = validation_status.maybe_max_validated_wave {
                         if validated_wave >= max(*commit_wave, validation_status.required_wave) {
                             let mut status_write = RwLockUpgradableReadGuard::upgrade(status);
                             // Upgrade the execution status read lock to write lock.
                             // Can commit.
                             *status_write = ExecutionStatus::Committed(incarnation);
-
+                            // Halting below acquires the execution status locks.
+                            drop(status_write);
+                            self.record_event(SchedulerEvent::Commit(
+                                (*commit_idx, incarnation),
+                                *commit_wave,
+                            ));
+
+                            *committed_gas +=
+                                self.txn_gas[*commit_idx as usize].load(Ordering::Relaxed) as u128;
                             *commit_idx += 1;
                             if *commit_idx == self.num_txns {
                                 // All txns have been committed, the parallel execution can finish.
                                 self.done_marker.store(true, Ordering::SeqCst);
+                                self.unpark_workers();
+                            } else if self
+                                .config
+                                .commit_gas_check
+                                .as_ref()
+                                .map_or(false, |check| check.should_stop_commit(*committed_gas))
+                            {
+                                // Keep the committed prefix. As halting sets the status of all
+                                // transactions to ExecutionHalted, nothing else can be committed.
+                                self.halt_with_reason(HaltReason::GasLimit);
                             }
                             return Some(*commit_idx - 1);
                         }
@@ -314,6 +715,27 @@ impl Scheduler {
         None
     }
 
+    /// Returns the most recent SchedulerEvents (at most event_log_capacity), oldest first.
+    #[cfg(feature = "event-log")]
+    pub fn recent_events(&self) -> Vec<SchedulerEvent> {
+        self.event_log.recent_events()
+    }
+
+    /// Returns the number of committed transactions, i.e. the length of the committed prefix of
+    /// the block, including after the execution was halted (whose outputs are kept).
+    pub fn committed_prefix_len(&self) -> usize {
+        self.commit_state.lock().0 as usize
+    }
+
+    #[cfg(test)]
+    /// Makes the choice between available execution and validation tasks in next_task random,
+    /// driven by the seed, instead of always preferring the lower index. The indices of the
+    /// tasks are still handed out in order, so with a single thread stepping the tasks, the
+    /// schedule only depends on the seed.
+    pub fn set_seed(&self, seed: u64) {
+        *self.rng.lock() = Some(StdRng::seed_from_u64(seed));
+    }
+
     #[cfg(test)]
     /// Return the TxnIndex and Wave of current commit index
     pub fn commit_state(&self) -> (TxnIndex, u32) {
@@ -327,6 +749,16 @@ impl Scheduler {
     /// returns false. Since incarnation numbers never decrease, this also ensures
     /// that the same version may not successfully abort more than once.
     pub fn try_abort(&self, txn_idx: TxnIndex, incarnation: Incarnation) -> bool {
+        self.try_abort_detailed(txn_idx, incarnation).is_ok()
+    }
+
+    /// Same as try_abort, but if the abort is unsuccessful, returns the observed status of
+    /// the transaction (e.g. already Aborting or Committed).
+    pub fn try_abort_detailed(
+        &self,
+        txn_idx: TxnIndex,
+        incarnation: Incarnation,
+    ) -> Result<(), AbortRejection> {
         // lock the execution status.
         // Note: we could upgradable read, then upgrade and write. Similar for other places.
         // However, it is likely an overkill (and overhead to actually upgrade),
@@ -335,57 +767,58 @@ impl Scheduler {
 
         if *status == ExecutionStatus::Executed(incarnation) {
             *status = ExecutionStatus::Aborting(incarnation);
-            true
+            self.record_event(SchedulerEvent::Abort((txn_idx, incarnation)));
+            Ok(())
         } else {
-            false
+            Err(AbortRejection {
+                kind: status.kind(),
+                incarnation: status.incarnation(),
+            })
         }
     }
 
-    /// Return the next task for the thread.
-    pub fn next_task(&self, committing: bool) -> SchedulerTask {
+    /// Return the next task for the thread. The worker_id (in [0, num_workers)) identifies the
+    /// calling thread in the per-worker counts of dispatched tasks.
+    /// All threads (including the committing thread) are handed the lowest available index
+    /// among the execution and validation tasks, i.e. the work closest to the commit frontier.
+    pub fn next_task(&self, committing: bool, worker_id: usize) -> SchedulerTask {
         let _timer = GET_NEXT_TASK_SECONDS.start_timer();
-        loop {
-            if self.done() {
-                // No more tasks.
-                return SchedulerTask::Done;
-            }
+        let task = self.find_next_task(committing);
 
-            let (idx_to_validate, wave) =
-                Self::unpack_validation_idx(self.validation_idx.load(Ordering::Acquire));
-            let idx_to_execute = self.execution_idx.load(Ordering::Acquire);
-
-            let prefer_validate = idx_to_validate < min(idx_to_execute, self.num_txns)
-                && !self.never_executed(idx_to_validate);
-
-            if !prefer_validate && idx_to_execute >= self.num_txns {
-                return if self.done() {
-                    // Check again to avoid commit delay due to a race.
-                    SchedulerTask::Done
-                } else {
-                    if !committing {
-                        // Avoid pointlessly spinning, and give priority to other threads
-                        // that may be working to finish the remaining tasks.
-                        // We don't want to hint on the thread that is committing
-                        // because it may have work to do (to commit) even if there
-                        // is no more conventional (validation and execution tasks) work.
-                        hint::spin_loop();
-                    }
-                    SchedulerTask::NoTask
-                };
-            }
-
-            if prefer_validate {
-                if let Some((version_to_validate, wave)) =
-                    self.try_validate_next_version(idx_to_validate, wave)
-                {
-                    return SchedulerTask::ValidationTask(version_to_validate, wave);
-                }
-            } else if let Some((version_to_execute, execution_task_type)) =
-                self.try_execute_next_version()
-            {
-                return SchedulerTask::ExecutionTask(version_to_execute, execution_task_type);
+        if let Some(counts) = self.worker_task_counts.get(worker_id) {
+            match &task {
+                SchedulerTask::ExecutionTask(_, _) => {
+                    counts.0.fetch_add(1, Ordering::Relaxed);
+                },
+                SchedulerTask::ValidationTask(_, _) => {
+                    counts.1.fetch_add(1, Ordering::Relaxed);
+                },
+                SchedulerTask::ValidationRange(start, end, _) => {
+                    counts.1.fetch_add((end - start) as u64, Ordering::Relaxed);
+                },
+                SchedulerTask::NoTask | SchedulerTask::Done => (),
             }
         }
+        task
+    }
+
+    /// Returns the number of times next_task spun because no tasks were available.
+    pub fn spin_iterations(&self) -> u64 {
+        self.spin_iterations.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of (execution, validation) tasks dispatched to each worker by
+    /// next_task, which helps diagnose an uneven utilization of the workers.
+    pub fn per_worker_counts(&self) -> Vec<(u64, u64)> {
+        self.worker_task_counts
+            .iter()
+            .map(|counts| {
+                (
+                    counts.0.load(Ordering::Relaxed),
+                    counts.1.load(Ordering::Relaxed),
+                )
+            })
+            .collect()
     }
 
     /// When a txn depends on another txn, adds it to the dependency list of the other txn.
@@ -394,6 +827,8 @@ impl Scheduler {
     /// transaction txn_idx will be resumed, and corresponding execution task created.
     /// If false is returned, it is caller's responsibility to repeat the read that caused the
     /// dependency and continue the ongoing execution of txn_idx.
+    /// Resolved is also returned (without adding a dependency) if suspending txn_idx would
+    /// create a chain of suspended transactions longer than the configured max_dependency_depth.
     pub fn wait_for_dependency(
         &self,
         txn_idx: TxnIndex,
@@ -422,6 +857,13 @@ impl Scheduler {
             return DependencyResult::Resolved;
         }
 
+        // dep_txn_idx may itself be suspended, in which case txn_idx would extend its chain.
+        let depth = self.dependency_depth[dep_txn_idx as usize].load(Ordering::Relaxed) + 1;
+        if depth > self.config.max_dependency_depth {
+            // Do not suspend, the caller will repeat the read (until dependency is resolved).
+            return DependencyResult::Resolved;
+        }
+
         // If the execution is already halted, suspend will return false.
         // The synchronization is guaranteed by the Mutex around txn_status.
         // If the execution is halted, the first finishing thread will first set the status of each txn
@@ -431,9 +873,14 @@ impl Scheduler {
         if !self.suspend(txn_idx, dep_condvar.clone()) {
             return DependencyResult::ExecutionHalted;
         }
+        self.dependency_depth[txn_idx as usize].store(depth, Ordering::Relaxed);
 
         // Safe to add dependency here (still holding the lock) - finish_execution of txn
         // dep_txn_idx is guaranteed to acquire the same lock later and clear the dependency.
+        // txn_idx can't already be a dependent: it was Executing (checked by suspend), i.e.
+        // it was resumed and the list containing it was taken by finish_execution, before any
+        // later incarnation could wait on dep_txn_idx again.
+        debug_assert!(!stored_deps.contains(&txn_idx));
         stored_deps.push(txn_idx);
 
         // Stored deps gets unlocked here.
@@ -450,16 +897,43 @@ impl Scheduler {
         );
     }
 
+    /// Finalize the successful validations of all transactions in [start, end) with the
+    /// given wave, e.g. for the (parts of a) ValidationRange task that were validated.
+    pub fn finish_validation_range(&self, start: TxnIndex, end: TxnIndex, wave: Wave) {
+        for txn_idx in start..end {
+            self.finish_validation(txn_idx, wave);
+        }
+    }
+
+    /// Returns Some(incarnation) iff the transaction's status is Executed(incarnation).
+    /// Used by the callers to determine the versions to validate in a ValidationRange.
+    pub fn executed_incarnation(&self, txn_idx: TxnIndex) -> Option<Incarnation> {
+        self.is_executed(txn_idx, false)
+    }
+
     /// After txn is executed, schedule its dependencies for re-execution.
     /// If revalidate_suffix is true, decrease validation_idx to schedule all higher transactions
     /// for (re-)validation. Otherwise, in some cases (if validation_idx not already lower),
     /// return a validation task of the transaction to the caller (otherwise NoTask).
+    /// If the configured ModuleConflictCheck flags the transaction, halts the scheduler instead.
     pub fn finish_execution(
         &self,
         txn_idx: TxnIndex,
         incarnation: Incarnation,
         revalidate_suffix: bool,
     ) -> SchedulerTask {
+        if self
+            .config
+            .module_conflict_check
+            .as_ref()
+            .map_or(false, |check| check.is_module_conflict(txn_idx))
+        {
+            // Early halt, also resolving the conditional variables of all suspended
+            // transactions (including the dependencies of txn_idx).
+            self.halt_with_reason(HaltReason::ModuleConflict);
+            return SchedulerTask::NoTask;
+        }
+
         // Note: It is preferable to hold the validation lock throughout the finish_execution,
         // in particular before updating execution status. The point was that we don't want
         // any validation to come before the validation status is correspondingly updated.
@@ -470,24 +944,17 @@ impl Scheduler {
         let mut validation_status = self.txn_status[txn_idx as usize].1.write();
         self.set_executed_status(txn_idx, incarnation);
 
-        let txn_deps: Vec<TxnIndex> = {
+        let mut txn_deps: Vec<TxnIndex> = {
             let mut stored_deps = self.txn_dependency[txn_idx as usize].lock();
             // Holding the lock, take dependency vector.
             std::mem::take(&mut stored_deps)
         };
+        // Resume in increasing index order, so that the lowest dependencies (that will be
+        // picked up first after execution_idx is decreased) become ready as early as possible.
+        txn_deps.sort_unstable();
 
         // Mark dependencies as resolved and find the minimum index among them.
-        let min_dep = txn_deps
-            .into_iter()
-            .map(|dep| {
-                // Mark the status of dependencies as 'Ready' since dependency on
-                // transaction txn_idx is now resolved.
-                self.resume(dep);
-
-                dep
-            })
-            .min();
-        if let Some(execution_target_idx) = min_dep {
+        if let Some(execution_target_idx) = self.resume_many(&txn_deps) {
             // Decrease the execution index as necessary to ensure resolved dependencies
             // get a chance to be re-executed.
             self.execution_idx
@@ -560,7 +1027,8 @@ impl Scheduler {
     }
 
     /// This function can halt the BlockSTM early, even if there are unfinished tasks.
-    /// It will set the done_marker to be true, resolve all pending dependencies.
+    /// It will set the done_marker to be true, resolve all pending dependencies, and record
+    /// the reason for halting, available via halt_reason.
     ///
     /// Currently there are 4 scenarios to early halt the BlockSTM execution.
     /// 1. There is a module publishing txn that has read/write intersection with any txns even during speculative execution.
@@ -570,7 +1038,12 @@ impl Scheduler {
     ///
     /// For scenarios 1 and 2, only the error will be returned as the output of the block execution.
     /// For scenarios 3 and 4, the execution outputs of the committed txn prefix will be returned.
-    pub fn halt(&self) {
+    ///
+    /// If halted multiple times (possibly concurrently), the reason of the first call is kept.
+    pub fn halt_with_reason(&self, reason: HaltReason) {
+        // Record the reason before setting done_marker, so it's available once halted.
+        let _ = self.halt_reason.set(reason);
+
         // The first thread that sets done_marker to be true will be reponsible for
         // resolving the conditional variables, to help other theads that may be pending
         // on the read dependency. See the comment of the function resolve_condvar().
@@ -578,6 +1051,37 @@ impl Scheduler {
             for txn_idx in 0..self.num_txns {
                 self.resolve_condvar(txn_idx);
             }
+            // Parked threads would otherwise only observe the halt after their park timeout.
+            self.unpark_workers();
+        }
+    }
+
+    /// Returns the reason the execution was halted with, or None if it wasn't halted.
+    pub fn halt_reason(&self) -> Option<HaltReason> {
+        self.halt_reason.get().copied()
+    }
+
+    /// Returns true iff the parallel execution is finished, i.e. all transactions have been
+    /// committed or the execution was halted (next_task returns Done).
+    pub fn is_done(&self) -> bool {
+        self.done()
+    }
+
+    /// Returns whether the parallel execution is finished, and if so, whether all transactions
+    /// have been committed or the execution was halted early. A halt after the last commit
+    /// (e.g. on the gas limit or SkipRest status of the last transaction) does not stop any
+    /// transaction from committing, so it is reported as CommittedAll.
+    pub fn completion(&self) -> Completion {
+        if !self.done() {
+            return Completion::Running;
+        }
+        if self.committed_prefix_len() == self.num_txns as usize {
+            return Completion::CommittedAll;
+        }
+        // The halt reason is set before the done marker, so it is observed here if halted.
+        match self.halt_reason() {
+            Some(reason) => Completion::Halted(reason),
+            None => Completion::CommittedAll,
         }
     }
 
@@ -609,6 +1113,57 @@ impl Scheduler {
 
 /// Private functions of the Scheduler
 impl Scheduler {
+    /// Find the next task for the thread (see next_task).
+    fn find_next_task(&self, committing: bool) -> SchedulerTask {
+        loop {
+            if self.done() {
+                // No more tasks.
+                return SchedulerTask::Done;
+            }
+
+            let (idx_to_validate, wave) =
+                Self::unpack_validation_idx(self.validation_idx.load(Ordering::Acquire));
+            let idx_to_execute = self.execution_idx.load(Ordering::Acquire);
+
+            let prefer_validate = idx_to_validate < min(idx_to_execute, self.num_txns)
+                && !self.never_executed(idx_to_validate);
+            #[cfg(test)]
+            let prefer_validate = self.seeded_preference(prefer_validate, idx_to_execute);
+
+            if !prefer_validate && idx_to_execute >= self.num_txns {
+                return if self.done() {
+                    // Check again to avoid commit delay due to a race.
+                    SchedulerTask::Done
+                } else {
+                    if !committing {
+                        // Avoid pointlessly spinning, and give priority to other threads
+                        // that may be working to finish the remaining tasks.
+                        // We don't want to hint on the thread that is committing
+                        // because it may have work to do (to commit) even if there
+                        // is no more conventional (validation and execution tasks) work.
+                        self.wait_for_task();
+                    }
+                    SchedulerTask::NoTask
+                };
+            }
+
+            if prefer_validate {
+                if let Some(end) = self.try_validate_next_range(idx_to_validate, wave) {
+                    return SchedulerTask::ValidationRange(idx_to_validate, end, wave);
+                }
+                if let Some((version_to_validate, wave)) =
+                    self.try_validate_next_version(idx_to_validate, wave)
+                {
+                    return SchedulerTask::ValidationTask(version_to_validate, wave);
+                }
+            } else if let Some((version_to_execute, execution_task_type)) =
+                self.try_execute_next_version()
+            {
+                return SchedulerTask::ExecutionTask(version_to_execute, execution_task_type);
+            }
+        }
+    }
+
     fn unpack_validation_idx(validation_idx: u64) -> (TxnIndex, Wave) {
         (
             (validation_idx & TXN_IDX_MASK) as TxnIndex,
@@ -624,33 +1179,61 @@ impl Scheduler {
             return None;
         }
 
-        if let Ok(prev_val_idx) =
-            self.validation_idx
-                .fetch_update(Ordering::Acquire, Ordering::SeqCst, |val_idx| {
-                    let (txn_idx, wave) = Self::unpack_validation_idx(val_idx);
-                    if txn_idx > target_idx {
-                        let mut validation_status = self.txn_status[target_idx as usize].1.write();
-                        // Update the minimum wave all the suffix txn needs to pass.
-                        // We set it to max for safety (to avoid overwriting with lower values
-                        // by a slower thread), but currently this isn't strictly required
-                        // as all callers of decrease_validation_idx hold a write lock on the
-                        // previous transaction's validation status.
-                        validation_status.max_triggered_wave =
-                            max(validation_status.max_triggered_wave, wave + 1);
-
-                        // Pack into validation index.
-                        Some((target_idx as u64) | ((wave as u64 + 1) << 32))
-                    } else {
-                        None
-                    }
-                })
-        {
-            let (_, wave) = Self::unpack_validation_idx(prev_val_idx);
-            // Note that 'wave' is the previous wave value, and we must update it to 'wave + 1'.
-            Some(wave + 1)
-        } else {
-            None
+        // The wave stored by the successful update (the closure may be invoked multiple times).
+        let mut new_wave = 0;
+        self.validation_idx
+            .fetch_update(Ordering::Acquire, Ordering::SeqCst, |val_idx| {
+                let (txn_idx, wave) = Self::unpack_validation_idx(val_idx);
+                if txn_idx > target_idx {
+                    new_wave = max(self.config.wave_strategy.next_wave(wave), wave + 1);
+
+                    let mut validation_status = self.txn_status[target_idx as usize].1.write();
+                    // Update the minimum wave all the suffix txn needs to pass.
+                    // We set it to max for safety (to avoid overwriting with lower values
+                    // by a slower thread), but currently this isn't strictly required
+                    // as all callers of decrease_validation_idx hold a write lock on the
+                    // previous transaction's validation status.
+                    validation_status.max_triggered_wave =
+                        max(validation_status.max_triggered_wave, new_wave);
+
+                    // Pack into validation index.
+                    Some((target_idx as u64) | ((new_wave as u64) << 32))
+                } else {
+                    None
+                }
+            })
+            .ok()
+            .map(|_| new_wave)
+    }
+
+    /// Acquires the validation status read lock of the transaction that try_commit attempts to
+    /// commit. If the lock is contended (e.g. by finish_validation calls on the same index), but
+    /// the transaction is executed (and thus likely committable), spins for a bounded number of
+    /// attempts and then falls back to a blocking read, so that the commit thread does not keep
+    /// failing while the contention lasts. Otherwise, returns None.
+    fn try_read_validation_status_for_commit(
+        &self,
+        txn_idx: TxnIndex,
+    ) -> Option<RwLockReadGuard<'_, ValidationStatus>> {
+        let validation_lock = &self.txn_status[txn_idx as usize].1;
+        if let Some(validation_status) = validation_lock.try_read() {
+            return Some(validation_status);
+        }
+
+        // Note: is_executed acquires and releases the execution status lock before the
+        // validation status lock is acquired below, preserving the lock order (validation
+        // status lock before execution status lock).
+        self.is_executed(txn_idx, false)?;
+
+        for _ in 0..COMMIT_VALIDATION_LOCK_SPINS {
+            hint::spin_loop();
+            if let Some(validation_status) = validation_lock.try_read() {
+                return Some(validation_status);
+            }
         }
+        // Writers hold the validation status lock for short critical sections that do not
+        // depend on the commit thread, so a blocking read can't deadlock.
+        Some(validation_lock.read())
     }
 
     /// Try and incarnate a transaction. Only possible when the status is
@@ -670,6 +1253,7 @@ impl Scheduler {
         if let ExecutionStatus::Ready(incarnation, execution_task_type) = &*status {
             let ret: (u32, ExecutionTaskType) = (*incarnation, (*execution_task_type).clone());
             *status = ExecutionStatus::Executing(*incarnation);
+            self.record_event(SchedulerEvent::Execute((txn_idx, ret.0)));
             Some(ret)
         } else {
             None
@@ -748,14 +1332,55 @@ impl Scheduler {
             // Successfully claimed idx_to_validate to attempt validation.
             // If incarnation was last executed, and thus ready for validation,
             // return version and wave for validation task, otherwise None.
-            return self
-                .is_executed(idx_to_validate, false)
-                .map(|incarnation| ((idx_to_validate, incarnation), wave));
+            return self.is_executed(idx_to_validate, false).map(|incarnation| {
+                self.record_event(SchedulerEvent::Validate(
+                    (idx_to_validate, incarnation),
+                    wave,
+                ));
+                ((idx_to_validate, incarnation), wave)
+            });
         }
 
         None
     }
 
+    /// If validation ranges are enabled, and at least two consecutive transactions starting
+    /// at idx_to_validate are executed, try to claim them all (at most max_validation_range)
+    /// by moving validation_idx past them, in which case the end of the range is returned.
+    /// Otherwise, returns None (and validation_idx is not modified).
+    fn try_validate_next_range(&self, idx_to_validate: TxnIndex, wave: Wave) -> Option<TxnIndex> {
+        if self.config.max_validation_range <= 1 {
+            return None;
+        }
+
+        let max_end = min(
+            self.num_txns,
+            idx_to_validate.saturating_add(self.config.max_validation_range),
+        );
+        let mut end = idx_to_validate;
+        while end < max_end && self.is_executed(end, false).is_some() {
+            end += 1;
+        }
+        if end - idx_to_validate < 2 {
+            // Handled as a single validation task.
+            return None;
+        }
+
+        // Statuses may change after being observed above, hence the callers re-check them
+        // (see executed_incarnation) when performing the validations.
+        let validation_idx = (idx_to_validate as u64) | ((wave as u64) << 32);
+        let new_validation_idx = (end as u64) | ((wave as u64) << 32);
+        self.validation_idx
+            .compare_exchange(
+                validation_idx,
+                new_validation_idx,
+                Ordering::Acquire,
+                Ordering::SeqCst,
+            )
+            .ok()
+            .map(|_| end)
+    }
+
     /// Grab an index to try and execute next (by fetch-and-incrementing execution_idx).
     /// - If the index is out of bounds, return None (and invoke a check of whether
     /// all txns can be committed).
@@ -805,6 +1430,7 @@ impl Scheduler {
         }
 
         if let ExecutionStatus::Suspended(incarnation, dep_condvar) = &*status {
+            self.dependency_depth[txn_idx as usize].store(0, Ordering::Relaxed);
             *status = ExecutionStatus::Ready(
                 *incarnation,
                 ExecutionTaskType::Wakeup(dep_condvar.clone()),
@@ -814,6 +1440,19 @@ impl Scheduler {
         }
     }
 
+    /// Resume all transactions in deps (see resume), and return the minimum index among
+    /// them (None if deps is empty). Each status lock is acquired and released individually,
+    /// and the minimum is computed in the same pass.
+    fn resume_many(&self, deps: &[TxnIndex]) -> Option<TxnIndex> {
+        let mut min_dep = None;
+        for &dep in deps {
+            // Mark the status of dependencies as 'Ready' since the dependency is now resolved.
+            self.resume(dep);
+            min_dep = Some(min_dep.map_or(dep, |cur_min| min(cur_min, dep)));
+        }
+        min_dep
+    }
+
     /// Set status of the transaction to Executed(incarnation).
     fn set_executed_status(&self, txn_idx: TxnIndex, incarnation: Incarnation) {
         let mut status = self.txn_status[txn_idx as usize].0.write();
@@ -841,7 +1480,59 @@ impl Scheduler {
         *status = ExecutionStatus::Ready(incarnation + 1, ExecutionTaskType::Execution);
     }
 
-    /// Checks whether the done marker is set. The marker can only be set by 'try_commit'.
+    /// Called by a non-committing thread when there are no available tasks, waits according
+    /// to the configured WaitStrategy.
+    fn wait_for_task(&self) {
+        match self.config.wait_strategy {
+            WaitStrategy::Spin => {
+                self.spin_iterations.fetch_add(1, Ordering::Relaxed);
+                hint::spin_loop();
+            },
+            WaitStrategy::Park(timeout) => {
+                let current = thread::current();
+                // Register before checking the done marker: unpark_workers is called after the
+                // marker is set, so either the check below observes it, or the registered
+                // thread gets unparked (and park_timeout returns immediately).
+                self.parked_workers.lock().push(current.clone());
+                // The timeout is measured by the configured clock, parking again on spurious
+                // wakeups (or when the clock is behind the system time).
+                let start = self.now();
+                while !self.done() && !self.timed_out(start, timeout) {
+                    thread::park_timeout(timeout - self.now().saturating_duration_since(start));
+                }
+                self.parked_workers
+                    .lock()
+                    .retain(|worker| worker.id() != current.id());
+            },
+        }
+    }
+
+    /// Records the event in the event log, if the event-log feature is enabled.
+    #[cfg_attr(not(feature = "event-log"), allow(unused_variables))]
+    fn record_event(&self, event: SchedulerEvent) {
+        #[cfg(feature = "event-log")]
+        self.event_log.record(event);
+    }
+
+    /// Wakes up all threads parked in wait_for_task.
+    fn unpark_workers(&self) {
+        for worker in self.parked_workers.lock().iter() {
+            worker.unpark();
+        }
+    }
+
+    #[cfg(test)]
+    /// If a seed is set and both validation and execution tasks are available, randomly
+    /// decides whether to prefer the validation task.
+    fn seeded_preference(&self, prefer_validate: bool, idx_to_execute: TxnIndex) -> bool {
+        match self.rng.lock().as_mut() {
+            Some(rng) if prefer_validate && idx_to_execute < self.num_txns => rng.gen(),
+            _ => prefer_validate,
+        }
+    }
+
+    /// Checks whether the done marker is set. The marker can only be set by 'try_commit' or
+    /// 'halt_with_reason'.
     fn done(&self) -> bool {
         self.done_marker.load(Ordering::Acquire)
     }
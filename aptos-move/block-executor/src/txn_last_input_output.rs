@@ -233,6 +233,16 @@ impl<K: ModulePath, T: TransactionOutput, E: Debug + Send + Clone> TxnLastInputO
         }
     }
 
+    pub fn is_skip_rest(&self, txn_idx: TxnIndex) -> bool {
+        matches!(
+            &self.outputs[txn_idx as usize]
+                .load_full()
+                .expect("[BlockSTM]: Execution output must be recorded after execution")
+                .output_status,
+            ExecutionStatus::SkipRest(_)
+        )
+    }
+
     pub fn update_to_skip_rest(&self, txn_idx: TxnIndex) {
         if let ExecutionStatus::Success(output) = self.take_output(txn_idx) {
             self.outputs[txn_idx as usize].store(Some(Arc::new(TxnOutput {
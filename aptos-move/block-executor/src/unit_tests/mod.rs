@@ -5,15 +5,22 @@
 use crate::{
     executor::BlockExecutor,
     proptest_types::types::{DeltaDataView, ExpectedOutput, KeyType, Task, Transaction, ValueType},
-    scheduler::{DependencyResult, ExecutionTaskType, Scheduler, SchedulerTask},
+    scheduler::{
+        AbortRejection, Clock, CommitGasCheck, Completion, DependencyResult, DependencyStatus,
+        ExecutionStatus, ExecutionStatusKind, ExecutionTaskType, HaltReason, ModuleConflictCheck,
+        Scheduler, SchedulerConfig, SchedulerTask, SchedulerTaskRecord, WaitStrategy, Wave,
+        WaveStrategy,
+    },
 };
 use aptos_aggregator::delta_change_set::{delta_add, delta_sub, DeltaOp, DeltaUpdate};
-use aptos_mvhashmap::types::TxnIndex;
+use aptos_infallible::Mutex;
+use aptos_metrics_core::IntGauge;
+use aptos_mvhashmap::types::{Incarnation, TxnIndex};
 use aptos_types::{
     executable::{ExecutableTestType, ModulePath},
     write_set::TransactionWrite,
 };
-use claims::{assert_matches, assert_some_eq};
+use claims::{assert_matches, assert_none, assert_some_eq};
 use rand::{prelude::*, random};
 use std::{
     cmp::min,
@@ -21,7 +28,11 @@ use std::{
     fmt::Debug,
     hash::Hash,
     marker::PhantomData,
-    sync::{atomic::AtomicUsize, Arc},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Condvar,
+    },
+    time::{Duration, Instant},
 };
 
 fn run_and_assert<K, V>(transactions: Vec<Transaction<K, V>>)
@@ -263,12 +274,12 @@ fn early_skips() {
 
 #[test]
 fn scheduler_tasks() {
-    let s = Scheduler::new(5);
+    let s = Scheduler::with_config(5, SchedulerConfig::default());
 
     for i in 0..5 {
         // No validation tasks.
         assert!(matches!(
-            s.next_task(false),
+            s.next_task(false, 0),
             SchedulerTask::ExecutionTask((j, 0), ExecutionTaskType::Execution) if i == j
         ));
     }
@@ -284,7 +295,7 @@ fn scheduler_tasks() {
 
     for i in 0..5 {
         assert!(matches!(
-            s.next_task(false),
+            s.next_task(false, 0),
             SchedulerTask::ValidationTask((j, 0), 0) if i == j
         ));
     }
@@ -324,12 +335,12 @@ fn scheduler_tasks() {
 
     // Another validation task for (2, 0).
     assert!(matches!(
-        s.next_task(false),
+        s.next_task(false, 0),
         SchedulerTask::ValidationTask((2, 0), 1)
     ));
     // Now skip over txn 3 (status is Executing), and validate 4.
     assert!(matches!(
-        s.next_task(false),
+        s.next_task(false, 0),
         SchedulerTask::ValidationTask((4, 1), 1)
     ));
 
@@ -349,17 +360,17 @@ fn scheduler_tasks() {
         assert_some_eq!(s.try_commit(), i);
     }
 
-    assert!(matches!(s.next_task(false), SchedulerTask::Done));
+    assert!(matches!(s.next_task(false, 0), SchedulerTask::Done));
 }
 
 #[test]
 fn scheduler_first_wave() {
-    let s = Scheduler::new(6);
+    let s = Scheduler::with_config(6, SchedulerConfig::default());
 
     for i in 0..5 {
         // Nothing to validate.
         assert!(matches!(
-            s.next_task(false),
+            s.next_task(false, 0),
             SchedulerTask::ExecutionTask((j, 0), ExecutionTaskType::Execution) if j == i
         ));
     }
@@ -373,16 +384,16 @@ fn scheduler_first_wave() {
 
     // Now we can validate version (0, 0).
     assert!(matches!(
-        s.next_task(false),
+        s.next_task(false, 0),
         SchedulerTask::ValidationTask((0, 0), 0)
     ));
     assert!(matches!(
-        s.next_task(false),
+        s.next_task(false, 0),
         SchedulerTask::ExecutionTask((5, 0), ExecutionTaskType::Execution)
     ));
     // Since (1, 0) is not EXECUTED, no validation tasks, and execution index
     // is already at the limit, so no tasks immediately available.
-    assert!(matches!(s.next_task(false), SchedulerTask::NoTask));
+    assert!(matches!(s.next_task(false, 0), SchedulerTask::NoTask));
 
     assert!(matches!(
         s.finish_execution(2, 0, false),
@@ -390,31 +401,31 @@ fn scheduler_first_wave() {
     ));
     // There should be no tasks, but finishing (1,0) should enable validating
     // (1, 0) then (2,0).
-    assert!(matches!(s.next_task(false), SchedulerTask::NoTask));
+    assert!(matches!(s.next_task(false, 0), SchedulerTask::NoTask));
 
     assert!(matches!(
         s.finish_execution(1, 0, false),
         SchedulerTask::NoTask
     ));
     assert!(matches!(
-        s.next_task(false),
+        s.next_task(false, 0),
         SchedulerTask::ValidationTask((1, 0), 0)
     ));
     assert!(matches!(
-        s.next_task(false),
+        s.next_task(false, 0),
         SchedulerTask::ValidationTask((2, 0), 0)
     ));
-    assert!(matches!(s.next_task(false), SchedulerTask::NoTask));
+    assert!(matches!(s.next_task(false, 0), SchedulerTask::NoTask));
 }
 
 #[test]
 fn scheduler_dependency() {
-    let s = Scheduler::new(10);
+    let s = Scheduler::with_config(10, SchedulerConfig::default());
 
     for i in 0..5 {
         // Nothing to validate.
         assert!(matches!(
-            s.next_task(false),
+            s.next_task(false, 0),
             SchedulerTask::ExecutionTask((j, 0), ExecutionTaskType::Execution) if j == i
         ));
     }
@@ -427,7 +438,7 @@ fn scheduler_dependency() {
     ));
     // Now we can validate version (0, 0).
     assert!(matches!(
-        s.next_task(false),
+        s.next_task(false, 0),
         SchedulerTask::ValidationTask((0, 0), 0)
     ));
     // Current status of 0 is executed - hence, no dependency added.
@@ -448,20 +459,1145 @@ fn scheduler_dependency() {
 
     // resumed task doesn't bump incarnation
     assert!(matches!(
-        s.next_task(false),
+        s.next_task(false, 0),
         SchedulerTask::ExecutionTask((4, 0), ExecutionTaskType::Wakeup(_))
     ));
 }
 
+#[test]
+fn scheduler_resume_many_dependencies() {
+    let s = Scheduler::with_config(12, SchedulerConfig::default());
+
+    for i in 0..12 {
+        assert!(matches!(
+            s.next_task(false, 0),
+            SchedulerTask::ExecutionTask((j, 0), ExecutionTaskType::Execution) if j == i
+        ));
+    }
+
+    // Transactions 1..=10 all depend on transaction 0 (added in decreasing order).
+    for i in (1..=10).rev() {
+        assert!(matches!(
+            s.wait_for_dependency(i, 0),
+            DependencyResult::Dependency(_)
+        ));
+    }
+
+    // Resumes all 10 dependencies, and decreases execution index to the minimum (1).
+    assert!(matches!(
+        s.finish_execution(0, 0, false),
+        SchedulerTask::NoTask
+    ));
+    assert!(matches!(
+        s.next_task(false, 0),
+        SchedulerTask::ValidationTask((0, 0), 0)
+    ));
+
+    // Every dependency is ready again and gets woken up, in the increasing index order.
+    for i in 1..=10 {
+        assert!(matches!(
+            s.next_task(false, 0),
+            SchedulerTask::ExecutionTask((j, 0), ExecutionTaskType::Wakeup(_)) if j == i
+        ));
+    }
+    // Transaction 11 is still executing, so there is nothing else to do.
+    assert!(matches!(s.next_task(false, 0), SchedulerTask::NoTask));
+}
+
+#[test]
+fn scheduler_max_dependency_depth() {
+    let s = Scheduler::with_config(5, SchedulerConfig {
+        max_dependency_depth: 2,
+        ..SchedulerConfig::default()
+    });
+
+    for i in 0..5 {
+        assert!(matches!(
+            s.next_task(false, 0),
+            SchedulerTask::ExecutionTask((j, 0), ExecutionTaskType::Execution) if j == i
+        ));
+    }
+
+    // Chain 2 -> 1 -> 0 has depth 2, within the bound.
+    assert!(matches!(
+        s.wait_for_dependency(1, 0),
+        DependencyResult::Dependency(_)
+    ));
+    assert!(matches!(
+        s.wait_for_dependency(2, 1),
+        DependencyResult::Dependency(_)
+    ));
+    // Suspending 3 on 2 would create a chain of depth 3, so the caller must re-read instead.
+    assert!(matches!(
+        s.wait_for_dependency(3, 2),
+        DependencyResult::Resolved
+    ));
+    // Transaction 3 was not suspended, so 4 can start a new chain by depending on it.
+    assert!(matches!(
+        s.wait_for_dependency(4, 3),
+        DependencyResult::Dependency(_)
+    ));
+
+    // Once 1 is resumed, it no longer extends a chain, and 3 may be suspended on it.
+    assert!(matches!(
+        s.finish_execution(0, 0, false),
+        SchedulerTask::NoTask
+    ));
+    assert!(matches!(
+        s.wait_for_dependency(3, 1),
+        DependencyResult::Dependency(_)
+    ));
+}
+
+#[test]
+fn commit_with_contended_validation_status() {
+    let num_txns: TxnIndex = 50;
+    let s = Scheduler::with_config(num_txns, SchedulerConfig::default());
+
+    for i in 0..num_txns {
+        assert!(matches!(
+            s.next_task(false, 0),
+            SchedulerTask::ExecutionTask((j, 0), ExecutionTaskType::Execution) if j == i
+        ));
+        assert!(matches!(
+            s.finish_execution(i, 0, false),
+            SchedulerTask::NoTask
+        ));
+        assert!(matches!(
+            s.next_task(false, 0),
+            SchedulerTask::ValidationTask((j, 0), 0) if j == i
+        ));
+        s.finish_validation(i, 0);
+    }
+
+    let stop = AtomicBool::new(false);
+    std::thread::scope(|scope| {
+        for _ in 0..4 {
+            scope.spawn(|| {
+                // Hammer the validation status write locks of all transactions.
+                while !stop.load(Ordering::Relaxed) {
+                    for i in 0..num_txns {
+                        s.finish_validation(i, 0);
+                    }
+                }
+            });
+        }
+
+        // Every executed and validated transaction is committed on the first attempt,
+        // regardless of the contention on the validation status lock.
+        for i in 0..num_txns {
+            assert_some_eq!(s.try_commit(), i);
+        }
+        stop.store(true, Ordering::Relaxed);
+    });
+
+    assert!(matches!(s.next_task(false, 0), SchedulerTask::Done));
+}
+
+#[test]
+fn finish_abort_after_halt() {
+    let s = Scheduler::with_config(3, SchedulerConfig::default());
+
+    for i in 0..3 {
+        assert!(matches!(
+            s.next_task(false, 0),
+            SchedulerTask::ExecutionTask((j, 0), ExecutionTaskType::Execution) if j == i
+        ));
+    }
+    for i in 0..3 {
+        assert!(matches!(
+            s.finish_execution(i, 0, false),
+            SchedulerTask::NoTask
+        ));
+    }
+
+    // Interleaving: a validation of txn 1 fails and aborts, then the committing thread halts
+    // the execution, and only then the aborting thread calls finish_abort.
+    assert!(s.try_abort(1, 0));
+    s.halt_with_reason(HaltReason::VmAbort);
+
+    // execution_idx (3) is higher than 1, but incarnation fails as the execution is halted,
+    // so no re-execution gets scheduled.
+    assert!(matches!(s.finish_abort(1, 0), SchedulerTask::NoTask));
+    assert!(matches!(s.next_task(false, 0), SchedulerTask::Done));
+}
+
+#[test]
+fn scheduler_task_record_round_trip() {
+    let s = Scheduler::with_config(3, SchedulerConfig::default());
+
+    let mut records: Vec<SchedulerTaskRecord> = vec![];
+    for _ in 0..3 {
+        records.push((&s.next_task(false, 0)).into());
+    }
+    assert!(matches!(
+        s.wait_for_dependency(2, 0),
+        DependencyResult::Dependency(_)
+    ));
+    records.push((&s.finish_execution(0, 0, false)).into());
+    // Validation of (0, 0), then a wakeup of the suspended transaction 2.
+    records.push((&s.next_task(false, 0)).into());
+    records.push((&s.next_task(false, 0)).into());
+    records.push((&SchedulerTask::Done).into());
+
+    assert_eq!(records, vec![
+        SchedulerTaskRecord::ExecutionTask((0, 0)),
+        SchedulerTaskRecord::ExecutionTask((1, 0)),
+        SchedulerTaskRecord::ExecutionTask((2, 0)),
+        SchedulerTaskRecord::NoTask,
+        SchedulerTaskRecord::ValidationTask((0, 0), 0),
+        SchedulerTaskRecord::WakeupTask((2, 0)),
+        SchedulerTaskRecord::Done,
+    ]);
+
+    let json = serde_json::to_string(&records).unwrap();
+    let deserialized: Vec<SchedulerTaskRecord> = serde_json::from_str(&json).unwrap();
+    assert_eq!(deserialized, records);
+}
+
+#[test]
+fn execution_status_accessors() {
+    let condvar = Arc::new((Mutex::new(DependencyStatus::Unresolved), Condvar::new()));
+
+    let statuses = [
+        (
+            ExecutionStatus::Ready(1, ExecutionTaskType::Execution),
+            Some(1),
+            ExecutionStatusKind::Ready,
+        ),
+        (
+            ExecutionStatus::Ready(2, ExecutionTaskType::Wakeup(condvar.clone())),
+            Some(2),
+            ExecutionStatusKind::Ready,
+        ),
+        (
+            ExecutionStatus::Executing(3),
+            Some(3),
+            ExecutionStatusKind::Executing,
+        ),
+        (
+            ExecutionStatus::Suspended(4, condvar),
+            Some(4),
+            ExecutionStatusKind::Suspended,
+        ),
+        (
+            ExecutionStatus::Executed(5),
+            Some(5),
+            ExecutionStatusKind::Executed,
+        ),
+        (
+            ExecutionStatus::Committed(6),
+            Some(6),
+            ExecutionStatusKind::Committed,
+        ),
+        (
+            ExecutionStatus::Aborting(7),
+            Some(7),
+            ExecutionStatusKind::Aborting,
+        ),
+        (
+            ExecutionStatus::ExecutionHalted,
+            None,
+            ExecutionStatusKind::ExecutionHalted,
+        ),
+    ];
+
+    for (status, incarnation, kind) in statuses {
+        assert_eq!(status.incarnation(), incarnation);
+        assert_eq!(status.kind(), kind);
+    }
+
+    // PartialEq still compares the incarnations (and ignores the task types).
+    assert!(
+        ExecutionStatus::Ready(1, ExecutionTaskType::Execution)
+            == ExecutionStatus::Ready(1, ExecutionTaskType::Execution)
+    );
+    assert!(ExecutionStatus::Executed(1) != ExecutionStatus::Committed(1));
+}
+
+#[test]
+fn scheduler_default_config() {
+    // Drives a scheduler through executions, a dependency, an abort, validations and commits,
+    // recording all returned tasks and committed indices.
+    let record_tasks = |s: Scheduler| {
+        let mut records: Vec<SchedulerTaskRecord> = vec![];
+        for _ in 0..4 {
+            records.push((&s.next_task(false, 0)).into());
+        }
+        assert!(matches!(
+            s.wait_for_dependency(3, 1),
+            DependencyResult::Dependency(_)
+        ));
+        for i in 0..3 {
+            records.push((&s.finish_execution(i, 0, i == 1)).into());
+        }
+        while let task @ (SchedulerTask::ValidationTask(..) | SchedulerTask::ExecutionTask(..)) =
+            s.next_task(false, 0)
+        {
+            records.push((&task).into());
+        }
+        assert!(s.try_abort(2, 0));
+        records.push((&s.finish_abort(2, 0)).into());
+        records.push((&s.finish_execution(2, 1, false)).into());
+        records.push((&s.finish_execution(3, 0, false)).into());
+        let mut committed = vec![];
+        for i in 0..4 {
+            s.finish_validation(i, 1);
+            committed.push(s.try_commit());
+        }
+        records.push((&s.next_task(false, 0)).into());
+        (records, committed)
+    };
+
+    let default_records = record_tasks(Scheduler::with_config(4, SchedulerConfig::default()));
+    assert_eq!(default_records.0.last(), Some(&SchedulerTaskRecord::Done));
+    assert_eq!(default_records.1, vec![Some(0), Some(1), Some(2), Some(3)]);
+    assert_eq!(
+        record_tasks(Scheduler::with_config(4, SchedulerConfig::default())),
+        default_records
+    );
+}
+
+/// A clock that only moves when manually advanced.
+#[derive(Debug)]
+struct ManualClock(Mutex<Instant>);
+
+impl ManualClock {
+    fn advance(&self, duration: Duration) {
+        *self.0.lock() += duration;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        *self.0.lock()
+    }
+}
+
+#[test]
+fn scheduler_manual_clock_timeout() {
+    let clock = Arc::new(ManualClock(Mutex::new(Instant::now())));
+    let s = Scheduler::with_config(1, SchedulerConfig {
+        clock: clock.clone(),
+        ..SchedulerConfig::default()
+    });
+
+    let timeout = Duration::from_secs(10);
+    let start = s.now();
+    assert!(!s.timed_out(start, timeout));
+
+    clock.advance(Duration::from_secs(9));
+    assert!(!s.timed_out(start, timeout));
+
+    clock.advance(Duration::from_secs(1));
+    assert!(s.timed_out(start, timeout));
+    assert_eq!(s.now().duration_since(start), timeout);
+}
+
+#[test]
+fn scheduler_approx_memory_bytes() {
+    let num_txns: TxnIndex = 100;
+    let s = Scheduler::with_config(num_txns, SchedulerConfig::default());
+    let initial_bytes = s.approx_memory_bytes();
+    assert!(initial_bytes >= num_txns as usize * 2 * std::mem::size_of::<u32>());
+
+    for i in 0..num_txns {
+        assert!(matches!(
+            s.next_task(false, 0),
+            SchedulerTask::ExecutionTask((j, 0), ExecutionTaskType::Execution) if j == i
+        ));
+    }
+    // All other transactions depend on transaction 0.
+    for i in 1..num_txns {
+        assert!(matches!(
+            s.wait_for_dependency(i, 0),
+            DependencyResult::Dependency(_)
+        ));
+    }
+
+    let bytes = s.approx_memory_bytes();
+    assert!(bytes >= initial_bytes + (num_txns as usize - 1) * std::mem::size_of::<TxnIndex>());
+}
+
+#[test]
+fn scheduler_halt_reason() {
+    let s = Scheduler::with_config(3, SchedulerConfig::default());
+    assert_eq!(s.halt_reason(), None);
+
+    s.halt_with_reason(HaltReason::GasLimit);
+    assert_eq!(s.halt_reason(), Some(HaltReason::GasLimit));
+    s.halt_with_reason(HaltReason::ModuleConflict);
+    s.halt_with_reason(HaltReason::SkipRest);
+    assert_eq!(s.halt_reason(), Some(HaltReason::GasLimit));
+    assert!(matches!(s.next_task(false, 0), SchedulerTask::Done));
+
+    // Concurrent halts: all threads observe the same (first) reason.
+    let reasons = [
+        HaltReason::ModuleConflict,
+        HaltReason::VmAbort,
+        HaltReason::SkipRest,
+        HaltReason::GasLimit,
+    ];
+    for _ in 0..20 {
+        let s = Scheduler::with_config(10, SchedulerConfig::default());
+        let barrier = std::sync::Barrier::new(reasons.len());
+        let observed: Vec<HaltReason> = std::thread::scope(|scope| {
+            let handles: Vec<_> = reasons
+                .iter()
+                .map(|reason| {
+                    let (s, barrier) = (&s, &barrier);
+                    scope.spawn(move || {
+                        barrier.wait();
+                        s.halt_with_reason(*reason);
+                        s.halt_reason().unwrap()
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+
+        let first = s.halt_reason().unwrap();
+        assert!(reasons.contains(&first));
+        assert!(observed.iter().all(|reason| *reason == first));
+    }
+}
+
+#[test]
+fn halt_wakes_parked_worker() {
+    let park_timeout = Duration::from_secs(60);
+    let s = Scheduler::with_config(1, SchedulerConfig {
+        wait_strategy: WaitStrategy::Park(park_timeout),
+        ..SchedulerConfig::default()
+    });
+
+    // The only transaction is being executed, so there are no tasks for the worker.
+    assert!(matches!(
+        s.next_task(true, 0),
+        SchedulerTask::ExecutionTask((0, 0), ExecutionTaskType::Execution)
+    ));
+
+    let start = Instant::now();
+    std::thread::scope(|scope| {
+        let worker = scope.spawn(|| {
+            assert!(matches!(s.next_task(false, 0), SchedulerTask::NoTask));
+            assert!(matches!(s.next_task(false, 0), SchedulerTask::Done));
+        });
+
+        // Give the worker the time to park.
+        std::thread::sleep(Duration::from_millis(100));
+        s.halt_with_reason(HaltReason::VmAbort);
+        worker.join().unwrap();
+    });
+    assert!(start.elapsed() < park_timeout / 4);
+}
+
+#[test]
+fn park_timeout_follows_clock() {
+    let clock = Arc::new(ManualClock(Mutex::new(Instant::now())));
+    let park_timeout = Duration::from_millis(10);
+    let s = Scheduler::with_config(1, SchedulerConfig {
+        clock: clock.clone(),
+        wait_strategy: WaitStrategy::Park(park_timeout),
+        ..SchedulerConfig::default()
+    });
+
+    assert!(matches!(
+        s.next_task(true, 0),
+        SchedulerTask::ExecutionTask((0, 0), ExecutionTaskType::Execution)
+    ));
+
+    let returned = AtomicBool::new(false);
+    std::thread::scope(|scope| {
+        let worker = scope.spawn(|| {
+            assert!(matches!(s.next_task(false, 0), SchedulerTask::NoTask));
+            returned.store(true, Ordering::SeqCst);
+        });
+
+        // Many park timeouts elapse on the system clock, but the worker keeps waiting until
+        // the scheduler's clock advances.
+        std::thread::sleep(park_timeout * 10);
+        assert!(!returned.load(Ordering::SeqCst));
+
+        clock.advance(park_timeout);
+        worker.join().unwrap();
+    });
+    assert!(returned.load(Ordering::SeqCst));
+}
+
+#[test]
+fn scheduler_validation_range() {
+    let s = Scheduler::with_config(8, SchedulerConfig {
+        max_validation_range: 10,
+        ..SchedulerConfig::default()
+    });
+
+    for i in 0..8 {
+        assert!(matches!(
+            s.next_task(false, 0),
+            SchedulerTask::ExecutionTask((j, 0), ExecutionTaskType::Execution) if j == i
+        ));
+    }
+    for i in [0, 1, 2, 3, 4, 6] {
+        assert!(matches!(
+            s.finish_execution(i, 0, false),
+            SchedulerTask::NoTask
+        ));
+    }
+
+    // Range covers exactly the consecutive executed transactions 0..5.
+    assert!(matches!(
+        s.next_task(false, 0),
+        SchedulerTask::ValidationRange(0, 5, 0)
+    ));
+    // Transaction 5 has never been executed.
+    assert!(matches!(s.next_task(false, 0), SchedulerTask::NoTask));
+
+    assert!(matches!(
+        s.finish_execution(5, 0, false),
+        SchedulerTask::NoTask
+    ));
+    assert!(matches!(
+        s.next_task(false, 0),
+        SchedulerTask::ValidationRange(5, 7, 0)
+    ));
+
+    // A single executed transaction is returned as a single validation task.
+    assert!(matches!(
+        s.finish_execution(7, 0, false),
+        SchedulerTask::NoTask
+    ));
+    assert!(matches!(
+        s.next_task(false, 0),
+        SchedulerTask::ValidationTask((7, 0), 0)
+    ));
+
+    s.finish_validation_range(0, 7, 0);
+    s.finish_validation(7, 0);
+    for i in 0..8 {
+        assert_some_eq!(s.try_commit(), i);
+    }
+    assert!(matches!(s.next_task(false, 0), SchedulerTask::Done));
+
+    // The range length is capped by max_validation_range.
+    let s = Scheduler::with_config(8, SchedulerConfig {
+        max_validation_range: 3,
+        ..SchedulerConfig::default()
+    });
+    for i in 0..8 {
+        assert!(matches!(
+            s.next_task(false, 0),
+            SchedulerTask::ExecutionTask((j, 0), ExecutionTaskType::Execution) if j == i
+        ));
+    }
+    for i in 0..8 {
+        assert!(matches!(
+            s.finish_execution(i, 0, false),
+            SchedulerTask::NoTask
+        ));
+    }
+    assert!(matches!(
+        s.next_task(false, 0),
+        SchedulerTask::ValidationRange(0, 3, 0)
+    ));
+    assert!(matches!(
+        s.next_task(false, 0),
+        SchedulerTask::ValidationRange(3, 6, 0)
+    ));
+    assert!(matches!(
+        s.next_task(false, 0),
+        SchedulerTask::ValidationRange(6, 8, 0)
+    ));
+}
+
+#[test]
+fn commit_wave_gauge() {
+    // A test-only gauge, not registered with the global registry.
+    let gauge = IntGauge::new("test_commit_wave", "Commit wave in a test").unwrap();
+    let s = Scheduler::with_config(3, SchedulerConfig {
+        commit_wave_gauge: gauge.clone(),
+        ..SchedulerConfig::default()
+    });
+
+    for i in 0..3 {
+        assert!(matches!(
+            s.next_task(false, 0),
+            SchedulerTask::ExecutionTask((j, 0), ExecutionTaskType::Execution) if j == i
+        ));
+    }
+    for i in 0..3 {
+        assert!(matches!(
+            s.finish_execution(i, 0, false),
+            SchedulerTask::NoTask
+        ));
+    }
+    for i in 0..3 {
+        assert!(matches!(
+            s.next_task(false, 0),
+            SchedulerTask::ValidationTask((j, 0), 0) if j == i
+        ));
+    }
+
+    // Conflict: txn 0 aborts, which triggers wave 1 for the higher transactions.
+    assert!(s.try_abort(0, 0));
+    assert!(matches!(
+        s.finish_abort(0, 0),
+        SchedulerTask::ExecutionTask((0, 1), ExecutionTaskType::Execution)
+    ));
+    assert!(matches!(
+        s.finish_execution(0, 1, false),
+        SchedulerTask::ValidationTask((0, 1), 1)
+    ));
+    s.finish_validation(0, 1);
+    assert_some_eq!(s.try_commit(), 0);
+    assert_eq!(gauge.get(), 0);
+
+    s.finish_validation(1, 0);
+    assert!(s.try_commit().is_none());
+    assert_eq!(s.commit_state(), (1, 1));
+    assert_eq!(gauge.get(), 1);
+}
+
+#[test]
+fn per_worker_task_counts() {
+    let s = Scheduler::with_config(4, SchedulerConfig {
+        num_workers: 2,
+        ..SchedulerConfig::default()
+    });
+
+    // Worker 0 executes txns 0 and 1, worker 1 executes txns 2 and 3.
+    for (i, worker_id) in [0, 0, 1, 1].into_iter().enumerate() {
+        assert!(matches!(
+            s.next_task(false, worker_id),
+            SchedulerTask::ExecutionTask((j, 0), ExecutionTaskType::Execution) if j == i as u32
+        ));
+    }
+    for i in 0..4 {
+        assert!(matches!(
+            s.finish_execution(i, 0, false),
+            SchedulerTask::NoTask
+        ));
+    }
+    // Worker 1 takes all the validations.
+    for i in 0..4 {
+        assert!(matches!(
+            s.next_task(false, 1),
+            SchedulerTask::ValidationTask((j, 0), 0) if j == i
+        ));
+    }
+    // Out of range worker ids and empty tasks are not counted.
+    assert!(matches!(s.next_task(false, 2), SchedulerTask::NoTask));
+    assert!(matches!(s.next_task(false, 0), SchedulerTask::NoTask));
+
+    assert_eq!(s.per_worker_counts(), vec![(2, 0), (2, 4)]);
+}
+
+#[derive(Debug)]
+struct FlaggedTxns(Vec<TxnIndex>);
+
+impl ModuleConflictCheck for FlaggedTxns {
+    fn is_module_conflict(&self, txn_idx: TxnIndex) -> bool {
+        self.0.contains(&txn_idx)
+    }
+}
+
+#[test]
+fn module_conflict_halts_scheduler() {
+    let s = Scheduler::with_config(3, SchedulerConfig {
+        module_conflict_check: Some(Arc::new(FlaggedTxns(vec![1]))),
+        ..SchedulerConfig::default()
+    });
+
+    for i in 0..3 {
+        assert!(matches!(
+            s.next_task(false, 0),
+            SchedulerTask::ExecutionTask((j, 0), ExecutionTaskType::Execution) if j == i
+        ));
+    }
+    // Txn 2 suspends on txn 1.
+    let condvar = match s.wait_for_dependency(2, 1) {
+        DependencyResult::Dependency(condvar) => condvar,
+        _ => unreachable!(),
+    };
+
+    assert!(matches!(
+        s.finish_execution(0, 0, false),
+        SchedulerTask::NoTask
+    ));
+    assert_eq!(s.halt_reason(), None);
+
+    // Flagged txn halts the scheduler when it finishes execution.
+    assert!(matches!(
+        s.finish_execution(1, 0, false),
+        SchedulerTask::NoTask
+    ));
+    assert_eq!(s.halt_reason(), Some(HaltReason::ModuleConflict));
+    assert!(matches!(
+        *condvar.0.lock(),
+        DependencyStatus::ExecutionHalted
+    ));
+    assert!(matches!(s.next_task(false, 0), SchedulerTask::Done));
+}
+
+#[derive(Debug)]
+struct GasLimit(u128);
+
+impl CommitGasCheck for GasLimit {
+    fn should_stop_commit(&self, committed_gas: u128) -> bool {
+        committed_gas >= self.0
+    }
+}
+
+#[test]
+fn commit_stops_at_gas_limit() {
+    let num_txns: TxnIndex = 10;
+    let s = Scheduler::with_config(num_txns, SchedulerConfig {
+        commit_gas_check: Some(Arc::new(GasLimit(25))),
+        ..SchedulerConfig::default()
+    });
+
+    for i in 0..num_txns {
+        assert!(matches!(
+            s.next_task(false, 0),
+            SchedulerTask::ExecutionTask((j, 0), ExecutionTaskType::Execution) if j == i
+        ));
+        s.record_gas(i, 10);
+        assert!(matches!(
+            s.finish_execution(i, 0, false),
+            SchedulerTask::NoTask
+        ));
+        assert!(matches!(
+            s.next_task(false, 0),
+            SchedulerTask::ValidationTask((j, 0), 0) if j == i
+        ));
+        s.finish_validation(i, 0);
+    }
+
+    // The third commit reaches the limit, the committed prefix is kept.
+    for i in 0..3 {
+        assert_eq!(s.halt_reason(), None);
+        assert_some_eq!(s.try_commit(), i);
+    }
+    assert_eq!(s.halt_reason(), Some(HaltReason::GasLimit));
+    assert_none!(s.try_commit());
+    assert_eq!(s.commit_state().0, 3);
+    assert!(matches!(s.next_task(false, 0), SchedulerTask::Done));
+}
+
+#[test]
+fn scheduler_repeated_dependency() {
+    let s = Scheduler::with_config(3, SchedulerConfig::default());
+
+    for i in 0..2 {
+        assert!(matches!(
+            s.next_task(false, 0),
+            SchedulerTask::ExecutionTask((j, 0), ExecutionTaskType::Execution) if j == i
+        ));
+    }
+    assert!(matches!(
+        s.wait_for_dependency(1, 0),
+        DependencyResult::Dependency(_)
+    ));
+    assert!(matches!(
+        s.finish_execution(0, 0, false),
+        SchedulerTask::NoTask
+    ));
+    assert!(s.try_abort(0, 0));
+    assert!(matches!(
+        s.finish_abort(0, 0),
+        SchedulerTask::ExecutionTask((0, 1), ExecutionTaskType::Execution)
+    ));
+    assert!(matches!(
+        s.next_task(false, 0),
+        SchedulerTask::ExecutionTask((1, 0), ExecutionTaskType::Wakeup(_))
+    ));
+
+    // The woken up txn 1 waits on the next incarnation of txn 0.
+    assert!(matches!(
+        s.wait_for_dependency(1, 0),
+        DependencyResult::Dependency(_)
+    ));
+    assert!(matches!(
+        s.finish_execution(0, 1, false),
+        SchedulerTask::ValidationTask((0, 1), 0)
+    ));
+
+    // Txn 1 is resumed once, before the execution of txn 2.
+    assert!(matches!(
+        s.next_task(false, 0),
+        SchedulerTask::ExecutionTask((1, 0), ExecutionTaskType::Wakeup(_))
+    ));
+    assert!(matches!(
+        s.next_task(false, 0),
+        SchedulerTask::ExecutionTask((2, 0), ExecutionTaskType::Execution)
+    ));
+}
+
+#[test]
+fn scheduler_completion() {
+    let s = Scheduler::with_config(2, SchedulerConfig::default());
+    for i in 0..2 {
+        assert_eq!(s.completion(), Completion::Running);
+        assert!(matches!(
+            s.next_task(false, 0),
+            SchedulerTask::ExecutionTask((j, 0), ExecutionTaskType::Execution) if j == i
+        ));
+        assert!(matches!(
+            s.finish_execution(i, 0, false),
+            SchedulerTask::NoTask
+        ));
+        assert!(matches!(
+            s.next_task(false, 0),
+            SchedulerTask::ValidationTask((j, 0), 0) if j == i
+        ));
+        s.finish_validation(i, 0);
+        assert!(!s.is_done());
+        assert_some_eq!(s.try_commit(), i);
+    }
+    assert!(s.is_done());
+    assert_eq!(s.completion(), Completion::CommittedAll);
+
+    // The executor may halt after committing the last transaction (e.g. due to the gas limit).
+    s.halt_with_reason(HaltReason::GasLimit);
+    assert_eq!(s.completion(), Completion::CommittedAll);
+
+    let s = Scheduler::with_config(2, SchedulerConfig::default());
+    assert_eq!(s.completion(), Completion::Running);
+    s.halt_with_reason(HaltReason::VmAbort);
+    assert!(s.is_done());
+    assert_eq!(s.completion(), Completion::Halted(HaltReason::VmAbort));
+}
+
+#[test]
+fn scheduler_abort_rejection() {
+    let s = Scheduler::with_config(2, SchedulerConfig::default());
+
+    assert!(matches!(
+        s.next_task(false, 0),
+        SchedulerTask::ExecutionTask((0, 0), ExecutionTaskType::Execution)
+    ));
+    assert_eq!(
+        s.try_abort_detailed(0, 0),
+        Err(AbortRejection {
+            kind: ExecutionStatusKind::Executing,
+            incarnation: Some(0),
+        })
+    );
+    assert!(matches!(
+        s.finish_execution(0, 0, false),
+        SchedulerTask::NoTask
+    ));
+    assert!(matches!(
+        s.next_task(false, 0),
+        SchedulerTask::ValidationTask((0, 0), 0)
+    ));
+    s.finish_validation(0, 0);
+    assert_some_eq!(s.try_commit(), 0);
+
+    assert_eq!(
+        s.try_abort_detailed(0, 0),
+        Err(AbortRejection {
+            kind: ExecutionStatusKind::Committed,
+            incarnation: Some(0),
+        })
+    );
+    assert!(!s.try_abort(0, 0));
+}
+
+// Returns a scheduler where all txns are executed and validated (wave 0), txn 1 was aborted
+// and its re-execution (incarnation 1) is ongoing.
+fn reexecuting_one_scheduler(num_txns: TxnIndex, config: SchedulerConfig) -> Scheduler {
+    let s = Scheduler::with_config(num_txns, config);
+    for i in 0..num_txns {
+        assert!(matches!(
+            s.next_task(false, 0),
+            SchedulerTask::ExecutionTask((j, 0), ExecutionTaskType::Execution) if j == i
+        ));
+        assert!(matches!(
+            s.finish_execution(i, 0, false),
+            SchedulerTask::NoTask
+        ));
+        assert!(matches!(
+            s.next_task(false, 0),
+            SchedulerTask::ValidationTask((j, 0), 0) if j == i
+        ));
+        s.finish_validation(i, 0);
+    }
+    assert!(s.try_abort(1, 0));
+    assert!(matches!(
+        s.finish_abort(1, 0),
+        SchedulerTask::ExecutionTask((1, 1), ExecutionTaskType::Execution)
+    ));
+    s
+}
+
+fn validation_versions(tasks: Vec<SchedulerTask>) -> Vec<(TxnIndex, Incarnation, Wave)> {
+    tasks
+        .into_iter()
+        .map(|task| match task {
+            SchedulerTask::ValidationTask((txn_idx, incarnation), wave) => {
+                (txn_idx, incarnation, wave)
+            },
+            _ => unreachable!(),
+        })
+        .collect()
+}
+
+#[test]
+fn finish_execution_suffix_validations() {
+    let num_txns = 6;
+    let expected: Vec<_> = (1..num_txns)
+        .map(|i| (i, if i == 1 { 1 } else { 0 }, 1))
+        .collect();
+
+    // Suffix validations discovered by next_task.
+    let s = reexecuting_one_scheduler(num_txns, SchedulerConfig::default());
+    let mut tasks = vec![s.finish_execution(1, 1, true)];
+    loop {
+        match s.next_task(false, 0) {
+            SchedulerTask::NoTask => break,
+            task => tasks.push(task),
+        }
+    }
+    assert_eq!(validation_versions(tasks), expected);
+}
+
+#[derive(Debug)]
+struct SkipWaves(Wave);
+
+impl WaveStrategy for SkipWaves {
+    fn next_wave(&self, wave: Wave) -> Wave {
+        wave + self.0
+    }
+}
+
+#[test]
+fn scheduler_wave_strategy() {
+    // Waves that would not increase are raised to wave + 1.
+    for (skip, expected_wave) in [(10, 10), (0, 1)] {
+        let s = reexecuting_one_scheduler(4, SchedulerConfig {
+            wave_strategy: Arc::new(SkipWaves(skip)),
+            ..SchedulerConfig::default()
+        });
+
+        assert!(matches!(
+            s.finish_execution(1, 1, false),
+            SchedulerTask::ValidationTask((1, 1), w) if w == expected_wave
+        ));
+        s.finish_validation(1, expected_wave);
+        for i in 2..4 {
+            assert!(matches!(
+                s.next_task(false, 0),
+                SchedulerTask::ValidationTask((j, 0), w) if j == i && w == expected_wave
+            ));
+        }
+
+        assert_some_eq!(s.try_commit(), 0);
+        assert_some_eq!(s.try_commit(), 1);
+        // The validation of txn 2 in wave 0 (before the abort of txn 1) is not sufficient.
+        assert_none!(s.try_commit());
+        s.finish_validation(2, expected_wave);
+        assert_some_eq!(s.try_commit(), 2);
+        assert_eq!(s.commit_state(), (3, expected_wave));
+        s.finish_validation(3, expected_wave);
+        assert_some_eq!(s.try_commit(), 3);
+        assert!(matches!(s.next_task(false, 0), SchedulerTask::Done));
+    }
+}
+
+#[test]
+fn committing_thread_gets_frontier_task() {
+    let s = Scheduler::with_config(6, SchedulerConfig::default());
+    for i in 0..6 {
+        assert!(matches!(
+            s.next_task(false, 0),
+            SchedulerTask::ExecutionTask((j, 0), ExecutionTaskType::Execution) if j == i
+        ));
+    }
+    // Executions finish out of order, the frontier (txn 0) last.
+    for i in (0..6).rev() {
+        assert!(matches!(
+            s.finish_execution(i, 0, false),
+            SchedulerTask::NoTask
+        ));
+    }
+
+    assert!(matches!(
+        s.next_task(true, 0),
+        SchedulerTask::ValidationTask((0, 0), 0)
+    ));
+    s.finish_validation(0, 0);
+    assert_some_eq!(s.try_commit(), 0);
+    assert!(matches!(
+        s.next_task(false, 1),
+        SchedulerTask::ValidationTask((1, 0), 0)
+    ));
+    assert!(matches!(
+        s.next_task(true, 0),
+        SchedulerTask::ValidationTask((2, 0), 0)
+    ));
+}
+
+// Steps all tasks of the scheduler with a single thread, aborting (once) the validations of
+// the txns in to_abort, and returns the log of tasks.
+fn seeded_task_log(
+    num_txns: TxnIndex,
+    seed: u64,
+    to_abort: &[TxnIndex],
+) -> Vec<SchedulerTaskRecord> {
+    let s = Scheduler::with_config(num_txns, SchedulerConfig::default());
+    s.set_seed(seed);
+
+    let mut log = vec![];
+    let mut task = SchedulerTask::NoTask;
+    loop {
+        log.push((&task).into());
+        task = match task {
+            SchedulerTask::ExecutionTask((txn_idx, incarnation), ExecutionTaskType::Execution) => {
+                s.finish_execution(txn_idx, incarnation, false)
+            },
+            SchedulerTask::ValidationTask((txn_idx, incarnation), wave) => {
+                if incarnation == 0 && to_abort.contains(&txn_idx) && s.try_abort(txn_idx, 0) {
+                    s.finish_abort(txn_idx, 0)
+                } else {
+                    s.finish_validation(txn_idx, wave);
+                    SchedulerTask::NoTask
+                }
+            },
+            SchedulerTask::NoTask => {
+                while !s.is_done() && s.try_commit().is_some() {}
+                s.next_task(true, 0)
+            },
+            SchedulerTask::Done => break,
+            _ => unreachable!(),
+        };
+    }
+    log
+}
+
+#[test]
+fn seeded_schedule_is_deterministic() {
+    let to_abort = [2, 5, 6, 11];
+    let logs: Vec<_> = (0..8)
+        .map(|seed| seeded_task_log(20, seed, &to_abort))
+        .collect();
+    for (seed, log) in logs.iter().enumerate() {
+        assert_eq!(*log, seeded_task_log(20, seed as u64, &to_abort));
+        assert_eq!(log.last(), Some(&SchedulerTaskRecord::Done));
+    }
+    // The seeds drive different schedules.
+    assert!(logs.iter().any(|log| *log != logs[0]));
+}
+
+#[test]
+fn scheduler_spin_iterations() {
+    let s = Scheduler::with_config(1, SchedulerConfig::default());
+    assert!(matches!(
+        s.next_task(true, 0),
+        SchedulerTask::ExecutionTask((0, 0), ExecutionTaskType::Execution)
+    ));
+    // The committing thread does not spin.
+    assert!(matches!(s.next_task(true, 0), SchedulerTask::NoTask));
+    assert_eq!(s.spin_iterations(), 0);
+
+    // The only transaction is being executed, so there are no tasks for the workers.
+    std::thread::scope(|scope| {
+        for worker_id in 0..4 {
+            let s = &s;
+            scope.spawn(move || {
+                for _ in 0..10 {
+                    assert!(matches!(
+                        s.next_task(false, worker_id),
+                        SchedulerTask::NoTask
+                    ));
+                }
+            });
+        }
+    });
+    assert_eq!(s.spin_iterations(), 40);
+}
+
+#[test]
+fn scheduler_committed_prefix_len() {
+    let s = Scheduler::with_config(5, SchedulerConfig::default());
+    for i in 0..5 {
+        assert!(matches!(
+            s.next_task(false, 0),
+            SchedulerTask::ExecutionTask((j, 0), ExecutionTaskType::Execution) if j == i
+        ));
+        assert!(matches!(
+            s.finish_execution(i, 0, false),
+            SchedulerTask::NoTask
+        ));
+        assert!(matches!(
+            s.next_task(false, 0),
+            SchedulerTask::ValidationTask((j, 0), 0) if j == i
+        ));
+        s.finish_validation(i, 0);
+    }
+
+    assert_eq!(s.committed_prefix_len(), 0);
+    for i in 0..2 {
+        assert_some_eq!(s.try_commit(), i);
+        assert_eq!(s.committed_prefix_len(), i as usize + 1);
+    }
+    s.halt_with_reason(HaltReason::SkipRest);
+    assert_none!(s.try_commit());
+    assert_eq!(s.committed_prefix_len(), 2);
+}
+
+#[cfg(feature = "event-log")]
+#[test]
+fn scheduler_recent_events() {
+    use crate::scheduler::SchedulerEvent;
+
+    let s = Scheduler::with_config(10, SchedulerConfig {
+        event_log_capacity: 4,
+        ..SchedulerConfig::default()
+    });
+    for i in 0..3 {
+        assert!(matches!(
+            s.next_task(false, 0),
+            SchedulerTask::ExecutionTask((j, 0), ExecutionTaskType::Execution) if j == i
+        ));
+    }
+    assert_eq!(s.recent_events(), vec![
+        SchedulerEvent::Execute((0, 0)),
+        SchedulerEvent::Execute((1, 0)),
+        SchedulerEvent::Execute((2, 0)),
+    ]);
+
+    assert!(matches!(
+        s.finish_execution(0, 0, false),
+        SchedulerTask::NoTask
+    ));
+    assert!(matches!(
+        s.next_task(false, 0),
+        SchedulerTask::ValidationTask((0, 0), 0)
+    ));
+    assert!(s.try_abort(0, 0));
+    assert!(matches!(
+        s.finish_abort(0, 0),
+        SchedulerTask::ExecutionTask((0, 1), ExecutionTaskType::Execution)
+    ));
+
+    // The oldest events are dropped.
+    assert_eq!(s.recent_events(), vec![
+        SchedulerEvent::Execute((2, 0)),
+        SchedulerEvent::Validate((0, 0), 0),
+        SchedulerEvent::Abort((0, 0)),
+        SchedulerEvent::Execute((0, 1)),
+    ]);
+}
+
 // Will return a scheduler in a state where all transactions are scheduled for
 // for execution, validation index = num_txns, and wave = 0.
 fn incarnation_one_scheduler(num_txns: TxnIndex) -> Scheduler {
-    let s = Scheduler::new(num_txns);
+    let s = Scheduler::with_config(num_txns, SchedulerConfig::default());
 
     for i in 0..num_txns {
         // Get the first executions out of the way.
         assert!(matches!(
-            s.next_task(false),
+            s.next_task(false, 0),
             SchedulerTask::ExecutionTask((j, 0), ExecutionTaskType::Execution) if j == i
         ));
         assert!(matches!(
@@ -469,7 +1605,7 @@ fn incarnation_one_scheduler(num_txns: TxnIndex) -> Scheduler {
             SchedulerTask::NoTask
         ));
         assert!(matches!(
-            s.next_task(false),
+            s.next_task(false, 0),
             SchedulerTask::ValidationTask((j, 0), 0) if i == j
         ));
         assert!(s.try_abort(i, 0));
@@ -509,7 +1645,7 @@ fn scheduler_incarnation() {
     ));
 
     assert!(matches!(
-        s.next_task(false),
+        s.next_task(false, 0),
         SchedulerTask::ValidationTask((4, 1), 1),
     ));
 
@@ -531,15 +1667,15 @@ fn scheduler_incarnation() {
     assert!(matches!(s.finish_abort(4, 1), SchedulerTask::NoTask));
 
     assert!(matches!(
-        s.next_task(false),
+        s.next_task(false, 0),
         SchedulerTask::ExecutionTask((1, 1), ExecutionTaskType::Wakeup(_))
     ));
     assert!(matches!(
-        s.next_task(false),
+        s.next_task(false, 0),
         SchedulerTask::ExecutionTask((3, 1), ExecutionTaskType::Wakeup(_))
     ));
     assert!(matches!(
-        s.next_task(false),
+        s.next_task(false, 0),
         SchedulerTask::ExecutionTask((4, 2), ExecutionTaskType::Execution)
     ));
     // execution index = 5
@@ -563,19 +1699,19 @@ fn scheduler_incarnation() {
         SchedulerTask::NoTask
     ));
     assert!(matches!(
-        s.next_task(false),
+        s.next_task(false, 0),
         SchedulerTask::ValidationTask((4, 2), 2)
     ));
 }
 
 #[test]
 fn scheduler_basic() {
-    let s = Scheduler::new(3);
+    let s = Scheduler::with_config(3, SchedulerConfig::default());
 
     for i in 0..3 {
         // Nothing to validate.
         assert!(matches!(
-            s.next_task(false),
+            s.next_task(false, 0),
             SchedulerTask::ExecutionTask((j, 0), ExecutionTaskType::Execution) if j == i
         ));
     }
@@ -590,11 +1726,11 @@ fn scheduler_basic() {
         SchedulerTask::NoTask
     ));
     assert!(matches!(
-        s.next_task(false),
+        s.next_task(false, 0),
         SchedulerTask::ValidationTask((0, 0), 0)
     ));
     assert!(matches!(
-        s.next_task(false),
+        s.next_task(false, 0),
         SchedulerTask::ValidationTask((1, 0), 0)
     ));
     assert!(matches!(
@@ -602,7 +1738,7 @@ fn scheduler_basic() {
         SchedulerTask::NoTask
     ));
     assert!(matches!(
-        s.next_task(false),
+        s.next_task(false, 0),
         SchedulerTask::ValidationTask((2, 0), 0)
     ));
 
@@ -615,17 +1751,17 @@ fn scheduler_basic() {
         assert_some_eq!(s.try_commit(), i);
     }
 
-    assert!(matches!(s.next_task(false), SchedulerTask::Done));
+    assert!(matches!(s.next_task(false, 0), SchedulerTask::Done));
 }
 
 #[test]
 fn scheduler_drain_idx() {
-    let s = Scheduler::new(3);
+    let s = Scheduler::with_config(3, SchedulerConfig::default());
 
     for i in 0..3 {
         // Nothing to validate.
         assert!(matches!(
-            s.next_task(false),
+            s.next_task(false, 0),
             SchedulerTask::ExecutionTask((j, 0), ExecutionTaskType::Execution) if j == i
         ));
     }
@@ -640,11 +1776,11 @@ fn scheduler_drain_idx() {
         SchedulerTask::NoTask
     ));
     assert!(matches!(
-        s.next_task(false),
+        s.next_task(false, 0),
         SchedulerTask::ValidationTask((0, 0), 0)
     ));
     assert!(matches!(
-        s.next_task(false),
+        s.next_task(false, 0),
         SchedulerTask::ValidationTask((1, 0), 0)
     ));
     assert!(matches!(
@@ -652,7 +1788,7 @@ fn scheduler_drain_idx() {
         SchedulerTask::NoTask
     ));
     assert!(matches!(
-        s.next_task(false),
+        s.next_task(false, 0),
         SchedulerTask::ValidationTask((2, 0), 0)
     ));
 
@@ -665,7 +1801,7 @@ fn scheduler_drain_idx() {
         assert_some_eq!(s.try_commit(), i);
     }
 
-    assert!(matches!(s.next_task(false), SchedulerTask::Done));
+    assert!(matches!(s.next_task(false, 0), SchedulerTask::Done));
 }
 
 #[test]
@@ -743,7 +1879,7 @@ fn rolling_commit_wave() {
     assert_eq!(s.commit_state(), (3, 1));
 
     // All txns have been committed.
-    assert!(matches!(s.next_task(false), SchedulerTask::Done));
+    assert!(matches!(s.next_task(false, 0), SchedulerTask::Done));
 }
 
 #[test]
@@ -763,7 +1899,7 @@ fn no_conflict_task_count() {
 
     let num_txns: TxnIndex = 1000;
     for num_concurrent_tasks in [1, 5, 10, 20] {
-        let s = Scheduler::new(num_txns);
+        let s = Scheduler::with_config(num_txns, SchedulerConfig::default());
 
         let mut tasks = BTreeMap::new();
 
@@ -773,7 +1909,7 @@ fn no_conflict_task_count() {
 
         loop {
             while tasks.len() < num_concurrent_tasks {
-                match s.next_task(false) {
+                match s.next_task(false, 0) {
                     SchedulerTask::ExecutionTask((txn_idx, incarnation), _) => {
                         assert_eq!(incarnation, 0);
                         // true means an execution task.
@@ -788,6 +1924,8 @@ fn no_conflict_task_count() {
                     SchedulerTask::NoTask => break,
                     // Unreachable because we never call try_commit.
                     SchedulerTask::Done => unreachable!(),
+                    // Unreachable because validation ranges are not enabled.
+                    SchedulerTask::ValidationRange(..) => unreachable!(),
                 }
             }
 
@@ -828,6 +1966,6 @@ fn no_conflict_task_count() {
             assert_some_eq!(s.try_commit(), i);
             assert_eq!(s.commit_state(), (i + 1, 0));
         }
-        assert!(matches!(s.next_task(false), SchedulerTask::Done));
+        assert!(matches!(s.next_task(false, 0), SchedulerTask::Done));
     }
 }
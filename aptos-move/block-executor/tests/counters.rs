@@ -0,0 +1,29 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+// A separate test binary, so that the buckets are set before any use of the histogram.
+
+use aptos_block_executor::counters::{set_get_next_task_buckets, GET_NEXT_TASK_SECONDS};
+use aptos_metrics_core::gather;
+
+#[test]
+fn get_next_task_seconds_buckets() {
+    let buckets = vec![1e-8, 1e-7, 2.5e-7, 5e-7, 1e-6, 1e-5];
+    assert!(set_get_next_task_buckets(buckets.clone()));
+    GET_NEXT_TASK_SECONDS.observe(3e-7);
+    // Too late, the histogram is registered.
+    assert!(!set_get_next_task_buckets(vec![1.0]));
+
+    let family = gather()
+        .into_iter()
+        .find(|family| family.get_name() == "aptos_execution_get_next_task_seconds")
+        .unwrap();
+    let histogram = family.get_metric()[0].get_histogram();
+    let upper_bounds: Vec<f64> = histogram
+        .get_bucket()
+        .iter()
+        .map(|bucket| bucket.get_upper_bound())
+        .collect();
+    assert_eq!(upper_bounds, buckets);
+    assert_eq!(histogram.get_sample_count(), 1);
+}
@@ -70,3 +70,45 @@ fn test_delete_block_and_qc() {
     assert_eq!(db.get_blocks().unwrap().len(), 0);
     assert_eq!(db.get_quorum_certificates().unwrap().len(), 0);
 }
+
+#[test]
+fn test_dag_broadcast() {
+    let tmp_dir = TempPath::new();
+    let db = ConsensusDB::new(&tmp_dir);
+
+    assert_eq!(db.get_dag_broadcasts().unwrap().len(), 0);
+
+    let digest = HashValue::random();
+    db.save_dag_broadcast(digest, vec![1u8, 2, 3]).unwrap();
+    db.save_dag_broadcast(digest, vec![1u8, 2, 3, 4]).unwrap();
+    let broadcasts = db.get_dag_broadcasts().unwrap();
+    assert_eq!(broadcasts.len(), 1);
+    assert_eq!(broadcasts.get(&digest), Some(&vec![1u8, 2, 3, 4]));
+    assert_eq!(
+        db.get_dag_broadcast(digest).unwrap(),
+        Some(vec![1u8, 2, 3, 4])
+    );
+
+    db.delete_dag_broadcast(digest).unwrap();
+    assert_eq!(db.get_dag_broadcasts().unwrap().len(), 0);
+}
+
+#[test]
+fn test_dag_nodes() {
+    let tmp_dir = TempPath::new();
+    let db = ConsensusDB::new(&tmp_dir);
+
+    assert_eq!(db.get_dag_nodes().unwrap().len(), 0);
+
+    let author = Author::random();
+    db.save_dag_node((2, author), vec![2u8]).unwrap();
+    db.save_dag_node((1, author), vec![1u8]).unwrap();
+    // iteration is in round order
+    assert_eq!(db.get_dag_nodes().unwrap(), vec![
+        ((1, author), vec![1u8]),
+        ((2, author), vec![2u8])
+    ]);
+
+    db.delete_dag_nodes(vec![(1, author)]).unwrap();
+    assert_eq!(db.get_dag_nodes().unwrap(), vec![((2, author), vec![2u8])]);
+}
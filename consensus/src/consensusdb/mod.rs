@@ -9,17 +9,24 @@ mod schema;
 use crate::{
     consensusdb::schema::{
         block::BlockSchema,
+        dag::{DagBroadcastSchema, DagNodeSchema},
         quorum_certificate::QCSchema,
         single_entry::{SingleEntryKey, SingleEntrySchema},
     },
     error::DbError,
 };
 use anyhow::Result;
-use aptos_consensus_types::{block::Block, quorum_cert::QuorumCert};
+use aptos_consensus_types::{
+    block::Block,
+    common::{Author, Round},
+    quorum_cert::QuorumCert,
+};
 use aptos_crypto::HashValue;
 use aptos_logger::prelude::*;
 use aptos_schemadb::{Options, ReadOptions, SchemaBatch, DB, DEFAULT_COLUMN_FAMILY_NAME};
-use schema::{BLOCK_CF_NAME, QC_CF_NAME, SINGLE_ENTRY_CF_NAME};
+use schema::{
+    BLOCK_CF_NAME, DAG_BROADCAST_CF_NAME, DAG_NODE_CF_NAME, QC_CF_NAME, SINGLE_ENTRY_CF_NAME,
+};
 use std::{collections::HashMap, iter::Iterator, path::Path, time::Instant};
 
 /// The name of the consensus db file
@@ -52,6 +59,8 @@ impl ConsensusDB {
             BLOCK_CF_NAME,
             QC_CF_NAME,
             SINGLE_ENTRY_CF_NAME,
+            DAG_BROADCAST_CF_NAME,
+            DAG_NODE_CF_NAME,
         ];
 
         let path = db_root_path.as_ref().join(CONSENSUS_DB_NAME);
@@ -140,6 +149,52 @@ impl ConsensusDB {
         self.commit(batch)
     }
 
+    pub fn save_dag_broadcast(&self, digest: HashValue, broadcast: Vec<u8>) -> Result<(), DbError> {
+        let batch = SchemaBatch::new();
+        batch.put::<DagBroadcastSchema>(&digest, &broadcast)?;
+        self.commit(batch)
+    }
+
+    pub fn delete_dag_broadcast(&self, digest: HashValue) -> Result<(), DbError> {
+        let batch = SchemaBatch::new();
+        batch.delete::<DagBroadcastSchema>(&digest)?;
+        self.commit(batch)
+    }
+
+    pub fn get_dag_broadcast(&self, digest: HashValue) -> Result<Option<Vec<u8>>, DbError> {
+        Ok(self.db.get::<DagBroadcastSchema>(&digest)?)
+    }
+
+    /// Get all serialized in-flight DAG broadcasts.
+    pub fn get_dag_broadcasts(&self) -> Result<HashMap<HashValue, Vec<u8>>, DbError> {
+        let mut iter = self.db.iter::<DagBroadcastSchema>(ReadOptions::default())?;
+        iter.seek_to_first();
+        Ok(iter.collect::<Result<HashMap<HashValue, Vec<u8>>>>()?)
+    }
+
+    pub fn save_dag_node(&self, key: (Round, Author), node: Vec<u8>) -> Result<(), DbError> {
+        let batch = SchemaBatch::new();
+        batch.put::<DagNodeSchema>(&key, &node)?;
+        self.commit(batch)
+    }
+
+    pub fn delete_dag_nodes(&self, keys: Vec<(Round, Author)>) -> Result<(), DbError> {
+        if keys.is_empty() {
+            return Ok(());
+        }
+        let batch = SchemaBatch::new();
+        keys.iter()
+            .try_for_each(|key| batch.delete::<DagNodeSchema>(key))?;
+        self.commit(batch)
+    }
+
+    /// Get all serialized DAG nodes in round order.
+    pub fn get_dag_nodes(&self) -> Result<Vec<((Round, Author), Vec<u8>)>, DbError> {
+        let mut iter = self.db.iter::<DagNodeSchema>(ReadOptions::default())?;
+        iter.seek_to_first();
+        Ok(iter.collect::<Result<Vec<_>>>()?)
+    }
+
     /// Write the whole schema batch including all data necessary to mutate the ledger
     /// state of some transaction by leveraging rocksdb atomicity support.
     fn commit(&self, batch: SchemaBatch) -> Result<(), DbError> {
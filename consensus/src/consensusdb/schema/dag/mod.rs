@@ -0,0 +1,84 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module defines physical storage schema for DAG consensus data.
+//!
+//! Serialized in-flight reliable broadcasts identified by the message digest.
+//! ```text
+//! |<---key--->|<-------value------->|
+//! |  digest   |  pending broadcast  |
+//! ```
+//!
+//! Serialized certified nodes identified by round and author, so that iteration is in round order.
+//! ```text
+//! |<-------key------->|<------value------->|
+//! |  round | author   |  certified node    |
+//! ```
+
+use super::{ensure_slice_len_eq, DAG_BROADCAST_CF_NAME, DAG_NODE_CF_NAME};
+use anyhow::Result;
+use aptos_consensus_types::common::{Author, Round};
+use aptos_crypto::HashValue;
+use aptos_schemadb::{
+    define_schema,
+    schema::{KeyCodec, ValueCodec},
+};
+
+define_schema!(
+    DagBroadcastSchema,
+    HashValue,
+    Vec<u8>,
+    DAG_BROADCAST_CF_NAME
+);
+
+impl KeyCodec<DagBroadcastSchema> for HashValue {
+    fn encode_key(&self) -> Result<Vec<u8>> {
+        Ok(self.to_vec())
+    }
+
+    fn decode_key(data: &[u8]) -> Result<Self> {
+        Ok(HashValue::from_slice(data)?)
+    }
+}
+
+impl ValueCodec<DagBroadcastSchema> for Vec<u8> {
+    fn encode_value(&self) -> Result<Vec<u8>> {
+        Ok(self.clone())
+    }
+
+    fn decode_value(data: &[u8]) -> Result<Self> {
+        Ok(data.to_vec())
+    }
+}
+
+define_schema!(DagNodeSchema, (Round, Author), Vec<u8>, DAG_NODE_CF_NAME);
+
+impl KeyCodec<DagNodeSchema> for (Round, Author) {
+    fn encode_key(&self) -> Result<Vec<u8>> {
+        let mut bytes = self.0.to_be_bytes().to_vec();
+        bytes.extend(self.1.to_vec());
+        Ok(bytes)
+    }
+
+    fn decode_key(data: &[u8]) -> Result<Self> {
+        ensure_slice_len_eq(data, std::mem::size_of::<Round>() + Author::LENGTH)?;
+        let (round, author) = data.split_at(std::mem::size_of::<Round>());
+        Ok((
+            Round::from_be_bytes(round.try_into()?),
+            Author::from_bytes(author)?,
+        ))
+    }
+}
+
+impl ValueCodec<DagNodeSchema> for Vec<u8> {
+    fn encode_value(&self) -> Result<Vec<u8>> {
+        Ok(self.clone())
+    }
+
+    fn decode_value(data: &[u8]) -> Result<Self> {
+        Ok(data.to_vec())
+    }
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,18 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use super::*;
+use aptos_schemadb::{schema::fuzzing::assert_encode_decode, test_no_panic_decoding};
+
+#[test]
+fn test_dag_broadcast_schema() {
+    assert_encode_decode::<DagBroadcastSchema>(&HashValue::random(), &vec![1u8, 2u8, 3u8]);
+}
+
+#[test]
+fn test_dag_node_schema() {
+    assert_encode_decode::<DagNodeSchema>(&(10, Author::random()), &vec![1u8, 2u8, 3u8]);
+}
+
+test_no_panic_decoding!(DagBroadcastSchema);
+test_no_panic_decoding!(DagNodeSchema);
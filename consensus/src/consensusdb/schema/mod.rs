@@ -3,6 +3,7 @@
 // SPDX-License-Identifier: Apache-2.0
 
 pub(crate) mod block;
+pub(crate) mod dag;
 pub(crate) mod quorum_certificate;
 pub(crate) mod single_entry;
 
@@ -10,6 +11,8 @@ use anyhow::{ensure, Result};
 use aptos_schemadb::ColumnFamilyName;
 
 pub(super) const BLOCK_CF_NAME: ColumnFamilyName = "block";
+pub(super) const DAG_BROADCAST_CF_NAME: ColumnFamilyName = "dag_broadcast";
+pub(super) const DAG_NODE_CF_NAME: ColumnFamilyName = "dag_node";
 pub(super) const QC_CF_NAME: ColumnFamilyName = "quorum_certificate";
 pub(super) const SINGLE_ENTRY_CF_NAME: ColumnFamilyName = "single_entry";
 
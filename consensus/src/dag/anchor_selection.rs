@@ -0,0 +1,41 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::dag::{dag_store::Dag, types::CertifiedNode};
+use aptos_consensus_types::common::{Author, Round};
+use std::sync::Arc;
+
+/// Decides which node of a round, if any, is the anchor that drives the commit ordering.
+pub trait AnchorSelector: Send + Sync {
+    /// Returns the author of the anchor of the round, or None if the round has no anchor.
+    fn anchor_author(&self, round: Round) -> Option<Author>;
+
+    /// Returns the anchor of the round, or None if the round has no anchor or the anchor is not
+    /// in the DAG yet.
+    fn select_anchor(&self, round: Round, dag: &Dag) -> Option<Arc<CertifiedNode>> {
+        let author = self.anchor_author(round)?;
+        dag.nodes_at_round(round)
+            .into_iter()
+            .find(|node| *node.metadata().author() == author)
+    }
+}
+
+/// Every even round has an anchor, with the validators taking turns in order.
+pub struct RoundRobinAnchorSelector {
+    validators: Vec<Author>,
+}
+
+impl RoundRobinAnchorSelector {
+    pub fn new(validators: Vec<Author>) -> Self {
+        Self { validators }
+    }
+}
+
+impl AnchorSelector for RoundRobinAnchorSelector {
+    fn anchor_author(&self, round: Round) -> Option<Author> {
+        if round % 2 != 0 || self.validators.is_empty() {
+            return None;
+        }
+        Some(self.validators[(round / 2) as usize % self.validators.len()])
+    }
+}
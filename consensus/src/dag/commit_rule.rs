@@ -0,0 +1,76 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::dag::{dag_store::Dag, types::CertifiedNode};
+use aptos_consensus_types::common::{Author, Round};
+use aptos_types::validator_verifier::ValidatorVerifier;
+use std::{collections::BTreeSet, sync::Arc};
+
+/// Decides when an anchor is committed and which part of its causal history it orders. Anchors
+/// that are not committed directly are committed through a later committed anchor with a path to
+/// them, see `DagDriver`.
+pub trait CommitRule: Send {
+    /// Returns true if the anchor is committed directly given the current DAG.
+    fn is_committed(&self, anchor: &CertifiedNode, dag: &Dag) -> bool;
+
+    /// Returns the nodes a committed anchor orders, its causal history that is not ordered yet,
+    /// anchor included.
+    fn order_history(&mut self, anchor: &Arc<CertifiedNode>, dag: &Dag) -> Vec<Arc<CertifiedNode>>;
+}
+
+/// Commits an anchor once validators with more than a third of the voting power link to it in
+/// the next round, and orders its not yet ordered causal history by round and author, the order
+/// of `Dag::causal_history`.
+pub struct CausalOrderCommitRule {
+    verifier: ValidatorVerifier,
+    /// Nodes that are already ordered, pruned along with the DAG
+    ordered: BTreeSet<(Round, Author)>,
+}
+
+impl CausalOrderCommitRule {
+    pub fn new(verifier: ValidatorVerifier) -> Self {
+        Self {
+            verifier,
+            ordered: BTreeSet::new(),
+        }
+    }
+}
+
+impl CommitRule for CausalOrderCommitRule {
+    fn is_committed(&self, anchor: &CertifiedNode, dag: &Dag) -> bool {
+        let votes: u128 = dag
+            .nodes_at_round(anchor.metadata().round() + 1)
+            .iter()
+            .filter(|node| {
+                node.parents()
+                    .iter()
+                    .any(|parent| parent.metadata().digest() == anchor.metadata().digest())
+            })
+            .filter_map(|node| self.verifier.get_voting_power(node.metadata().author()))
+            .map(|voting_power| voting_power as u128)
+            .sum();
+        let threshold =
+            self.verifier.total_voting_power() - self.verifier.quorum_voting_power() + 1;
+        votes >= threshold
+    }
+
+    fn order_history(&mut self, anchor: &Arc<CertifiedNode>, dag: &Dag) -> Vec<Arc<CertifiedNode>> {
+        self.ordered = self.ordered.split_off(&(dag.lowest_round(), Author::ZERO));
+        let mut ordered_nodes = vec![];
+        let mut to_visit = vec![anchor.clone()];
+        while let Some(node) = to_visit.pop() {
+            let key = (node.metadata().round(), *node.metadata().author());
+            if !self.ordered.insert(key) {
+                continue;
+            }
+            to_visit.extend(
+                node.parents()
+                    .iter()
+                    .filter_map(|parent| dag.get_node(parent.metadata().digest())),
+            );
+            ordered_nodes.push(node);
+        }
+        ordered_nodes.sort_by_key(|node| (node.metadata().round(), *node.metadata().author()));
+        ordered_nodes
+    }
+}
@@ -0,0 +1,99 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use aptos_metrics_core::{register_int_counter_vec, register_int_gauge, IntCounterVec, IntGauge};
+use once_cell::sync::Lazy;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+/// Count of DAG messages sent by this validator, by message kind
+pub static DAG_MESSAGES_SENT: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "aptos_consensus_dag_messages_sent_count",
+        "Count of DAG messages sent by this validator",
+        &["kind"]
+    )
+    .unwrap()
+});
+
+/// Count of DAG messages received by this validator, by message kind
+pub static DAG_MESSAGES_RECEIVED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "aptos_consensus_dag_messages_received_count",
+        "Count of DAG messages received by this validator",
+        &["kind"]
+    )
+    .unwrap()
+});
+
+/// The round this validator is currently proposing in
+pub static DAG_CURRENT_ROUND: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "aptos_consensus_dag_current_round",
+        "The current round of the DAG driver"
+    )
+    .unwrap()
+});
+
+/// Number of nodes retained in the in-memory DAG
+pub static DAG_NUM_NODES: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "aptos_consensus_dag_num_nodes",
+        "Number of nodes retained in the DAG store"
+    )
+    .unwrap()
+});
+
+/// Number of incoming DAG requests taken off the network and waiting to be processed
+pub static DAG_HANDLER_QUEUE_DEPTH: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "aptos_consensus_dag_handler_queue_depth",
+        "Number of incoming DAG requests waiting to be processed"
+    )
+    .unwrap()
+});
+
+/// Number of fetches waiting for a free slot
+pub static DAG_FETCH_QUEUE_DEPTH: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "aptos_consensus_dag_fetch_queue_depth",
+        "Number of DAG fetches waiting for a free slot"
+    )
+    .unwrap()
+});
+
+/// Number of reliable broadcasts in progress
+pub static DAG_OUTSTANDING_BROADCASTS: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "aptos_consensus_dag_outstanding_broadcasts",
+        "Number of DAG reliable broadcasts in progress"
+    )
+    .unwrap()
+});
+
+/// Depth of a queue owned by one task, readable from the others and mirrored into a gauge.
+#[derive(Clone)]
+pub struct QueueDepth {
+    depth: Arc<AtomicUsize>,
+    gauge: IntGauge,
+}
+
+impl QueueDepth {
+    pub fn new(gauge: IntGauge) -> Self {
+        Self {
+            depth: Arc::new(AtomicUsize::new(0)),
+            gauge,
+        }
+    }
+
+    pub fn set(&self, depth: usize) {
+        self.depth.store(depth, Ordering::Relaxed);
+        self.gauge.set(depth as i64);
+    }
+
+    pub fn get(&self) -> usize {
+        self.depth.load(Ordering::Relaxed)
+    }
+}
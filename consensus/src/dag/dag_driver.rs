@@ -3,24 +3,197 @@
 
 use crate::{
     dag::{
+        anchor_selection::AnchorSelector,
+        commit_rule::CommitRule,
+        counters::{self, QueueDepth},
+        dag_fetcher::PendingFetches,
         dag_store::Dag,
         reliable_broadcast::ReliableBroadcast,
-        types::{CertificateAckState, CertifiedNode, Node, NodeCertificate, SignatureBuilder},
+        types::{
+            CertificateAckState, CertifiedNode, DAGMessage, Node, NodeCertificate, RoundTimeout,
+            RoundTimeoutCertificate, RoundTimeoutDelivery, SignatureBuilder,
+        },
     },
     state_replication::PayloadClient,
-    util::time_service::TimeService,
+    util::time_service::{SendTask, TimeService},
 };
-use aptos_consensus_types::common::{Author, Payload};
+use anyhow::ensure;
+use aptos_consensus_types::common::{Author, Payload, PayloadFilter};
 use aptos_infallible::RwLock;
-use aptos_types::{block_info::Round, epoch_state::EpochState};
+use aptos_logger::error;
+use aptos_types::{
+    aggregate_signature::PartialSignatures, block_info::Round, epoch_state::EpochState,
+    validator_signer::ValidatorSigner,
+};
 use futures::{
-    future::{AbortHandle, Abortable},
-    FutureExt,
+    future::{self, AbortHandle, Abortable, BoxFuture},
+    Future, FutureExt,
 };
-use std::sync::Arc;
+use serde::Serialize;
+use std::{cmp::max, collections::BTreeMap, sync::Arc, time::Duration};
+
+/// How strongly the proposal path should slow down, ordered from no to high backpressure.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub enum BackpressureLevel {
+    None,
+    Moderate,
+    High,
+}
+
+/// Snapshot of the DAG state, e.g. to render on an admin endpoint.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct DagHealth {
+    pub current_round: Round,
+    /// Lowest and highest rounds retained in the DAG
+    pub lowest_round: Round,
+    pub highest_round: Round,
+    pub num_nodes: usize,
+    /// Nodes queued or being fetched from peers
+    pub pending_fetches: usize,
+    pub backpressure_level: BackpressureLevel,
+    /// Broadcasts still waiting on acks
+    pub broadcast_backlog: usize,
+    /// Incoming requests waiting to be processed by the network handler
+    pub handler_queue_depth: usize,
+    /// Fetches waiting for a free slot
+    pub fetch_queue_depth: usize,
+    /// Broadcasts in progress in this process, unlike the backlog it leaves out the ones
+    /// persisted before a restart that weren't resumed
+    pub outstanding_broadcasts: usize,
+}
+
+const MODERATE_BACKPRESSURE_OCCUPANCY: f64 = 0.7;
+const HIGH_BACKPRESSURE_OCCUPANCY: f64 = 0.9;
+const MODERATE_BACKPRESSURE_ROUND_LAG: Round = 4;
+const HIGH_BACKPRESSURE_ROUND_LAG: Round = 10;
+
+#[derive(Clone, Copy, Debug)]
+pub struct AdaptiveTimeoutConfig {
+    /// Weight of the latest round latency in the moving average
+    pub smoothing: f64,
+    /// The round timeout is this multiple of the average round latency
+    pub multiplier: f64,
+    pub min_timeout: Duration,
+    pub max_timeout: Duration,
+}
+
+impl Default for AdaptiveTimeoutConfig {
+    fn default() -> Self {
+        Self {
+            smoothing: 0.2,
+            multiplier: 3.0,
+            min_timeout: Duration::from_millis(200),
+            max_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Derives the round timeout from an exponentially weighted moving average of the time it took
+/// to gather a quorum of nodes in the previous rounds.
+pub struct AdaptiveRoundTimeout {
+    config: AdaptiveTimeoutConfig,
+    initial_timeout: Duration,
+    average_latency: Option<f64>,
+}
+
+impl AdaptiveRoundTimeout {
+    pub fn new(initial_timeout: Duration, config: AdaptiveTimeoutConfig) -> Self {
+        Self {
+            config,
+            initial_timeout,
+            average_latency: None,
+        }
+    }
+
+    pub fn record_round_latency(&mut self, latency: Duration) {
+        let latency = latency.as_secs_f64();
+        self.average_latency = Some(match self.average_latency {
+            Some(average) => average + self.config.smoothing * (latency - average),
+            None => latency,
+        });
+    }
+
+    /// The initial timeout until a round latency is observed, bounded by min and max timeout.
+    pub fn timeout(&self) -> Duration {
+        let timeout = match self.average_latency {
+            Some(average) => Duration::from_secs_f64(average * self.config.multiplier),
+            None => self.initial_timeout,
+        };
+        timeout.clamp(self.config.min_timeout, self.config.max_timeout)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct AdaptiveBatchConfig {
+    /// Bounds of the number of transactions pulled into a node
+    pub min_batch_size: u64,
+    pub max_batch_size: u64,
+    /// Bytes of transactions pulled into a node at most
+    pub max_batch_bytes: u64,
+    /// Rounds that take longer than this count as a sign of load
+    pub target_round_latency: Duration,
+    /// Added to the batch size after a fast round without backpressure
+    pub increase_step: u64,
+    /// The batch size is multiplied by this after a slow round or under high backpressure
+    pub decrease_factor: f64,
+}
+
+impl Default for AdaptiveBatchConfig {
+    fn default() -> Self {
+        Self {
+            min_batch_size: 100,
+            max_batch_size: 5000,
+            max_batch_bytes: 600 * 1024,
+            target_round_latency: Duration::from_millis(500),
+            increase_step: 250,
+            decrease_factor: 0.5,
+        }
+    }
+}
+
+/// Sizes the batch of transactions of the next node from the outcome of the last round: it
+/// grows additively while rounds are fast and there's no backpressure, and shrinks
+/// multiplicatively when a round is slow or the backpressure is high. It starts from the
+/// smallest batch.
+pub struct AdaptiveBatchSize {
+    config: AdaptiveBatchConfig,
+    batch_size: u64,
+}
+
+impl AdaptiveBatchSize {
+    pub fn new(config: AdaptiveBatchConfig) -> Self {
+        Self {
+            config,
+            batch_size: config.min_batch_size,
+        }
+    }
+
+    pub fn record_round(&mut self, backpressure_level: BackpressureLevel, latency: Duration) {
+        let batch_size = if backpressure_level == BackpressureLevel::High
+            || latency > self.config.target_round_latency
+        {
+            (self.batch_size as f64 * self.config.decrease_factor) as u64
+        } else if backpressure_level == BackpressureLevel::None {
+            self.batch_size.saturating_add(self.config.increase_step)
+        } else {
+            self.batch_size
+        };
+        self.batch_size = batch_size.clamp(self.config.min_batch_size, self.config.max_batch_size);
+    }
+
+    pub fn batch_size(&self) -> u64 {
+        self.batch_size
+    }
+
+    pub fn max_batch_bytes(&self) -> u64 {
+        self.config.max_batch_bytes
+    }
+}
 
 pub(crate) struct DagDriver {
     author: Author,
+    /// Signs the round timeouts of this validator
+    signer: Arc<ValidatorSigner>,
     epoch_state: Arc<EpochState>,
     dag: Arc<RwLock<Dag>>,
     payload_client: Arc<dyn PayloadClient>,
@@ -28,20 +201,53 @@ pub(crate) struct DagDriver {
     current_round: Round,
     time_service: Arc<dyn TimeService>,
     rb_abort_handle: Option<AbortHandle>,
+    round_timeout: Duration,
+    /// Adjusts round_timeout to the observed round latency, when set
+    adaptive_timeout: Option<AdaptiveRoundTimeout>,
+    /// Time the current round started at
+    round_start: Duration,
+    /// Sizes the payload of the next node, when set
+    adaptive_batch_size: Option<AdaptiveBatchSize>,
+    /// Receives the round whenever a round timer expires
+    timeout_tx: aptos_channels::Sender<Round>,
+    round_timer_abort_handle: Option<AbortHandle>,
+    /// Timeouts of the current and the next round, the ones of later rounds are rejected
+    timeouts_by_round: BTreeMap<Round, PartialSignatures>,
+    timeout_abort_handle: Option<AbortHandle>,
+    anchor_selector: Arc<dyn AnchorSelector>,
+    /// Anchors of completed rounds that are not committed yet
+    uncommitted_anchors: BTreeMap<Round, Arc<CertifiedNode>>,
+    /// Round of the last committed anchor, the walk back to earlier anchors stops there
+    committed_anchor_round: Round,
+    commit_rule: Box<dyn CommitRule>,
+    /// Receives the nodes ordered by each committed anchor
+    ordered_nodes_tx: aptos_channels::Sender<Vec<Arc<CertifiedNode>>>,
+    /// Highest round of any node received, including the ones still missing parents
+    highest_seen_round: Round,
+    pending_fetches: Option<PendingFetches>,
+    fetch_queue_depth: Option<QueueDepth>,
+    handler_queue_depth: Option<QueueDepth>,
 }
 
 impl DagDriver {
     pub fn new(
-        author: Author,
+        signer: Arc<ValidatorSigner>,
         epoch_state: Arc<EpochState>,
         dag: Arc<RwLock<Dag>>,
         payload_client: Arc<dyn PayloadClient>,
         reliable_broadcast: Arc<ReliableBroadcast>,
         current_round: Round,
         time_service: Arc<dyn TimeService>,
+        round_timeout: Duration,
+        timeout_tx: aptos_channels::Sender<Round>,
+        anchor_selector: Arc<dyn AnchorSelector>,
+        commit_rule: Box<dyn CommitRule>,
+        ordered_nodes_tx: aptos_channels::Sender<Vec<Arc<CertifiedNode>>>,
     ) -> Self {
-        Self {
-            author,
+        let round_start = time_service.get_current_timestamp();
+        let mut driver = Self {
+            author: signer.author(),
+            signer,
             epoch_state,
             dag,
             payload_client,
@@ -49,61 +255,537 @@ impl DagDriver {
             current_round,
             time_service,
             rb_abort_handle: None,
+            round_timeout,
+            adaptive_timeout: None,
+            round_start,
+            adaptive_batch_size: None,
+            timeout_tx,
+            round_timer_abort_handle: None,
+            timeouts_by_round: BTreeMap::new(),
+            timeout_abort_handle: None,
+            anchor_selector,
+            uncommitted_anchors: BTreeMap::new(),
+            committed_anchor_round: 0,
+            commit_rule,
+            ordered_nodes_tx,
+            highest_seen_round: current_round,
+            pending_fetches: None,
+            fetch_queue_depth: None,
+            handler_queue_depth: None,
+        };
+        counters::DAG_CURRENT_ROUND.set(driver.current_round as i64);
+        driver.reset_round_timer();
+        driver.resume_pending_broadcasts();
+        driver
+    }
+
+    /// Resumes the broadcast of the node or certificate of this validator in the current round
+    /// that had not aggregated before the last shutdown, from the acks it had received, and drops
+    /// the records of earlier rounds.
+    fn resume_pending_broadcasts(&mut self) {
+        if let Err(e) = self
+            .reliable_broadcast
+            .clear_pending_broadcasts_below(self.current_round)
+        {
+            error!(error = ?e, "failed to clear pending broadcasts");
         }
+        let pending_broadcasts = match self.reliable_broadcast.pending_broadcasts() {
+            Ok(pending_broadcasts) => pending_broadcasts,
+            Err(e) => {
+                error!(error = ?e, "failed to read pending broadcasts");
+                return;
+            },
+        };
+        for message in pending_broadcasts {
+            if message.round() != Some(self.current_round) {
+                continue;
+            }
+            match message {
+                DAGMessage::NodeMsg(node) if *node.author() == self.author => {
+                    self.broadcast_node(node)
+                },
+                DAGMessage::NodeCertificateMsg(certificate)
+                    if *certificate.metadata().author() == self.author =>
+                {
+                    self.broadcast_certificate(certificate)
+                },
+                _ => {},
+            }
+        }
+    }
+
+    /// Adapts the round timeout to the time the rounds take to gather a quorum, starting from the
+    /// configured round timeout.
+    pub fn with_adaptive_round_timeout(mut self, config: AdaptiveTimeoutConfig) -> Self {
+        self.adaptive_timeout = Some(AdaptiveRoundTimeout::new(self.round_timeout, config));
+        self
+    }
+
+    /// Adapts the number of transactions pulled into each node to the backpressure and the
+    /// round latency.
+    pub fn with_adaptive_batch_size(mut self, config: AdaptiveBatchConfig) -> Self {
+        self.adaptive_batch_size = Some(AdaptiveBatchSize::new(config));
+        self
+    }
+
+    /// Reports the fetches of the given fetcher in the DAG health.
+    pub fn with_pending_fetches(mut self, pending_fetches: PendingFetches) -> Self {
+        self.pending_fetches = Some(pending_fetches);
+        self
+    }
+
+    /// Reports the depth of the queue of the given fetcher in the DAG health.
+    pub fn with_fetch_queue_depth(mut self, queue_depth: QueueDepth) -> Self {
+        self.fetch_queue_depth = Some(queue_depth);
+        self
+    }
+
+    /// Reports the depth of the queue of the given network handler in the DAG health.
+    pub fn with_handler_queue_depth(mut self, queue_depth: QueueDepth) -> Self {
+        self.handler_queue_depth = Some(queue_depth);
+        self
+    }
+
+    pub fn round_timeout(&self) -> Duration {
+        self.round_timeout
     }
 
     pub fn add_node(&mut self, node: CertifiedNode) -> anyhow::Result<()> {
         let mut dag_writer = self.dag.write();
         let round = node.metadata().round();
+        self.highest_seen_round = max(self.highest_seen_round, round);
         if dag_writer.all_exists(
             node.parents()
                 .iter()
                 .map(|certificate| certificate.metadata().digest()),
         ) {
             dag_writer.add_node(node)?;
+            drop(dag_writer);
             if self.current_round == round {
-                let maybe_strong_links = dag_writer
-                    .get_strong_links_for_round(self.current_round, &self.epoch_state.verifier);
-                drop(dag_writer);
-                if let Some(strong_links) = maybe_strong_links {
-                    self.enter_new_round(strong_links);
-                }
+                self.try_advance_round();
             }
+        } else {
+            // TODO: handle fetching missing dependencies
+            dag_writer.add_orphan(node)?;
         }
-        // TODO: handle fetching missing dependencies
         Ok(())
     }
 
-    pub fn enter_new_round(&mut self, strong_links: Vec<NodeCertificate>) {
-        // TODO: support pulling payload
-        let payload = Payload::empty(false);
+    /// Waits until the DAG holds enough nodes of the current round, including the ones that
+    /// didn't go through add_node such as fetched nodes, and enters the next round.
+    pub async fn wait_for_round_progress(&mut self) {
+        let node_added = self.dag.read().node_added();
+        loop {
+            let notified = node_added.notified();
+            if self.try_advance_round() {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    /// Enters the next round if the current round has enough strong links.
+    fn try_advance_round(&mut self) -> bool {
+        let dag_reader = self.dag.read();
+        let strong_links = match dag_reader
+            .get_strong_links_for_round(self.current_round, &self.epoch_state.verifier)
+        {
+            Some(strong_links) => strong_links,
+            None => return false,
+        };
+        if let Some(anchor) = self
+            .anchor_selector
+            .select_anchor(self.current_round, &dag_reader)
+        {
+            self.uncommitted_anchors.insert(self.current_round, anchor);
+        }
+        drop(dag_reader);
+        self.record_round_latency();
+        self.try_commit_anchors();
+        self.enter_new_round(strong_links, None);
+        true
+    }
+
+    /// Commits the highest anchor the commit rule commits directly, along with the earlier
+    /// anchors it has a path to. Every validator that commits the anchor walks back through the
+    /// same history, so they all commit the same anchors, in round order, each ordering its own
+    /// causal history.
+    fn try_commit_anchors(&mut self) {
+        let mut dag = self.dag.write();
+        let anchor = match self
+            .uncommitted_anchors
+            .values()
+            .rev()
+            .find(|anchor| self.commit_rule.is_committed(anchor, &dag))
+        {
+            Some(anchor) => anchor.clone(),
+            None => return,
+        };
+        let anchor_round = anchor.metadata().round();
+        let mut history = dag.causal_history(&anchor);
+        let mut committed_anchors = vec![anchor];
+        let lowest_round = max(self.committed_anchor_round + 1, dag.lowest_round());
+        for round in (lowest_round..anchor_round).rev() {
+            let author = match self.anchor_selector.anchor_author(round) {
+                Some(author) => author,
+                None => continue,
+            };
+            if let Some(prev_anchor) = history
+                .iter()
+                .find(|node| {
+                    node.metadata().round() == round && *node.metadata().author() == author
+                })
+                .cloned()
+            {
+                history = dag.causal_history(&prev_anchor);
+                committed_anchors.push(prev_anchor);
+            }
+        }
+        for anchor in committed_anchors.iter().rev() {
+            let ordered_nodes = self.commit_rule.order_history(anchor, &dag);
+            if let Err(e) = self.ordered_nodes_tx.try_send(ordered_nodes) {
+                error!(error = ?e, "failed to send ordered nodes");
+            }
+        }
+        self.committed_anchor_round = anchor_round;
+        self.uncommitted_anchors = self.uncommitted_anchors.split_off(&(anchor_round + 1));
+        dag.set_committed_round(anchor_round);
+    }
+
+    fn record_round_latency(&mut self) {
+        let latency = self
+            .time_service
+            .get_current_timestamp()
+            .saturating_sub(self.round_start);
+        if let Some(adaptive_timeout) = &mut self.adaptive_timeout {
+            adaptive_timeout.record_round_latency(latency);
+            self.round_timeout = adaptive_timeout.timeout();
+        }
+        let backpressure_level = self.backpressure_level();
+        if let Some(adaptive_batch_size) = &mut self.adaptive_batch_size {
+            adaptive_batch_size.record_round(backpressure_level, latency);
+        }
+    }
+
+    /// Derived from how full the DAG is and how many rounds the DAG is behind the highest round
+    /// seen from peers, i.e. how much there is left to fetch.
+    pub fn backpressure_level(&self) -> BackpressureLevel {
+        let occupancy = self.dag.read().occupancy();
+        let round_lag = self.highest_seen_round.saturating_sub(self.current_round);
+        if occupancy >= HIGH_BACKPRESSURE_OCCUPANCY || round_lag >= HIGH_BACKPRESSURE_ROUND_LAG {
+            BackpressureLevel::High
+        } else if occupancy >= MODERATE_BACKPRESSURE_OCCUPANCY
+            || round_lag >= MODERATE_BACKPRESSURE_ROUND_LAG
+        {
+            BackpressureLevel::Moderate
+        } else {
+            BackpressureLevel::None
+        }
+    }
+
+    pub fn dag_health(&self) -> DagHealth {
+        let (lowest_round, highest_round, num_nodes) = {
+            let dag_reader = self.dag.read();
+            let (lowest_round, highest_round) = dag_reader.rounds_range();
+            (lowest_round, highest_round, dag_reader.num_nodes())
+        };
+        let broadcast_backlog = match self.reliable_broadcast.pending_broadcasts() {
+            Ok(pending_broadcasts) => pending_broadcasts.len(),
+            Err(e) => {
+                error!(error = ?e, "failed to read pending broadcasts");
+                0
+            },
+        };
+        DagHealth {
+            current_round: self.current_round,
+            lowest_round,
+            highest_round,
+            num_nodes,
+            pending_fetches: self
+                .pending_fetches
+                .as_ref()
+                .map_or(0, |pending_fetches| pending_fetches.len()),
+            backpressure_level: self.backpressure_level(),
+            broadcast_backlog,
+            handler_queue_depth: self
+                .handler_queue_depth
+                .as_ref()
+                .map_or(0, |queue_depth| queue_depth.get()),
+            fetch_queue_depth: self
+                .fetch_queue_depth
+                .as_ref()
+                .map_or(0, |queue_depth| queue_depth.get()),
+            outstanding_broadcasts: self.reliable_broadcast.num_outstanding_broadcasts(),
+        }
+    }
+
+    pub fn uncommitted_anchors(&self) -> impl Iterator<Item = &Arc<CertifiedNode>> {
+        self.uncommitted_anchors.values()
+    }
+
+    /// Enters the next round with a node on the given parents, along with the timeout
+    /// certificate of the current round when it timed out short of 2f+1 parents.
+    pub fn enter_new_round(
+        &mut self,
+        strong_links: Vec<NodeCertificate>,
+        timeout_certificate: Option<RoundTimeoutCertificate>,
+    ) {
         // TODO: need to wait to pass median of parents timestamp
         let timestamp = self.time_service.get_current_timestamp();
+        let payload = self.pull_payload();
         self.current_round += 1;
-        let new_node = Node::new(
-            self.epoch_state.epoch,
-            self.current_round,
-            self.author,
-            timestamp.as_micros() as u64,
-            payload,
-            strong_links,
-        );
-        self.broadcast_node(new_node);
+        self.round_start = timestamp;
+        self.timeouts_by_round = self.timeouts_by_round.split_off(&self.current_round);
+        self.reliable_broadcast
+            .set_current_round(self.current_round);
+        self.dag.write().gc_orphans();
+        counters::DAG_CURRENT_ROUND.set(self.current_round as i64);
+        self.reset_round_timer();
+        let (epoch, round, author) = (self.epoch_state.epoch, self.current_round, self.author);
+        let rb = self.reliable_broadcast.clone();
+        let epoch_state = self.epoch_state.clone();
+        let task = async move {
+            let mut new_node = Node::new(
+                epoch,
+                round,
+                author,
+                timestamp.as_micros() as u64,
+                payload.await,
+                strong_links,
+            );
+            if let Some(certificate) = timeout_certificate {
+                new_node = new_node.with_timeout_certificate(certificate);
+            }
+            Self::node_broadcast(rb, epoch_state, new_node).await
+        };
+        self.spawn_broadcast(round, task);
+    }
+
+    /// Pulls as many transactions as the adaptive batch size allows for the node of the next
+    /// round, leaving out the ones in the uncommitted nodes of this validator. Without an
+    /// adaptive batch size, the node goes out with an empty payload.
+    fn pull_payload(&self) -> BoxFuture<'static, Payload> {
+        let (max_items, max_bytes) = match &self.adaptive_batch_size {
+            Some(adaptive_batch_size) => (
+                adaptive_batch_size.batch_size(),
+                adaptive_batch_size.max_batch_bytes(),
+            ),
+            None => return future::ready(Payload::empty(false)).boxed(),
+        };
+        let exclude = {
+            let dag_reader = self.dag.read();
+            let uncommitted_nodes: Vec<_> = (self.committed_anchor_round + 1..=self.current_round)
+                .flat_map(|round| dag_reader.nodes_at_round(round))
+                .filter(|node| *node.author() == self.author)
+                .collect();
+            let payloads: Vec<_> = uncommitted_nodes
+                .iter()
+                .map(|node| node.payload())
+                .collect();
+            PayloadFilter::from(&payloads)
+        };
+        let payload_client = self.payload_client.clone();
+        async move {
+            // the node goes out with the transactions available right away
+            payload_client
+                .pull_payload(
+                    Duration::ZERO,
+                    max_items,
+                    max_bytes,
+                    exclude,
+                    future::ready(()).boxed(),
+                    false,
+                    0,
+                    0.0,
+                )
+                .await
+                .unwrap_or_else(|e| {
+                    error!(error = ?e, "failed to pull payload");
+                    Payload::empty(false)
+                })
+        }
+        .boxed()
     }
 
     pub fn broadcast_node(&mut self, node: Node) {
-        let rb = self.reliable_broadcast.clone();
-        let (abort_handle, abort_registration) = AbortHandle::new_pair();
-        let signature_builder =
-            SignatureBuilder::new(node.metadata().clone(), self.epoch_state.clone());
-        let cert_ack_set = CertificateAckState::new(self.epoch_state.verifier.len());
+        let round = node.metadata().round();
+        let task = Self::node_broadcast(
+            self.reliable_broadcast.clone(),
+            self.epoch_state.clone(),
+            node,
+        );
+        self.spawn_broadcast(round, task);
+    }
+
+    /// Broadcasts the node, then the certificate it aggregates.
+    fn node_broadcast(
+        rb: Arc<ReliableBroadcast>,
+        epoch_state: Arc<EpochState>,
+        node: Node,
+    ) -> impl Future<Output = ()> + Send + 'static {
+        let signature_builder = SignatureBuilder::new(node.metadata().clone(), epoch_state.clone());
+        let cert_ack_set = CertificateAckState::new(*node.metadata().digest(), epoch_state);
+        rb.clone()
+            .broadcast_with_expiry(node, signature_builder)
+            .then(move |result| async move {
+                match result {
+                    Ok(Some(certificate)) => {
+                        if let Err(e) = rb.broadcast_with_expiry(certificate, cert_ack_set).await {
+                            error!(error = ?e, "failed to broadcast certificate");
+                        }
+                    },
+                    Ok(None) => (),
+                    Err(e) => error!(error = ?e, "failed to broadcast node"),
+                }
+            })
+    }
+
+    fn broadcast_certificate(&mut self, certificate: NodeCertificate) {
+        let round = certificate.metadata().round();
+        let cert_ack_set =
+            CertificateAckState::new(*certificate.metadata().digest(), self.epoch_state.clone());
         let task = self
             .reliable_broadcast
-            .broadcast(node, signature_builder)
-            .then(move |certificate| rb.broadcast(certificate, cert_ack_set));
+            .broadcast_with_expiry(certificate, cert_ack_set)
+            .map(|result| {
+                if let Err(e) = result {
+                    error!(error = ?e, "failed to broadcast certificate");
+                }
+            });
+        self.spawn_broadcast(round, task);
+    }
+
+    /// Replaces the broadcast in progress with the given one of a new round.
+    fn spawn_broadcast(&mut self, round: Round, task: impl Future<Output = ()> + Send + 'static) {
+        if let Some(prev_handle) = self.rb_abort_handle.take() {
+            prev_handle.abort();
+        }
+        // broadcasts of earlier rounds are superseded by this one
+        if let Err(e) = self
+            .reliable_broadcast
+            .clear_pending_broadcasts_below(round)
+        {
+            error!(error = ?e, "failed to clear pending broadcasts");
+        }
+        let (abort_handle, abort_registration) = AbortHandle::new_pair();
         tokio::spawn(Abortable::new(task, abort_registration));
-        if let Some(prev_handle) = self.rb_abort_handle.replace(abort_handle) {
+        self.rb_abort_handle = Some(abort_handle);
+    }
+
+    fn reset_round_timer(&mut self) {
+        if let Some(handle) = self.round_timer_abort_handle.take() {
+            handle.abort();
+        }
+        let task = SendTask::make(self.timeout_tx.clone(), self.current_round);
+        self.round_timer_abort_handle = Some(self.time_service.run_after(self.round_timeout, task));
+    }
+
+    /// Adds the timeout of a peer, and enters the next round if it completes a quorum of
+    /// timeouts of the current round. Timeouts of past rounds are ignored, and timeouts more than
+    /// one round ahead are rejected so that peers can't grow the pending timeouts. The network
+    /// handler sends the timeouts of the peers, see `NetworkHandler::with_round_timeout_sender`.
+    pub fn add_round_timeout(
+        &mut self,
+        timeout: RoundTimeout,
+    ) -> anyhow::Result<Option<RoundTimeoutCertificate>> {
+        ensure!(
+            timeout.epoch() == self.epoch_state.epoch,
+            "round timeout from a different epoch"
+        );
+        if timeout.round() < self.current_round {
+            return Ok(None);
+        }
+        ensure!(
+            timeout.round() <= self.current_round + 1,
+            "round timeout too far ahead"
+        );
+        timeout.verify(&self.epoch_state.verifier)?;
+        self.timeouts_by_round
+            .entry(timeout.round())
+            .or_insert_with(PartialSignatures::empty)
+            .add_signature(*timeout.author(), timeout.signature().clone());
+        self.try_certify_round_timeout()
+    }
+
+    /// Called when the timer of the round expires. Signs and broadcasts the timeout of this
+    /// validator, and returns a timeout certificate once a quorum timed out on the current round,
+    /// after entering the next round. Otherwise re-arms the timer.
+    pub fn process_round_timeout(
+        &mut self,
+        round: Round,
+    ) -> anyhow::Result<Option<RoundTimeoutCertificate>> {
+        // the round advanced before the timer expired
+        if round != self.current_round {
+            return Ok(None);
+        }
+        let timed_out = self
+            .timeouts_by_round
+            .get(&round)
+            .map_or(false, |timeouts| {
+                timeouts.signatures().contains_key(&self.author)
+            });
+        if !timed_out {
+            let timeout = RoundTimeout::new(self.epoch_state.epoch, round, &self.signer)?;
+            self.timeouts_by_round
+                .entry(round)
+                .or_insert_with(PartialSignatures::empty)
+                .add_signature(self.author, timeout.signature().clone());
+            self.broadcast_round_timeout(timeout);
+        }
+        let maybe_certificate = self.try_certify_round_timeout()?;
+        if maybe_certificate.is_none() {
+            self.reset_round_timer();
+        }
+        Ok(maybe_certificate)
+    }
+
+    fn broadcast_round_timeout(&mut self, timeout: RoundTimeout) {
+        let delivery = RoundTimeoutDelivery::new(timeout.round(), self.epoch_state.clone());
+        let task = self
+            .reliable_broadcast
+            .broadcast_with_expiry(timeout, delivery)
+            .map(|result| {
+                if let Err(e) = result {
+                    error!(error = ?e, "failed to broadcast round timeout");
+                }
+            });
+        if let Some(prev_handle) = self.timeout_abort_handle.take() {
             prev_handle.abort();
         }
+        let (abort_handle, abort_registration) = AbortHandle::new_pair();
+        tokio::spawn(Abortable::new(task, abort_registration));
+        self.timeout_abort_handle = Some(abort_handle);
+    }
+
+    /// Enters the next round if a quorum timed out on the current round, with the nodes of the
+    /// current round that made it to the DAG as parents. As they may fall short of 2f+1, the new
+    /// node carries the timeout certificate for the peers to accept it.
+    fn try_certify_round_timeout(&mut self) -> anyhow::Result<Option<RoundTimeoutCertificate>> {
+        let round = self.current_round;
+        let verifier = &self.epoch_state.verifier;
+        let timeouts = match self.timeouts_by_round.get(&round) {
+            Some(timeouts)
+                if verifier
+                    .check_voting_power(timeouts.signatures().keys())
+                    .is_ok() =>
+            {
+                timeouts
+            },
+            _ => return Ok(None),
+        };
+        let certificate = RoundTimeoutCertificate::new(
+            self.epoch_state.epoch,
+            round,
+            verifier.aggregate_signatures(timeouts)?,
+        );
+        let parents = self
+            .dag
+            .read()
+            .nodes_at_round(round)
+            .iter()
+            .map(|node| node.certificate().clone())
+            .collect();
+        self.enter_new_round(parents, Some(certificate.clone()));
+        Ok(Some(certificate))
     }
 }
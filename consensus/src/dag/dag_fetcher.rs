@@ -3,25 +3,52 @@
 
 use crate::{
     dag::{
-        dag_network::DAGNetworkSender,
-        dag_store::Dag,
-        types::{CertifiedNode, DAGMessage, FetchRequest, FetchResponse, Node},
+        counters::{self, QueueDepth},
+        dag_network::{with_timeout, DAGNetworkSender, RpcHandler},
+        dag_store::{Dag, DagStoreError},
+        types::{
+            BatchFetchRequest, BatchFetchResponse, BatchFetchTarget, CertifiedNode, DAGMessage,
+            FetchRequest, Node,
+        },
     },
     network::TConsensusMsg,
+    util::time_service::TimeService,
 };
-use aptos_consensus_types::common::Author;
-use aptos_infallible::RwLock;
-use aptos_logger::error;
+use anyhow::{bail, ensure};
+use aptos_consensus_types::common::{Author, Round};
+use aptos_crypto::HashValue;
+use aptos_infallible::{Mutex, RwLock};
+use aptos_logger::{debug, error};
+use aptos_network::constants::MAX_MESSAGE_SIZE;
 use aptos_types::epoch_state::EpochState;
-use std::{sync::Arc, time::Duration};
+use async_trait::async_trait;
+use rand::{seq::SliceRandom, Rng};
+use std::{
+    cmp::{max, min},
+    collections::{hash_map::Entry, BTreeMap, HashMap, HashSet},
+    sync::Arc,
+    time::Duration,
+};
+use thiserror::Error as ThisError;
 use tokio::sync::{
     mpsc::{Receiver, Sender},
-    oneshot,
+    oneshot, OwnedSemaphorePermit, Semaphore,
 };
 
-enum FetchCallback {
-    Node(Node, oneshot::Sender<Node>),
-    CertifiedNode(CertifiedNode, oneshot::Sender<CertifiedNode>),
+#[derive(ThisError, Clone, Debug, PartialEq, Eq)]
+pub enum FetchError {
+    /// No peer served the node within the retry budget, it is likely pruned everywhere and
+    /// has to be caught up through state sync.
+    #[error("retry budget exhausted fetching node {0}")]
+    RetryBudgetExhausted(HashValue),
+}
+
+pub enum FetchCallback {
+    Node(Node, oneshot::Sender<Result<Node, FetchError>>),
+    CertifiedNode(
+        CertifiedNode,
+        oneshot::Sender<Result<CertifiedNode, FetchError>>,
+    ),
 }
 
 impl FetchCallback {
@@ -32,10 +59,12 @@ impl FetchCallback {
         }
     }
 
-    pub fn notify(self) {
+    pub fn notify(self, result: Result<(), FetchError>) {
         if match self {
-            FetchCallback::Node(node, sender) => sender.send(node).map_err(|_| ()),
-            FetchCallback::CertifiedNode(node, sender) => sender.send(node).map_err(|_| ()),
+            FetchCallback::Node(node, sender) => sender.send(result.map(|_| node)).map_err(|_| ()),
+            FetchCallback::CertifiedNode(node, sender) => {
+                sender.send(result.map(|_| node)).map_err(|_| ())
+            },
         }
         .is_err()
         {
@@ -44,11 +73,166 @@ impl FetchCallback {
     }
 }
 
-struct DagFetcher {
+/// Handle on the targets a fetcher has queued or in flight, for reporting.
+#[derive(Clone)]
+pub struct PendingFetches(Arc<Mutex<HashMap<HashValue, Vec<FetchCallback>>>>);
+
+impl PendingFetches {
+    pub fn len(&self) -> usize {
+        self.0.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.lock().is_empty()
+    }
+}
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(1);
+
+pub const DEFAULT_FETCH_RETRY_BUDGET: usize = 10;
+
+/// Rpcs spent on each target node across fetches, so that a node no peer can serve is given up
+/// on instead of being fetched over and over.
+struct RetryBudget {
+    max_attempts: usize,
+    /// Round and attempts spent of the targets that failed to fetch so far
+    spent: HashMap<HashValue, (Round, usize)>,
+}
+
+impl RetryBudget {
+    fn new(max_attempts: usize) -> Self {
+        Self {
+            max_attempts,
+            spent: HashMap::new(),
+        }
+    }
+
+    fn remaining(&self, digest: &HashValue) -> usize {
+        let spent = self.spent.get(digest).map_or(0, |(_, attempts)| *attempts);
+        self.max_attempts.saturating_sub(spent)
+    }
+
+    /// Records failed attempts on the target, returns true once its budget is exhausted.
+    fn spend(&mut self, digest: HashValue, round: Round, attempts: usize) -> bool {
+        self.spent.entry(digest).or_insert((round, 0)).1 += attempts;
+        self.remaining(&digest) == 0
+    }
+
+    fn reset(&mut self, digest: &HashValue) {
+        self.spent.remove(digest);
+    }
+
+    fn prune(&mut self, lowest_round: Round) {
+        self.spent.retain(|_, (round, _)| *round >= lowest_round);
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PeerScoreConfig {
+    /// Weight of the latest fetch outcome in the score of a peer
+    pub smoothing: f64,
+    /// Time after which a score has moved half way back to the initial score
+    pub half_life: Duration,
+    /// Probability of trying a random peer first instead of the best scored one
+    pub exploration_rate: f64,
+}
+
+impl Default for PeerScoreConfig {
+    fn default() -> Self {
+        Self {
+            smoothing: 0.3,
+            half_life: Duration::from_secs(60),
+            exploration_rate: 0.1,
+        }
+    }
+}
+
+struct PeerScore {
+    score: f64,
+    /// Timestamp of the time service
+    updated_at: Duration,
+}
+
+/// Tracks how responsive each peer is to fetches. A peer scores close to 1 when it answers fast,
+/// close to 0 when it is slow and below 0 when it fails or times out. Peers start at the best
+/// score so that unknown peers get tried, and old outcomes fade over time.
+pub struct PeerScores {
+    config: PeerScoreConfig,
+    time_service: Arc<dyn TimeService>,
+    scores: HashMap<Author, PeerScore>,
+}
+
+impl PeerScores {
+    const INITIAL_SCORE: f64 = 1.0;
+
+    pub fn new(config: PeerScoreConfig, time_service: Arc<dyn TimeService>) -> Self {
+        Self {
+            config,
+            time_service,
+            scores: HashMap::new(),
+        }
+    }
+
+    pub fn score(&self, peer: &Author) -> f64 {
+        self.scores
+            .get(peer)
+            .map_or(Self::INITIAL_SCORE, |score| self.decayed(score))
+    }
+
+    fn decayed(&self, score: &PeerScore) -> f64 {
+        let elapsed = self
+            .time_service
+            .get_current_timestamp()
+            .saturating_sub(score.updated_at);
+        let half_lives = elapsed.as_secs_f64() / self.config.half_life.as_secs_f64();
+        Self::INITIAL_SCORE + (score.score - Self::INITIAL_SCORE) * 0.5f64.powf(half_lives)
+    }
+
+    pub fn record_success(&mut self, peer: Author, latency: Duration, timeout: Duration) {
+        let outcome = 1.0 - (latency.as_secs_f64() / timeout.as_secs_f64()).min(1.0);
+        self.record(peer, outcome);
+    }
+
+    pub fn record_failure(&mut self, peer: Author) {
+        self.record(peer, -1.0);
+    }
+
+    fn record(&mut self, peer: Author, outcome: f64) {
+        let score = self.score(&peer);
+        self.scores.insert(peer, PeerScore {
+            score: score + self.config.smoothing * (outcome - score),
+            updated_at: self.time_service.get_current_timestamp(),
+        });
+    }
+
+    /// Orders the peers by descending score, occasionally moving a random peer to the front to
+    /// find out whether it got better.
+    pub fn order_peers(&self, mut peers: Vec<Author>) -> Vec<Author> {
+        peers.sort_by(|a, b| self.score(b).total_cmp(&self.score(a)));
+        let mut rng = rand::thread_rng();
+        if peers.len() > 1 && rng.gen_bool(self.config.exploration_rate) {
+            if let Some(peer) = peers.choose(&mut rng).copied() {
+                peers.retain(|p| *p != peer);
+                peers.insert(0, peer);
+            }
+        }
+        peers
+    }
+}
+
+pub struct DagFetcher {
     epoch_state: Arc<EpochState>,
     network: Arc<dyn DAGNetworkSender>,
     dag: Arc<RwLock<Dag>>,
     request_rx: Receiver<(FetchRequest, FetchCallback)>,
+    max_concurrent_fetches: usize,
+    /// Callbacks waiting on the fetch of each target node, a request for a target that is
+    /// already queued or being fetched is attached here instead of issuing another rpc.
+    in_flight: Arc<Mutex<HashMap<HashValue, Vec<FetchCallback>>>>,
+    peer_scores: Arc<Mutex<PeerScores>>,
+    retry_budget: Arc<Mutex<RetryBudget>>,
+    queue_depth: QueueDepth,
+    time_service: Arc<dyn TimeService>,
 }
 
 impl DagFetcher {
@@ -56,6 +240,8 @@ impl DagFetcher {
         epoch_state: Arc<EpochState>,
         network: Arc<dyn DAGNetworkSender>,
         dag: Arc<RwLock<Dag>>,
+        time_service: Arc<dyn TimeService>,
+        max_concurrent_fetches: usize,
     ) -> (Self, Sender<(FetchRequest, FetchCallback)>) {
         let (request_tx, request_rx) = tokio::sync::mpsc::channel(16);
         (
@@ -64,35 +250,322 @@ impl DagFetcher {
                 network,
                 dag,
                 request_rx,
+                max_concurrent_fetches,
+                in_flight: Arc::new(Mutex::new(HashMap::new())),
+                peer_scores: Arc::new(Mutex::new(PeerScores::new(
+                    PeerScoreConfig::default(),
+                    time_service.clone(),
+                ))),
+                retry_budget: Arc::new(Mutex::new(RetryBudget::new(DEFAULT_FETCH_RETRY_BUDGET))),
+                queue_depth: QueueDepth::new(counters::DAG_FETCH_QUEUE_DEPTH.clone()),
+                time_service,
             },
             request_tx,
         )
     }
 
+    /// Sets how many rpcs, across all peers and fetches, are spent on a node before its fetch
+    /// fails with `FetchError::RetryBudgetExhausted`.
+    pub fn with_retry_budget(self, max_attempts: usize) -> Self {
+        *self.retry_budget.lock() = RetryBudget::new(max_attempts);
+        self
+    }
+
+    pub fn pending_fetches(&self) -> PendingFetches {
+        PendingFetches(self.in_flight.clone())
+    }
+
+    /// Fetches waiting for one of the max_concurrent_fetches slots.
+    pub fn queue_depth(&self) -> QueueDepth {
+        self.queue_depth.clone()
+    }
+
     pub async fn start(mut self) {
-        while let Some((request, callback)) = self.request_rx.recv().await {
-            let responders =
-                callback.responders(&self.epoch_state.verifier.get_ordered_account_addresses());
-            let network_request = DAGMessage::from(request.clone()).into_network_message();
-            if let Ok(response) = self
-                .network
-                .send_rpc_with_fallbacks(responders, network_request, Duration::from_secs(1))
-                .await
-                .and_then(DAGMessage::try_from)
-                .and_then(FetchResponse::try_from)
-                .and_then(|response| response.verify(&request, &self.epoch_state.verifier))
-            {
-                // TODO: support chunk response or fallback to state sync
-                let mut dag_writer = self.dag.write();
-                for rounds in response.certified_nodes() {
-                    for node in rounds {
-                        if let Err(e) = dag_writer.add_node(node) {
-                            error!("Failed to add node {}", e);
+        let semaphore = Arc::new(Semaphore::new(self.max_concurrent_fetches));
+        // lowest round first so the commit frontier advances as early as possible, requests of
+        // the same round are served in arrival order
+        let mut queue: BTreeMap<(Round, u64), (FetchRequest, Vec<Author>)> = BTreeMap::new();
+        let mut sequence = 0;
+        loop {
+            tokio::select! {
+                Some((request, callback)) = self.request_rx.recv() => {
+                    let digest = *request.target().digest();
+                    let lowest_round = self.dag.read().lowest_round();
+                    {
+                        let mut retry_budget = self.retry_budget.lock();
+                        retry_budget.prune(lowest_round);
+                        if retry_budget.remaining(&digest) == 0 {
+                            callback.notify(Err(FetchError::RetryBudgetExhausted(digest)));
+                            continue;
                         }
                     }
+                    let responders = callback
+                        .responders(&self.epoch_state.verifier.get_ordered_account_addresses());
+                    match self.in_flight.lock().entry(digest) {
+                        Entry::Occupied(mut entry) => {
+                            entry.get_mut().push(callback);
+                            continue;
+                        },
+                        Entry::Vacant(entry) => {
+                            entry.insert(vec![callback]);
+                        },
+                    }
+                    queue.insert((request.target().round(), sequence), (request, responders));
+                    sequence += 1;
+                    self.queue_depth.set(queue.len());
+                },
+                // at most max_concurrent_fetches are in flight, the rest wait in the queue
+                permit = semaphore.clone().acquire_owned(), if !queue.is_empty() => {
+                    let permit = permit.expect("semaphore is never closed");
+                    let (_, (request, responders)) =
+                        queue.pop_first().expect("queue is not empty");
+                    self.queue_depth.set(queue.len());
+                    self.spawn_fetch(request, responders, permit);
+                },
+                else => break,
+            }
+        }
+    }
+
+    fn spawn_fetch(
+        &self,
+        request: FetchRequest,
+        responders: Vec<Author>,
+        permit: OwnedSemaphorePermit,
+    ) {
+        let digest = *request.target().digest();
+        let round = request.target().round();
+        let epoch_state = self.epoch_state.clone();
+        let network = self.network.clone();
+        let dag = self.dag.clone();
+        let in_flight = self.in_flight.clone();
+        let peer_scores = self.peer_scores.clone();
+        let time_service = self.time_service.clone();
+        let retry_budget = self.retry_budget.clone();
+        let max_attempts = retry_budget.lock().remaining(&digest);
+        let attempts = min(responders.len(), max_attempts);
+        tokio::spawn(async move {
+            let fetched = Self::fetch(
+                epoch_state,
+                network,
+                dag.clone(),
+                peer_scores,
+                time_service,
+                request,
+                responders,
+                max_attempts,
+            )
+            .await;
+            drop(permit);
+            if !fetched {
+                dag.write().orphan_fetch_failed(&digest);
+            }
+            // the budget is settled before the callbacks are released so that a new request
+            // for the target sees it
+            let result = if fetched {
+                retry_budget.lock().reset(&digest);
+                Some(Ok(()))
+            } else if retry_budget.lock().spend(digest, round, attempts) {
+                Some(Err(FetchError::RetryBudgetExhausted(digest)))
+            } else {
+                None
+            };
+            let callbacks = in_flight.lock().remove(&digest).unwrap_or_default();
+            if let Some(result) = result {
+                for callback in callbacks {
+                    callback.notify(result.clone());
+                }
+            }
+        });
+    }
+
+    async fn fetch(
+        epoch_state: Arc<EpochState>,
+        network: Arc<dyn DAGNetworkSender>,
+        dag: Arc<RwLock<Dag>>,
+        peer_scores: Arc<Mutex<PeerScores>>,
+        time_service: Arc<dyn TimeService>,
+        request: FetchRequest,
+        responders: Vec<Author>,
+        max_attempts: usize,
+    ) -> bool {
+        // the peers are tried best scored first, each failure falls back to the next one
+        let responders = peer_scores.lock().order_peers(responders);
+        for responder in responders.into_iter().take(max_attempts) {
+            let start = time_service.get_current_timestamp();
+            match Self::fetch_from(responder, &epoch_state, network.as_ref(), &dag, &request).await
+            {
+                Ok(()) => {
+                    let latency = time_service.get_current_timestamp().saturating_sub(start);
+                    peer_scores
+                        .lock()
+                        .record_success(responder, latency, FETCH_TIMEOUT);
+                    return true;
+                },
+                Err(e) => {
+                    debug!(error = ?e, "fetch from {} failed", responder);
+                    peer_scores.lock().record_failure(responder);
+                },
+            }
+        }
+        false
+    }
+
+    /// Fetches the nodes of the rounds below the target from the responder in batches. A
+    /// response cut short by the message size limit is followed by a request for the rest,
+    /// starting at the round of its last node as that round may have been cut in the middle.
+    async fn fetch_from(
+        responder: Author,
+        epoch_state: &EpochState,
+        network: &dyn DAGNetworkSender,
+        dag: &RwLock<Dag>,
+        request: &FetchRequest,
+    ) -> anyhow::Result<()> {
+        let end_round = request.target().round().saturating_sub(1);
+        let mut start_round = request.start_round();
+        let mut received = HashSet::new();
+        loop {
+            let batch_request = BatchFetchRequest::new(
+                request.target().epoch(),
+                BatchFetchTarget::Rounds(start_round, end_round),
+            );
+            let network_request = DAGMessage::from(batch_request.clone());
+            counters::DAG_MESSAGES_SENT
+                .with_label_values(&[network_request.name()])
+                .inc();
+            let response = with_timeout(
+                FETCH_TIMEOUT,
+                network.send_rpc(
+                    responder,
+                    network_request.into_network_message(),
+                    FETCH_TIMEOUT,
+                ),
+            )
+            .await
+            .and_then(DAGMessage::try_from)
+            .and_then(|response| {
+                counters::DAG_MESSAGES_RECEIVED
+                    .with_label_values(&[response.name()])
+                    .inc();
+                BatchFetchResponse::try_from(response)
+            })
+            .and_then(|response| response.verify(&batch_request, &epoch_state.verifier))?;
+
+            let complete = response.is_complete();
+            let nodes = response.certified_nodes();
+            let last_round = nodes.last().map(|node| node.metadata().round());
+            let mut progress = false;
+            {
+                let mut dag_writer = dag.write();
+                for node in nodes {
+                    // the rest of a cut round comes along with the nodes already received
+                    if !received.insert(node.digest()) {
+                        continue;
+                    }
+                    progress = true;
+                    if dag_writer.exists(&node.digest()) {
+                        continue;
+                    }
+                    match dag_writer.add_node(node) {
+                        Ok(()) => {},
+                        Err(DagStoreError::Equivocation(equivocation)) => {
+                            dag_writer.report_equivocation(*equivocation)
+                        },
+                        Err(e) => error!("Failed to add node {}", e),
+                    }
                 }
-                callback.notify();
             }
+            if complete {
+                return Ok(());
+            }
+            // the response was cut short by the message size limit
+            match last_round {
+                Some(last_round) if progress => start_round = last_round,
+                _ => bail!(
+                    "batch fetch from {} made no progress at round {}",
+                    responder,
+                    start_round
+                ),
+            }
+        }
+    }
+}
+
+/// Room left in a batch response for the message envelope around the nodes.
+const BATCH_RESPONSE_OVERHEAD: usize = 1024;
+
+/// Serves batch fetch requests from the local DAG, answering with as many of the requested
+/// nodes as fit in a single message. A request whose first node alone doesn't fit fails.
+pub struct BatchFetchHandler {
+    dag: Arc<RwLock<Dag>>,
+    epoch_state: Arc<EpochState>,
+    max_message_bytes: usize,
+}
+
+impl BatchFetchHandler {
+    pub fn new(dag: Arc<RwLock<Dag>>, epoch_state: Arc<EpochState>) -> Self {
+        Self {
+            dag,
+            epoch_state,
+            max_message_bytes: MAX_MESSAGE_SIZE,
+        }
+    }
+
+    pub fn with_max_message_bytes(mut self, max_message_bytes: usize) -> Self {
+        self.max_message_bytes = max_message_bytes;
+        self
+    }
+}
+
+#[async_trait]
+impl RpcHandler for BatchFetchHandler {
+    type Request = BatchFetchRequest;
+    type Response = BatchFetchResponse;
+
+    async fn process(&mut self, request: Self::Request) -> anyhow::Result<Self::Response> {
+        ensure!(
+            request.epoch() == self.epoch_state.epoch,
+            "batch fetch request from a different epoch"
+        );
+        let dag_reader = self.dag.read();
+        let mut nodes: Vec<_> = match request.target() {
+            BatchFetchTarget::Digests(digests) => digests
+                .iter()
+                .filter_map(|digest| dag_reader.get_node(digest))
+                .collect(),
+            BatchFetchTarget::Rounds(start_round, end_round) => {
+                let (lowest_round, highest_round) = dag_reader.rounds_range();
+                (max(*start_round, lowest_round)..=min(*end_round, highest_round))
+                    .flat_map(|round| dag_reader.nodes_at_round(round))
+                    .collect()
+            },
+        };
+        drop(dag_reader);
+        nodes.sort_by_key(|node| node.metadata().round());
+
+        let budget = self
+            .max_message_bytes
+            .saturating_sub(BATCH_RESPONSE_OVERHEAD);
+        let mut size = 0;
+        let mut certified_nodes = vec![];
+        for node in &nodes {
+            size += bcs::serialized_size(node.as_ref())?;
+            if size > budget {
+                // the response can't go out without exceeding the limit
+                ensure!(
+                    !certified_nodes.is_empty(),
+                    "node {} doesn't fit in a batch fetch response",
+                    node.digest()
+                );
+                break;
+            }
+            certified_nodes.push(node.as_ref().clone());
         }
+        let complete = certified_nodes.len() == nodes.len();
+        Ok(BatchFetchResponse::new(
+            self.epoch_state.epoch,
+            certified_nodes,
+            complete,
+        ))
     }
 }
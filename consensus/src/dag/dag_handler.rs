@@ -2,59 +2,379 @@
 
 use crate::{
     dag::{
-        dag_network::RpcHandler, dag_store::Dag, reliable_broadcast::NodeBroadcastHandler,
-        types::DAGMessage,
+        anchor_selection::AnchorSelector,
+        counters::{self, QueueDepth},
+        dag_fetcher::BatchFetchHandler,
+        dag_network::{verify_sender, RpcHandler},
+        dag_store::Dag,
+        reliable_broadcast::NodeBroadcastHandler,
+        types::{DAGMessage, DAGMessageSizeError, RoundTimeout, RoundTimeoutAck},
     },
     network::{IncomingDAGRequest, TConsensusMsg},
+    network_interface::ConsensusMsg,
 };
+use anyhow::{bail, ensure};
 use aptos_channels::aptos_channel;
 use aptos_consensus_types::common::Author;
+use aptos_crypto::HashValue;
 use aptos_infallible::RwLock;
-use aptos_logger::{error, warn};
-use aptos_network::protocols::network::RpcError;
+use aptos_logger::{debug, error, warn};
+use aptos_network::{constants::MAX_MESSAGE_SIZE, protocols::network::RpcError};
+use aptos_rate_limiter::rate_limit::TokenBucketRateLimiter;
 use aptos_types::{epoch_state::EpochState, validator_signer::ValidatorSigner};
 use bytes::Bytes;
-use futures::StreamExt;
-use std::sync::Arc;
+use futures::{FutureExt, SinkExt, StreamExt};
+use lru::LruCache;
+use std::{
+    cmp::max,
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+};
+
+/// Token bucket parameters for a single kind of DAG message.
+#[derive(Clone, Copy, Debug)]
+pub struct RateLimitConfig {
+    /// Maximum burst of messages a peer may send.
+    pub bucket_size: usize,
+    /// Number of tokens refilled per second.
+    pub fill_rate: usize,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            bucket_size: 100,
+            fill_rate: 50,
+        }
+    }
+}
+
+/// Limits the rate of incoming DAG messages per peer and per message kind.
+/// Every throttled message lowers the score of its sender.
+pub struct PeerRateLimiter {
+    default_config: RateLimitConfig,
+    configs: HashMap<&'static str, RateLimitConfig>,
+    limiters: HashMap<&'static str, TokenBucketRateLimiter<Author>>,
+    peer_scores: HashMap<Author, i64>,
+}
+
+impl PeerRateLimiter {
+    pub fn new(default_config: RateLimitConfig) -> Self {
+        Self {
+            default_config,
+            configs: HashMap::new(),
+            limiters: HashMap::new(),
+            peer_scores: HashMap::new(),
+        }
+    }
 
-struct NetworkHandler {
+    /// Overrides the limits for the message kind as returned by `DAGMessage::name`.
+    pub fn with_config(mut self, kind: &'static str, config: RateLimitConfig) -> Self {
+        self.configs.insert(kind, config);
+        self
+    }
+
+    /// Returns true if the message is within the peer's rate, otherwise decrements
+    /// the peer's score and returns false.
+    pub fn allow(&mut self, peer: Author, kind: &'static str) -> bool {
+        let config = *self.configs.get(kind).unwrap_or(&self.default_config);
+        let limiter = self.limiters.entry(kind).or_insert_with(|| {
+            TokenBucketRateLimiter::new(
+                kind,
+                "dag".to_string(),
+                100,
+                config.bucket_size,
+                config.fill_rate,
+                None,
+            )
+        });
+        let allowed = limiter.bucket(peer).lock().acquire_all_tokens(1).is_ok();
+        if !allowed {
+            *self.peer_scores.entry(peer).or_default() -= 1;
+        }
+        allowed
+    }
+
+    pub fn peer_score(&self, peer: &Author) -> i64 {
+        self.peer_scores.get(peer).copied().unwrap_or_default()
+    }
+
+    /// Lowers the score of a peer that misbehaved in a way other than exceeding its rate.
+    pub fn penalize(&mut self, peer: Author, penalty: i64) {
+        *self.peer_scores.entry(peer).or_default() -= penalty;
+    }
+}
+
+/// Requests waiting to be processed. Requests carrying an anchor go first since anchors drive
+/// commits, but after anchor_burst anchors in a row a waiting regular request is let through so
+/// that regular requests aren't starved.
+pub struct AnchorPriorityQueue<T> {
+    anchors: VecDeque<T>,
+    others: VecDeque<T>,
+    anchor_burst: usize,
+    consecutive_anchors: usize,
+}
+
+impl<T> AnchorPriorityQueue<T> {
+    pub fn new(anchor_burst: usize) -> Self {
+        Self {
+            anchors: VecDeque::new(),
+            others: VecDeque::new(),
+            anchor_burst,
+            consecutive_anchors: 0,
+        }
+    }
+
+    pub fn push(&mut self, item: T, is_anchor: bool) {
+        if is_anchor {
+            self.anchors.push_back(item);
+        } else {
+            self.others.push_back(item);
+        }
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        let anchor_first = !self.anchors.is_empty()
+            && (self.others.is_empty() || self.consecutive_anchors < self.anchor_burst);
+        if anchor_first {
+            self.consecutive_anchors += 1;
+            self.anchors.pop_front()
+        } else {
+            self.consecutive_anchors = 0;
+            self.others.pop_front()
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.anchors.len() + self.others.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.anchors.is_empty() && self.others.is_empty()
+    }
+}
+
+pub struct NetworkHandler {
     dag_rpc_rx: aptos_channel::Receiver<Author, IncomingDAGRequest>,
     node_receiver: NodeBroadcastHandler,
+    fetch_handler: BatchFetchHandler,
+    rate_limiter: PeerRateLimiter,
+    max_message_bytes: usize,
+    /// Responses to recently processed messages along with the author and kind of the message,
+    /// keyed by the digest of the message payload
+    seen_messages: LruCache<HashValue, (Option<Author>, &'static str, DAGMessage)>,
+    /// Tells the requests carrying an anchor apart, without it requests are processed in order
+    anchor_selector: Option<Arc<dyn AnchorSelector>>,
+    epoch_state: Arc<EpochState>,
+    /// Receives the verified round timeouts of the peers, for the driver to add them
+    round_timeout_tx: Option<aptos_channels::Sender<RoundTimeout>>,
+    queue_depth: QueueDepth,
 }
 
+/// Rounds of messages the dedup cache holds, nodes of older rounds are rarely rebroadcast.
+const DEDUP_CACHE_ROUNDS: usize = 10;
+
+/// Score penalty for sending a message over the size limit, a rate limited message costs 1.
+const OVERSIZED_MESSAGE_PENALTY: i64 = 10;
+
+/// Score penalty for sending a message on behalf of another author.
+const SPOOFED_MESSAGE_PENALTY: i64 = 10;
+
+/// Anchors processed in a row before a waiting regular request is let through.
+const ANCHOR_BURST: usize = 4;
+
+/// Requests taken off the channel ahead of processing to look for anchors among them, the rest
+/// stay in the channel and are subject to its limits.
+const MAX_QUEUED_REQUESTS: usize = 64;
+
 impl NetworkHandler {
-    fn new(
+    pub fn new(
         dag: Arc<RwLock<Dag>>,
         dag_rpc_rx: aptos_channel::Receiver<Author, IncomingDAGRequest>,
         signer: ValidatorSigner,
         epoch_state: Arc<EpochState>,
+        rate_limiter: PeerRateLimiter,
     ) -> Self {
         Self {
             dag_rpc_rx,
-            node_receiver: NodeBroadcastHandler::new(dag, signer, epoch_state.verifier.clone()),
+            node_receiver: NodeBroadcastHandler::new(
+                dag.clone(),
+                signer,
+                epoch_state.verifier.clone(),
+            ),
+            fetch_handler: BatchFetchHandler::new(dag, epoch_state.clone()),
+            epoch_state: epoch_state.clone(),
+            rate_limiter,
+            max_message_bytes: MAX_MESSAGE_SIZE,
+            seen_messages: LruCache::new(max(1, epoch_state.verifier.len() * DEDUP_CACHE_ROUNDS)),
+            anchor_selector: None,
+            round_timeout_tx: None,
+            queue_depth: QueueDepth::new(counters::DAG_HANDLER_QUEUE_DEPTH.clone()),
         }
     }
 
-    async fn start(mut self) {
-        while let Some(msg) = self.dag_rpc_rx.next().await {
-            if let Err(e) = self.process_rpc(msg).await {
+    /// Processes the requests carrying an anchor of the given selector ahead of the others.
+    pub fn with_anchor_selector(mut self, anchor_selector: Arc<dyn AnchorSelector>) -> Self {
+        self.anchor_selector = Some(anchor_selector);
+        self
+    }
+
+    /// Verifies the round timeouts of the peers and sends them to the given channel, which the
+    /// driver takes them from. Without it they're rejected.
+    pub fn with_round_timeout_sender(
+        mut self,
+        round_timeout_tx: aptos_channels::Sender<RoundTimeout>,
+    ) -> Self {
+        self.round_timeout_tx = Some(round_timeout_tx);
+        self
+    }
+
+    /// Rejects incoming messages whose payload, before or after decompression, is larger than
+    /// max_message_bytes, and keeps batch fetch responses within the same limit.
+    pub fn with_max_message_bytes(mut self, max_message_bytes: usize) -> Self {
+        self.max_message_bytes = max_message_bytes;
+        self.fetch_handler = self.fetch_handler.with_max_message_bytes(max_message_bytes);
+        self
+    }
+
+    pub fn rate_limiter(&self) -> &PeerRateLimiter {
+        &self.rate_limiter
+    }
+
+    /// Requests taken off the network channel and waiting to be processed.
+    pub fn queue_depth(&self) -> QueueDepth {
+        self.queue_depth.clone()
+    }
+
+    pub async fn start(mut self) {
+        let mut queue = AnchorPriorityQueue::new(ANCHOR_BURST);
+        loop {
+            // take in what already arrived so that the anchors among it can go ahead
+            while queue.len() < MAX_QUEUED_REQUESTS {
+                match self.dag_rpc_rx.next().now_or_never() {
+                    Some(Some(msg)) => {
+                        // only an anchor selector needs the message before it's processed, it's
+                        // queued decoded so that it isn't decoded again
+                        let decoded = match (&self.anchor_selector, &msg.req) {
+                            (Some(_), ConsensusMsg::DAGMessage(dag_msg)) => Some(
+                                dag_msg.decode_with_limit::<DAGMessage>(self.max_message_bytes),
+                            ),
+                            _ => None,
+                        };
+                        let is_anchor = matches!(
+                            &decoded,
+                            Some(Ok(dag_message)) if self.carries_anchor(dag_message)
+                        );
+                        queue.push((msg, decoded), is_anchor);
+                    },
+                    _ => break,
+                }
+            }
+            let next = queue.pop();
+            self.queue_depth.set(queue.len());
+            let (msg, decoded) = match next {
+                Some(next) => next,
+                None => match self.dag_rpc_rx.next().await {
+                    Some(msg) => (msg, None),
+                    None => break,
+                },
+            };
+            if let Err(e) = self.process_request(msg, decoded).await {
                 warn!(error = ?e, "error sending rpc response for request");
             }
         }
     }
 
-    async fn process_rpc(&mut self, rpc_request: IncomingDAGRequest) -> anyhow::Result<()> {
-        let dag_message: DAGMessage = TConsensusMsg::from_network_message(rpc_request.req)?;
-        let response: anyhow::Result<DAGMessage> = match dag_message {
-            DAGMessage::NodeMsg(node) => self.node_receiver.process(node).map(|r| r.into()),
-            _ => {
-                error!("unknown rpc message {:?}", dag_message);
-                Err(anyhow::anyhow!("unknown rpc message"))
-            },
+    /// Whether the message is the node or certificate of an anchor.
+    fn carries_anchor(&self, dag_message: &DAGMessage) -> bool {
+        let anchor_selector = match &self.anchor_selector {
+            Some(anchor_selector) => anchor_selector,
+            None => return false,
+        };
+        let metadata = match dag_message {
+            DAGMessage::NodeMsg(node) => node.metadata(),
+            DAGMessage::NodeCertificateMsg(certificate) => certificate.metadata(),
+            _ => return false,
+        };
+        anchor_selector.anchor_author(metadata.round()) == Some(*metadata.author())
+    }
+
+    /// Whether the message is within the rate of the sender, the ones that aren't are dropped
+    /// without a response.
+    fn allow(&mut self, sender: Author, kind: &'static str) -> bool {
+        let allowed = self.rate_limiter.allow(sender, kind);
+        if !allowed {
+            debug!("dropping rate limited {} from {}", kind, sender);
+        }
+        allowed
+    }
+
+    pub(super) async fn process_rpc(
+        &mut self,
+        rpc_request: IncomingDAGRequest,
+    ) -> anyhow::Result<()> {
+        self.process_request(rpc_request, None).await
+    }
+
+    /// Processes the request, whose message is decoded here unless it already was.
+    async fn process_request(
+        &mut self,
+        mut rpc_request: IncomingDAGRequest,
+        decoded: Option<anyhow::Result<DAGMessage>>,
+    ) -> anyhow::Result<()> {
+        let msg = match &rpc_request.req {
+            ConsensusMsg::DAGMessage(msg) => msg,
+            msg => bail!("unexpected consensus message type {:?}", msg),
+        };
+        // the same message fanned in from several peers is answered from the cache, without
+        // decoding or verifying it again, but still within the rate of the sender
+        let digest = HashValue::sha3_256_of(&msg.data);
+        let response = if let Some((author, kind, response)) = self.seen_messages.get(&digest) {
+            let (author, kind, response) = (*author, *kind, response.clone());
+            self.verify_sender(rpc_request.sender, author.as_ref())?;
+            if !self.allow(rpc_request.sender, kind) {
+                return Ok(());
+            }
+            Ok(response)
+        } else {
+            let decoded = decoded.unwrap_or_else(|| msg.decode_with_limit(self.max_message_bytes));
+            let dag_message: DAGMessage = decoded.map_err(|e| {
+                if e.is::<DAGMessageSizeError>() {
+                    self.rate_limiter
+                        .penalize(rpc_request.sender, OVERSIZED_MESSAGE_PENALTY);
+                }
+                e
+            })?;
+            counters::DAG_MESSAGES_RECEIVED
+                .with_label_values(&[dag_message.name()])
+                .inc();
+            self.verify_sender(rpc_request.sender, dag_message.author())?;
+            let kind = dag_message.name();
+            if !self.allow(rpc_request.sender, kind) {
+                return Ok(());
+            }
+            // fetch responses change as the DAG grows, they aren't served from the cache
+            let cacheable = !matches!(dag_message, DAGMessage::BatchFetchRequest(_));
+            let author = dag_message.author().copied();
+            let response = tokio::select! {
+                response = self.process_message(dag_message) => response,
+                // The requester is gone, dropping the handler future cancels its in-flight work.
+                _ = rpc_request.response_sender.cancellation() => return Ok(()),
+            };
+            if let Ok(response) = &response {
+                if cacheable {
+                    self.seen_messages
+                        .put(digest, (author, kind, response.clone()));
+                }
+            }
+            response
         };
 
         let response = response
             .and_then(|response_msg| {
+                counters::DAG_MESSAGES_SENT
+                    .with_label_values(&[response_msg.name()])
+                    .inc();
                 rpc_request
                     .protocol
                     .to_bytes(&response_msg.into_network_message())
@@ -67,4 +387,38 @@ impl NetworkHandler {
             .send(response)
             .map_err(|_| anyhow::anyhow!("unable to process rpc"))
     }
+
+    /// Penalizes the peer if it sent a message on behalf of another author.
+    fn verify_sender(&mut self, peer: Author, author: Option<&Author>) -> anyhow::Result<()> {
+        verify_sender(peer, author).map_err(|e| {
+            self.rate_limiter.penalize(peer, SPOOFED_MESSAGE_PENALTY);
+            e.into()
+        })
+    }
+
+    async fn process_message(&mut self, dag_message: DAGMessage) -> anyhow::Result<DAGMessage> {
+        match dag_message {
+            DAGMessage::NodeMsg(node) => self.node_receiver.process(node).await.map(|r| r.into()),
+            DAGMessage::BatchFetchRequest(request) => {
+                self.fetch_handler.process(request).await.map(|r| r.into())
+            },
+            DAGMessage::RoundTimeoutMsg(timeout) => match &mut self.round_timeout_tx {
+                Some(round_timeout_tx) => {
+                    ensure!(
+                        timeout.epoch() == self.epoch_state.epoch,
+                        "round timeout from a different epoch"
+                    );
+                    timeout.verify(&self.epoch_state.verifier)?;
+                    let ack = RoundTimeoutAck::new(timeout.epoch(), timeout.round());
+                    round_timeout_tx.send(timeout).await?;
+                    Ok(ack.into())
+                },
+                None => Err(anyhow::anyhow!("no driver to take round timeouts")),
+            },
+            _ => {
+                error!("unknown rpc message {:?}", dag_message);
+                Err(anyhow::anyhow!("unknown rpc message"))
+            },
+        }
+    }
 }
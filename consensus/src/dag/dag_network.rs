@@ -1,15 +1,59 @@
 // Copyright © Aptos Foundation
 
 use crate::network_interface::ConsensusMsg;
+use anyhow::anyhow;
 use aptos_consensus_types::common::Author;
+use aptos_infallible::Mutex;
+use aptos_rate_limiter::rate_limit::Bucket;
 use async_trait::async_trait;
-use std::time::Duration;
+use std::{future::Future, sync::Arc, time::Duration};
+use thiserror::Error as ThisError;
 
-pub trait RpcHandler {
-    type Request;
+/// Dropping the future returned by `process` cancels the request, including any network calls
+/// the handler has in flight.
+#[async_trait]
+pub trait RpcHandler: Send {
+    type Request: Send;
     type Response;
 
-    fn process(&mut self, message: Self::Request) -> anyhow::Result<Self::Response>;
+    async fn process(&mut self, message: Self::Request) -> anyhow::Result<Self::Response>;
+}
+
+/// Fails the rpc if no response arrives within the timeout, so a dead peer can't leave it hanging.
+pub async fn with_timeout<T>(
+    timeout: Duration,
+    rpc: impl Future<Output = anyhow::Result<T>>,
+) -> anyhow::Result<T> {
+    tokio::time::timeout(timeout, rpc)
+        .await
+        .map_err(|_| anyhow!("rpc timed out after {:?}", timeout))?
+}
+
+#[derive(ThisError, Debug)]
+#[error("message authored by {author} received from {peer}")]
+pub struct SenderMismatchError {
+    pub author: Author,
+    pub peer: Author,
+}
+
+/// Checks that a message came from the author it claims, as returned by `DAGMessage::author`.
+/// The transport authenticates the peer, but nothing else ties the peer to that author.
+pub fn verify_sender(peer: Author, author: Option<&Author>) -> Result<(), SenderMismatchError> {
+    match author {
+        Some(author) if *author != peer => Err(SenderMismatchError {
+            author: *author,
+            peer,
+        }),
+        _ => Ok(()),
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RpcRetryPolicy {
+    /// Time to wait for each peer
+    pub timeout: Duration,
+    /// Maximum number of peers to try
+    pub max_attempts: usize,
 }
 
 #[async_trait]
@@ -29,4 +73,104 @@ pub trait DAGNetworkSender: Send + Sync {
         message: ConsensusMsg,
         timeout: Duration,
     ) -> anyhow::Result<ConsensusMsg>;
+
+    /// Sends the rpc to the responders in order, moving on to the next one whenever a peer fails
+    /// or times out, for at most max_attempts peers.
+    async fn send_rpc_with_policy(
+        &self,
+        responders: Vec<Author>,
+        message: ConsensusMsg,
+        policy: RpcRetryPolicy,
+    ) -> anyhow::Result<ConsensusMsg> {
+        let mut last_error = anyhow!("no responders");
+        for responder in responders.into_iter().take(policy.max_attempts) {
+            match with_timeout(
+                policy.timeout,
+                self.send_rpc(responder, message.clone(), policy.timeout),
+            )
+            .await
+            {
+                Ok(response) => return Ok(response),
+                Err(e) => last_error = e,
+            }
+        }
+        Err(last_error)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct BandwidthLimitConfig {
+    /// Outbound bytes allowed per second
+    pub bytes_per_sec: usize,
+    /// Bytes that can be sent at once after a quiet period, at least bytes_per_sec
+    pub burst_bytes: usize,
+}
+
+/// Caps the outbound byte rate of DAG messages with a token bucket over bytes. A send over
+/// budget waits until the bucket refilled enough, except for high priority messages, see
+/// `DAGMessage::is_high_priority`. Messages larger than the burst wait for a full bucket.
+pub struct BandwidthLimitedSender {
+    inner: Arc<dyn DAGNetworkSender>,
+    bucket: Mutex<Bucket>,
+    burst_bytes: usize,
+}
+
+impl BandwidthLimitedSender {
+    pub fn new(inner: Arc<dyn DAGNetworkSender>, config: BandwidthLimitConfig) -> Self {
+        Self {
+            inner,
+            bucket: Mutex::new(Bucket::new(
+                "dag_bandwidth".to_string(),
+                String::new(),
+                String::new(),
+                config.burst_bytes,
+                config.burst_bytes,
+                config.bytes_per_sec,
+                None,
+            )),
+            burst_bytes: config.burst_bytes,
+        }
+    }
+
+    async fn acquire(&self, message: &ConsensusMsg) -> anyhow::Result<()> {
+        if let ConsensusMsg::DAGMessage(dag_message) = message {
+            if dag_message.is_high_priority() {
+                return Ok(());
+            }
+        }
+        let tokens = bcs::serialized_size(message)?.min(self.burst_bytes);
+        loop {
+            let ready_at = match self.bucket.lock().acquire_all_tokens(tokens) {
+                Ok(()) => return Ok(()),
+                Err(Some(ready_at)) => ready_at,
+                Err(None) => unreachable!("at most burst_bytes tokens are requested"),
+            };
+            tokio::time::sleep_until(ready_at.into()).await;
+        }
+    }
+}
+
+#[async_trait]
+impl DAGNetworkSender for BandwidthLimitedSender {
+    async fn send_rpc(
+        &self,
+        receiver: Author,
+        message: ConsensusMsg,
+        timeout: Duration,
+    ) -> anyhow::Result<ConsensusMsg> {
+        self.acquire(&message).await?;
+        self.inner.send_rpc(receiver, message, timeout).await
+    }
+
+    async fn send_rpc_with_fallbacks(
+        &self,
+        responders: Vec<Author>,
+        message: ConsensusMsg,
+        timeout: Duration,
+    ) -> anyhow::Result<ConsensusMsg> {
+        self.acquire(&message).await?;
+        self.inner
+            .send_rpc_with_fallbacks(responders, message, timeout)
+            .await
+    }
 }
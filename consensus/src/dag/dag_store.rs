@@ -1,15 +1,90 @@
 // Copyright © Aptos Foundation
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::dag::types::{CertifiedNode, NodeCertificate};
+use crate::dag::{
+    counters,
+    storage::NodeStore,
+    types::{CertifiedNode, Equivocation, NodeCertificate},
+};
 use anyhow::{anyhow, ensure};
 use aptos_consensus_types::common::{Author, Round};
 use aptos_crypto::HashValue;
+use aptos_logger::error;
 use aptos_types::validator_verifier::ValidatorVerifier;
+use serde::{Deserialize, Serialize};
 use std::{
-    collections::{BTreeMap, HashMap},
-    sync::Arc,
+    cmp::max,
+    collections::{BTreeMap, HashMap, HashSet},
+    mem::size_of,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
 };
+use thiserror::Error as ThisError;
+use tokio::sync::Notify;
+
+/// Serialized size and recency of a node, used to evict committed nodes under memory pressure.
+struct NodeStats {
+    size: usize,
+    last_access: AtomicU64,
+}
+
+pub const DEFAULT_RETENTION_ROUNDS: Round = 100;
+
+pub const DEFAULT_ORPHAN_GC_ROUNDS: Round = 10;
+
+#[derive(ThisError, Debug)]
+pub enum DagStoreError {
+    #[error("unknown author")]
+    UnknownAuthor,
+    #[error("round too low")]
+    RoundTooLow,
+    #[error("round too high")]
+    RoundTooHigh,
+    #[error("parent not exist")]
+    MissingParent,
+    #[error("duplicate node")]
+    DuplicateNode,
+    /// The proof is boxed, it holds two nodes
+    #[error(transparent)]
+    Equivocation(Box<Equivocation>),
+    #[error("failed to persist node: {0}")]
+    Storage(anyhow::Error),
+}
+
+/// A node whose parents aren't all in the DAG yet, it's added once they are.
+struct Orphan {
+    node: Arc<CertifiedNode>,
+    /// Fetching the missing parents failed, so the orphan may be collected
+    fetch_failed: bool,
+}
+
+/// Memory the digest, stats and author indices take per node, on top of the node itself.
+const NODE_INDEX_BYTES: usize = size_of::<(HashValue, Arc<CertifiedNode>)>()
+    + size_of::<(HashValue, NodeStats)>()
+    + size_of::<(Round, Arc<CertifiedNode>)>();
+
+/// A node of a `DagSnapshot`, with its parents referred to by digest.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodeSnapshot {
+    pub digest: HashValue,
+    pub round: Round,
+    pub author: Author,
+    pub parents: Vec<HashValue>,
+    /// The node is in a round below the committed round
+    pub committed: bool,
+}
+
+/// Serializable copy of the nodes retained in the DAG, ordered by round and author, for
+/// debugging and offline analysis.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DagSnapshot {
+    pub lowest_round: Round,
+    pub highest_round: Round,
+    pub committed_round: Round,
+    pub nodes: Vec<NodeSnapshot>,
+}
 
 /// Data structure that stores the DAG representation, it maintains both hash based index and
 /// round based index.
@@ -18,6 +93,25 @@ pub struct Dag {
     nodes_by_round: BTreeMap<Round, Vec<Option<Arc<CertifiedNode>>>>,
     /// Map between peer id to vector index
     author_to_index: HashMap<Author, usize>,
+    /// Secondary index of the nodes in nodes_by_round, by author and round
+    nodes_by_author: HashMap<Author, BTreeMap<Round, Arc<CertifiedNode>>>,
+    node_stats: HashMap<HashValue, NodeStats>,
+    access_clock: AtomicU64,
+    total_bytes: usize,
+    max_bytes: usize,
+    /// Nodes below this round are committed, they're kept to serve fetches and can be evicted
+    /// (least recently used first) once the DAG grows over max_bytes.
+    committed_round: Round,
+    /// Number of rounds below the committed round that are kept, older rounds are pruned
+    retention_rounds: Round,
+    storage: Option<Arc<dyn NodeStore>>,
+    /// Wakes the tasks waiting on new nodes
+    node_added: Arc<Notify>,
+    orphans: HashMap<HashValue, Orphan>,
+    /// Rounds the DAG has to be past an orphan whose parents failed to fetch before it's dropped
+    orphan_gc_rounds: Round,
+    /// Receives the equivocations that have no caller to return them to
+    equivocation_tx: Option<aptos_channels::Sender<Equivocation>>,
 }
 
 impl Dag {
@@ -29,7 +123,79 @@ impl Dag {
             nodes_by_digest: HashMap::new(),
             nodes_by_round,
             author_to_index,
+            nodes_by_author: HashMap::new(),
+            node_stats: HashMap::new(),
+            access_clock: AtomicU64::new(0),
+            total_bytes: 0,
+            max_bytes: usize::MAX,
+            committed_round: initial_round,
+            retention_rounds: DEFAULT_RETENTION_ROUNDS,
+            storage: None,
+            node_added: Arc::new(Notify::new()),
+            orphans: HashMap::new(),
+            orphan_gc_rounds: DEFAULT_ORPHAN_GC_ROUNDS,
+            equivocation_tx: None,
+        }
+    }
+
+    pub fn with_max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    pub fn with_retention_rounds(mut self, retention_rounds: Round) -> Self {
+        self.retention_rounds = retention_rounds;
+        self
+    }
+
+    pub fn with_orphan_gc_rounds(mut self, orphan_gc_rounds: Round) -> Self {
+        self.orphan_gc_rounds = orphan_gc_rounds;
+        self
+    }
+
+    /// Sends the equivocations found while adopting orphans, and the ones reported through
+    /// `report_equivocation`, to the given channel instead of logging them.
+    pub fn with_equivocation_tx(
+        mut self,
+        equivocation_tx: aptos_channels::Sender<Equivocation>,
+    ) -> Self {
+        self.equivocation_tx = Some(equivocation_tx);
+        self
+    }
+
+    /// Persists every node added from now on, and deletes them from storage once pruned.
+    pub fn with_storage(mut self, storage: Arc<dyn NodeStore>) -> Self {
+        self.storage = Some(storage);
+        self
+    }
+
+    /// Rebuilds the DAG from the nodes in storage, starting from the lowest stored round at or
+    /// above initial_round.
+    pub fn recover(
+        author_to_index: HashMap<Author, usize>,
+        initial_round: Round,
+        storage: Arc<dyn NodeStore>,
+    ) -> anyhow::Result<Self> {
+        let nodes: Vec<_> = storage
+            .get_nodes()?
+            .into_iter()
+            .filter(|node| node.metadata().round() >= initial_round)
+            .collect();
+        let lowest_round = nodes
+            .iter()
+            .map(|node| node.metadata().round())
+            .min()
+            .unwrap_or(initial_round);
+        let mut dag = Self::new(author_to_index, lowest_round).with_storage(storage);
+        for node in nodes {
+            let index = *dag
+                .author_to_index
+                .get(node.metadata().author())
+                .ok_or_else(|| anyhow!("unknown author"))?;
+            dag.insert_node(index, Arc::new(node));
         }
+        counters::DAG_NUM_NODES.set(dag.num_nodes() as i64);
+        Ok(dag)
     }
 
     pub(crate) fn lowest_round(&self) -> Round {
@@ -48,33 +214,180 @@ impl Dag {
             .unwrap_or(&0)
     }
 
-    pub fn add_node(&mut self, node: CertifiedNode) -> anyhow::Result<()> {
-        let node = Arc::new(node);
+    /// Lowest and highest rounds retained in the DAG.
+    pub fn rounds_range(&self) -> (Round, Round) {
+        (self.lowest_round(), self.highest_round())
+    }
+
+    /// Fails with `DagStoreError::Equivocation` if the author already has a different node in
+    /// the same round. The orphans whose parents are now all in the DAG are added along with it,
+    /// their equivocations go to `report_equivocation`.
+    pub fn add_node(&mut self, node: CertifiedNode) -> Result<(), DagStoreError> {
+        self.try_add_node(Arc::new(node))?;
+        self.adopt_orphans();
+        Ok(())
+    }
+
+    fn try_add_node(&mut self, node: Arc<CertifiedNode>) -> Result<(), DagStoreError> {
         let index = *self
             .author_to_index
             .get(node.metadata().author())
-            .ok_or_else(|| anyhow!("unknown author"))?;
+            .ok_or(DagStoreError::UnknownAuthor)?;
         let round = node.metadata().round();
-        ensure!(round >= self.lowest_round(), "round too low");
-        ensure!(round <= self.highest_round() + 1, "round too high");
-        for parent in node.parents() {
-            ensure!(self.exists(parent.metadata().digest()), "parent not exist");
+        if round < self.lowest_round() {
+            return Err(DagStoreError::RoundTooLow);
+        }
+        if round > self.highest_round() + 1 {
+            return Err(DagStoreError::RoundTooHigh);
+        }
+        if !self.all_exists(
+            node.parents()
+                .iter()
+                .map(|parent| parent.metadata().digest()),
+        ) {
+            return Err(DagStoreError::MissingParent);
+        }
+        if self.nodes_by_digest.contains_key(&node.digest()) {
+            return Err(DagStoreError::DuplicateNode);
+        }
+        if let Some(existing) = self
+            .nodes_by_round
+            .get(&round)
+            .and_then(|nodes| nodes[index].as_ref())
+        {
+            return Err(DagStoreError::Equivocation(Box::new(Equivocation {
+                existing: existing.as_ref().clone(),
+                conflicting: node.as_ref().clone(),
+            })));
         }
+        if let Some(storage) = &self.storage {
+            storage.save_node(&node).map_err(DagStoreError::Storage)?;
+        }
+        self.orphans.remove(&node.digest());
+        self.insert_node(index, node);
+        self.evict_if_needed();
+        counters::DAG_NUM_NODES.set(self.num_nodes() as i64);
+        Ok(())
+    }
+
+    /// Holds on to a node whose parents are missing until they're added, or until it's
+    /// collected by `gc_orphans`.
+    pub fn add_orphan(&mut self, node: CertifiedNode) -> anyhow::Result<()> {
+        ensure!(
+            self.author_to_index.contains_key(node.metadata().author()),
+            "unknown author"
+        );
         ensure!(
-            self.nodes_by_digest
-                .insert(node.digest(), node.clone())
-                .is_none(),
+            node.metadata().round() >= self.lowest_round(),
+            "round too low"
+        );
+        ensure!(
+            !self.exists(&node.digest()) && !self.orphans.contains_key(&node.digest()),
             "duplicate node"
         );
-        let round_ref = self
-            .nodes_by_round
-            .entry(round)
-            .or_insert_with(|| vec![None; self.author_to_index.len()]);
-        ensure!(round_ref[index].is_none(), "equivocate node");
-        round_ref[index] = Some(node);
+        self.orphans.insert(node.digest(), Orphan {
+            node: Arc::new(node),
+            fetch_failed: false,
+        });
         Ok(())
     }
 
+    /// Lets `gc_orphans` collect the orphan once fetching its missing parents failed.
+    pub fn orphan_fetch_failed(&mut self, digest: &HashValue) {
+        if let Some(orphan) = self.orphans.get_mut(digest) {
+            orphan.fetch_failed = true;
+        }
+    }
+
+    pub fn num_orphans(&self) -> usize {
+        self.orphans.len()
+    }
+
+    /// Drops the orphans below the lowest round, and the ones whose parents failed to fetch
+    /// once the DAG is orphan_gc_rounds rounds past them. Returns how many were dropped.
+    pub fn gc_orphans(&mut self) -> usize {
+        let (lowest_round, highest_round) = self.rounds_range();
+        let orphan_gc_rounds = self.orphan_gc_rounds;
+        let num_orphans = self.orphans.len();
+        self.orphans.retain(|_, orphan| {
+            let round = orphan.node.metadata().round();
+            let expired =
+                orphan.fetch_failed && round.saturating_add(orphan_gc_rounds) <= highest_round;
+            round >= lowest_round && !expired
+        });
+        num_orphans - self.orphans.len()
+    }
+
+    fn adopt_orphans(&mut self) {
+        loop {
+            let adoptable: Vec<_> = self
+                .orphans
+                .iter()
+                .filter(|(_, orphan)| {
+                    self.all_exists(
+                        orphan
+                            .node
+                            .parents()
+                            .iter()
+                            .map(|parent| parent.metadata().digest()),
+                    )
+                })
+                .map(|(digest, _)| *digest)
+                .collect();
+            if adoptable.is_empty() {
+                return;
+            }
+            for digest in adoptable {
+                if let Some(orphan) = self.orphans.remove(&digest) {
+                    match self.try_add_node(orphan.node) {
+                        Ok(()) => {},
+                        Err(DagStoreError::Equivocation(equivocation)) => {
+                            self.report_equivocation(*equivocation)
+                        },
+                        Err(e) => error!(error = ?e, "failed to add orphan node"),
+                    }
+                }
+            }
+        }
+    }
+
+    /// Hands an equivocation proof to the channel set with `with_equivocation_tx`, or logs it
+    /// without one.
+    pub fn report_equivocation(&mut self, equivocation: Equivocation) {
+        match &mut self.equivocation_tx {
+            Some(equivocation_tx) => {
+                if let Err(e) = equivocation_tx.try_send(equivocation) {
+                    error!(error = ?e, "failed to report equivocation");
+                }
+            },
+            None => error!(error = %equivocation, "equivocation"),
+        }
+    }
+
+    fn insert_node(&mut self, index: usize, node: Arc<CertifiedNode>) {
+        self.nodes_by_round
+            .entry(node.metadata().round())
+            .or_insert_with(|| vec![None; self.author_to_index.len()])[index] = Some(node.clone());
+        self.nodes_by_author
+            .entry(*node.metadata().author())
+            .or_default()
+            .insert(node.metadata().round(), node.clone());
+        let size = bcs::serialized_size(node.as_ref()).expect("Unable to serialize node");
+        self.node_stats.insert(node.digest(), NodeStats {
+            size,
+            last_access: AtomicU64::new(self.access_clock.fetch_add(1, Ordering::Relaxed)),
+        });
+        self.total_bytes += size;
+        self.nodes_by_digest.insert(node.digest(), node);
+        self.node_added.notify_waiters();
+    }
+
+    /// Notified whenever a node is added, `notified()` must be called before checking the DAG so
+    /// that no node is missed in between.
+    pub fn node_added(&self) -> Arc<Notify> {
+        self.node_added.clone()
+    }
+
     pub fn exists(&self, digest: &HashValue) -> bool {
         self.nodes_by_digest.contains_key(digest)
     }
@@ -84,7 +397,186 @@ impl Dag {
     }
 
     pub fn get_node(&self, digest: &HashValue) -> Option<Arc<CertifiedNode>> {
-        self.nodes_by_digest.get(digest).cloned()
+        let node = self.nodes_by_digest.get(digest).cloned();
+        if let Some(stats) = self.node_stats.get(digest) {
+            stats.last_access.store(
+                self.access_clock.fetch_add(1, Ordering::Relaxed),
+                Ordering::Relaxed,
+            );
+        }
+        node
+    }
+
+    /// Returns the author's node in the highest round retained in the DAG.
+    pub fn latest_node_by_author(&self, author: &Author) -> Option<Arc<CertifiedNode>> {
+        self.nodes_by_author
+            .get(author)
+            .and_then(|nodes| nodes.last_key_value())
+            .map(|(_, node)| node.clone())
+    }
+
+    /// Returns the transitive parents of the node that are still in the DAG, parents in pruned
+    /// or evicted rounds end the traversal. The history is ordered by round, then by author, the
+    /// same order the commit rule delivers nodes in, so it's deterministic across validators.
+    pub fn causal_history(&self, node: &CertifiedNode) -> Vec<Arc<CertifiedNode>> {
+        let mut visited = HashSet::new();
+        let mut history = vec![];
+        let mut to_visit: Vec<_> = node.parents().iter().collect();
+        while let Some(certificate) = to_visit.pop() {
+            let digest = certificate.metadata().digest();
+            if !visited.insert(*digest) {
+                continue;
+            }
+            if let Some(parent) = self.nodes_by_digest.get(digest) {
+                to_visit.extend(parent.parents());
+                history.push(parent.clone());
+            }
+        }
+        history.sort_by_key(|node| (node.metadata().round(), *node.metadata().author()));
+        history
+    }
+
+    pub fn nodes_at_round(&self, round: Round) -> Vec<Arc<CertifiedNode>> {
+        self.nodes_by_round
+            .get(&round)
+            .map(|nodes| nodes.iter().flatten().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn num_nodes(&self) -> usize {
+        self.nodes_by_digest.len()
+    }
+
+    /// Approximate memory held by the nodes, based on their serialized size.
+    pub fn size_bytes(&self) -> usize {
+        self.total_bytes
+    }
+
+    /// Approximate memory held by the DAG, the serialized size of the nodes plus the indices
+    /// over them, including the per round slots of absent nodes.
+    pub fn approx_memory_bytes(&self) -> usize {
+        let round_slot_bytes = self.nodes_by_round.len()
+            * (size_of::<(Round, Vec<Option<Arc<CertifiedNode>>>)>()
+                + self.author_to_index.len() * size_of::<Option<Arc<CertifiedNode>>>());
+        self.total_bytes + self.num_nodes() * NODE_INDEX_BYTES + round_slot_bytes
+    }
+
+    /// Fraction of max_bytes in use, above 1 when uncommitted nodes alone exceed the cap.
+    pub fn occupancy(&self) -> f64 {
+        self.total_bytes as f64 / max(self.max_bytes, 1) as f64
+    }
+
+    /// Captures the retained nodes. Taken under the lock guarding the DAG, so the snapshot is
+    /// consistent.
+    pub fn export_snapshot(&self) -> DagSnapshot {
+        let nodes = self
+            .nodes_by_round
+            .values()
+            .flat_map(|nodes| {
+                let mut nodes: Vec<_> = nodes.iter().flatten().collect();
+                nodes.sort_by_key(|node| *node.metadata().author());
+                nodes
+            })
+            .map(|node| NodeSnapshot {
+                digest: node.digest(),
+                round: node.metadata().round(),
+                author: *node.metadata().author(),
+                parents: node
+                    .parents()
+                    .iter()
+                    .map(|parent| *parent.metadata().digest())
+                    .collect(),
+                committed: node.metadata().round() < self.committed_round,
+            })
+            .collect();
+        DagSnapshot {
+            lowest_round: self.lowest_round(),
+            highest_round: self.highest_round(),
+            committed_round: self.committed_round,
+            nodes,
+        }
+    }
+
+    /// Marks all rounds below the given round as committed, making their nodes evictable, and
+    /// prunes the rounds that fall out of the retention window.
+    pub fn set_committed_round(&mut self, round: Round) {
+        self.committed_round = max(self.committed_round, round);
+        let prune_round = self.committed_round.saturating_sub(self.retention_rounds);
+        if prune_round > self.lowest_round() {
+            self.prune_below(prune_round);
+        }
+        self.evict_if_needed();
+        counters::DAG_NUM_NODES.set(self.num_nodes() as i64);
+    }
+
+    /// Removes all nodes below the given round.
+    pub fn prune_below(&mut self, round: Round) {
+        let retained = self.nodes_by_round.split_off(&round);
+        let pruned = std::mem::replace(&mut self.nodes_by_round, retained);
+        self.nodes_by_author.retain(|_, nodes| {
+            *nodes = nodes.split_off(&round);
+            !nodes.is_empty()
+        });
+        let mut pruned_keys = vec![];
+        for node in pruned.into_values().flatten().flatten() {
+            self.nodes_by_digest.remove(&node.digest());
+            if let Some(stats) = self.node_stats.remove(&node.digest()) {
+                self.total_bytes -= stats.size;
+            }
+            pruned_keys.push((node.metadata().round(), *node.metadata().author()));
+        }
+        if let Some(storage) = &self.storage {
+            if let Err(e) = storage.delete_nodes(pruned_keys) {
+                error!(error = ?e, "failed to delete pruned dag nodes");
+            }
+        }
+        if self.nodes_by_round.is_empty() {
+            self.nodes_by_round
+                .insert(round, vec![None; self.author_to_index.len()]);
+        }
+        counters::DAG_NUM_NODES.set(self.num_nodes() as i64);
+    }
+
+    fn evict_if_needed(&mut self) {
+        if self.total_bytes <= self.max_bytes {
+            return;
+        }
+        let mut evictable: Vec<_> = self
+            .nodes_by_round
+            .range(..self.committed_round)
+            .flat_map(|(_, nodes)| nodes.iter().flatten())
+            .map(|node| {
+                let last_access = self
+                    .node_stats
+                    .get(&node.digest())
+                    .map_or(0, |stats| stats.last_access.load(Ordering::Relaxed));
+                (last_access, node.clone())
+            })
+            .collect();
+        evictable.sort_unstable_by_key(|(last_access, _)| *last_access);
+        let mut evicted_keys = vec![];
+        for (_, node) in evictable {
+            if self.total_bytes <= self.max_bytes {
+                break;
+            }
+            let index = self.author_to_index[node.metadata().author()];
+            if let Some(nodes) = self.nodes_by_round.get_mut(&node.metadata().round()) {
+                nodes[index] = None;
+            }
+            if let Some(nodes) = self.nodes_by_author.get_mut(node.metadata().author()) {
+                nodes.remove(&node.metadata().round());
+            }
+            self.nodes_by_digest.remove(&node.digest());
+            if let Some(stats) = self.node_stats.remove(&node.digest()) {
+                self.total_bytes -= stats.size;
+            }
+            evicted_keys.push((node.metadata().round(), *node.metadata().author()));
+        }
+        if let Some(storage) = &self.storage {
+            if let Err(e) = storage.delete_nodes(evicted_keys) {
+                error!(error = ?e, "failed to delete evicted dag nodes");
+            }
+        }
     }
 
     pub fn get_strong_links_for_round(
@@ -2,12 +2,16 @@
 // SPDX-License-Identifier: Apache-2.0
 #![allow(dead_code)]
 
+mod anchor_selection;
+mod commit_rule;
+mod counters;
 mod dag_driver;
 mod dag_fetcher;
 mod dag_handler;
 mod dag_network;
 mod dag_store;
 mod reliable_broadcast;
+mod storage;
 #[cfg(test)]
 mod tests;
 mod types;
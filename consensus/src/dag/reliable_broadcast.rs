@@ -3,19 +3,39 @@
 
 use crate::{
     dag::{
+        counters,
         dag_network::{DAGNetworkSender, RpcHandler},
         dag_store::Dag,
-        types::{Node, NodeCertificate, NodeDigest, NodeDigestSignature, TDAGMessage},
+        storage::{broadcast_digest, BroadcastStore, PendingBroadcast},
+        types::{
+            verify_certificates, DAGMessage, Node, NodeCertificate, NodeDigestSignature,
+            TDAGMessage,
+        },
     },
     network::TConsensusMsg,
+    util::time_service::TimeService,
 };
 use anyhow::{bail, ensure};
 use aptos_consensus_types::common::{Author, Round};
-use aptos_infallible::RwLock;
+use aptos_crypto::HashValue;
+use aptos_infallible::{Mutex, RwLock};
+use aptos_logger::error;
 use aptos_types::{validator_signer::ValidatorSigner, validator_verifier::ValidatorVerifier};
-use futures::{stream::FuturesUnordered, StreamExt};
-use std::{collections::BTreeMap, future::Future, sync::Arc, time::Duration};
+use async_trait::async_trait;
+use futures::{future::pending, stream::FuturesUnordered, FutureExt, StreamExt};
+use rand::Rng;
+use std::{
+    cmp::min,
+    collections::{BTreeMap, HashMap},
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 use thiserror::Error as ThisError;
+use tokio::sync::watch;
 
 pub trait BroadcastStatus {
     type Ack: TDAGMessage;
@@ -25,31 +45,232 @@ pub trait BroadcastStatus {
     fn add(&mut self, peer: Author, ack: Self::Ack) -> anyhow::Result<Option<Self::Aggregated>>;
 }
 
+/// Exponential backoff between retries to the same peer. The random jitter keeps validators from
+/// retrying in lockstep after a network blip.
+#[derive(Clone, Debug)]
+pub struct BackoffConfig {
+    pub base: Duration,
+    pub max: Duration,
+    pub jitter: Duration,
+}
+
+impl Default for BackoffConfig {
+    fn default() -> Self {
+        Self {
+            base: Duration::from_millis(50),
+            max: Duration::from_secs(3),
+            jitter: Duration::from_millis(50),
+        }
+    }
+}
+
+impl BackoffConfig {
+    /// Delay before the given retry attempt (starting from 1), i.e. base * 2^(attempt - 1) plus
+    /// a random jitter, capped at max.
+    pub fn delay(&self, attempt: u32, rng: &mut impl Rng) -> Duration {
+        let factor = 1u32
+            .checked_shl(attempt.saturating_sub(1))
+            .unwrap_or(u32::MAX);
+        let jitter = if self.jitter.is_zero() {
+            Duration::ZERO
+        } else {
+            Duration::from_nanos(rng.gen_range(0, self.jitter.as_nanos() as u64))
+        };
+        min(
+            self.base.saturating_mul(factor).saturating_add(jitter),
+            self.max,
+        )
+    }
+}
+
+/// The digest and receipts of each broadcast in progress, by broadcast id. The same message may
+/// be broadcast more than once at a time, each broadcast keeps its own receipts.
+type DeliveryReceipts = Arc<Mutex<HashMap<u64, (HashValue, HashMap<Author, Duration>)>>>;
+
+/// Drops the receipts of a broadcast once it's done, however it ends.
+struct DeliveryReceiptsGuard {
+    receipts: DeliveryReceipts,
+    broadcast_id: u64,
+}
+
+impl Drop for DeliveryReceiptsGuard {
+    fn drop(&mut self) {
+        let mut receipts = self.receipts.lock();
+        receipts.remove(&self.broadcast_id);
+        counters::DAG_OUTSTANDING_BROADCASTS.set(receipts.len() as i64);
+    }
+}
+
 pub struct ReliableBroadcast {
     validators: Vec<Author>,
     network_sender: Arc<dyn DAGNetworkSender>,
+    backoff: BackoffConfig,
+    time_service: Arc<dyn TimeService>,
+    store: Arc<dyn BroadcastStore>,
+    /// How long to keep delivering to the remaining peers once the broadcast aggregated
+    best_effort_delivery: Option<Duration>,
+    /// Rounds after which a broadcast started with expiry is abandoned
+    round_ttl: Option<Round>,
+    current_round: watch::Sender<Round>,
+    /// When each peer acked, for the broadcasts in progress
+    receipts: DeliveryReceipts,
+    next_broadcast_id: AtomicU64,
 }
 
 impl ReliableBroadcast {
-    pub fn new(validators: Vec<Author>, network_sender: Arc<dyn DAGNetworkSender>) -> Self {
+    pub fn new(
+        validators: Vec<Author>,
+        network_sender: Arc<dyn DAGNetworkSender>,
+        backoff: BackoffConfig,
+        time_service: Arc<dyn TimeService>,
+        store: Arc<dyn BroadcastStore>,
+    ) -> Self {
         Self {
             validators,
             network_sender,
+            backoff,
+            time_service,
+            store,
+            best_effort_delivery: None,
+            round_ttl: None,
+            current_round: watch::channel(0).0,
+            receipts: Arc::new(Mutex::new(HashMap::new())),
+            next_broadcast_id: AtomicU64::new(0),
         }
     }
 
+    /// Keeps the rpcs that are still in flight when the broadcast aggregates running for up to
+    /// the given duration, without retrying them.
+    pub fn with_best_effort_delivery(mut self, duration: Duration) -> Self {
+        self.best_effort_delivery = Some(duration);
+        self
+    }
+
+    /// Abandons the broadcasts started with expiry once the current round is round_ttl rounds
+    /// past the round they started in.
+    pub fn with_round_ttl(mut self, round_ttl: Round) -> Self {
+        self.round_ttl = Some(round_ttl);
+        self
+    }
+
+    /// Advances the round the expiry of broadcasts is measured against, it never goes back.
+    pub fn set_current_round(&self, round: Round) {
+        self.current_round.send_if_modified(|current_round| {
+            let advanced = round > *current_round;
+            if advanced {
+                *current_round = round;
+            }
+            advanced
+        });
+    }
+
+    /// Drops the record of the pending broadcasts of rounds below the given round, which a new
+    /// round supersedes. The records of messages without a round are kept.
+    pub fn clear_pending_broadcasts_below(&self, round: Round) -> anyhow::Result<()> {
+        for (digest, pending) in self.store.get_broadcasts()? {
+            if pending
+                .message
+                .round()
+                .map_or(false, |message_round| message_round < round)
+            {
+                self.store.delete_broadcast(digest)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Messages whose broadcast had not aggregated before the last shutdown. Broadcasting any of
+    /// them again resumes from the acks already received.
+    pub fn pending_broadcasts(&self) -> anyhow::Result<Vec<DAGMessage>> {
+        Ok(self
+            .store
+            .get_broadcasts()?
+            .into_values()
+            .map(|pending| pending.message)
+            .collect())
+    }
+
+    /// Number of broadcasts in progress, i.e. started and neither aggregated nor dropped.
+    pub fn num_outstanding_broadcasts(&self) -> usize {
+        self.receipts.lock().len()
+    }
+
+    /// When each validator acked the broadcast in progress with the given digest, see
+    /// `broadcast_digest`, as a timestamp of the time service, or None if it hasn't yet. Acks
+    /// replayed after a restart are stamped when they're replayed. If the message is being
+    /// broadcast more than once, a peer counts as acked from its first ack to any of them.
+    pub fn delivery_status(&self, digest: HashValue) -> Vec<(Author, Option<Duration>)> {
+        let receipts = self.receipts.lock();
+        self.validators
+            .iter()
+            .map(|peer| {
+                let acked_at = receipts
+                    .values()
+                    .filter(|(broadcast_digest, _)| *broadcast_digest == digest)
+                    .filter_map(|(_, receipts)| receipts.get(peer))
+                    .min()
+                    .copied();
+                (*peer, acked_at)
+            })
+            .collect()
+    }
+
+    /// Sends the message to all validators, retrying the failed rpcs and rejected acks with
+    /// backoff until the acks aggregate. Fails only if there's no validator left to send to.
     pub fn broadcast<S: BroadcastStatus>(
+        &self,
+        message: S::Message,
+        aggregating: S,
+    ) -> impl Future<Output = anyhow::Result<S::Aggregated>> {
+        self.broadcast_until(message, aggregating, None)
+            .map(|result| {
+                result.map(|aggregated| {
+                    aggregated.expect("broadcast without expiry always aggregates")
+                })
+            })
+    }
+
+    /// Like `broadcast`, but returns None if the broadcast expires before it aggregates, which
+    /// stops the retries and drops its pending record. Never expires without a round_ttl.
+    pub fn broadcast_with_expiry<S: BroadcastStatus>(
+        &self,
+        message: S::Message,
+        aggregating: S,
+    ) -> impl Future<Output = anyhow::Result<Option<S::Aggregated>>> {
+        let expiry_round = self
+            .round_ttl
+            .map(|round_ttl| *self.current_round.borrow() + round_ttl);
+        self.broadcast_until(message, aggregating, expiry_round)
+    }
+
+    fn broadcast_until<S: BroadcastStatus>(
         &self,
         message: S::Message,
         mut aggregating: S,
-    ) -> impl Future<Output = S::Aggregated> {
+        expiry_round: Option<Round>,
+    ) -> impl Future<Output = anyhow::Result<Option<S::Aggregated>>> {
+        let mut current_round = self.current_round.subscribe();
         let receivers: Vec<_> = self.validators.clone();
         let network_sender = self.network_sender.clone();
+        let backoff = self.backoff.clone();
+        let time_service = self.time_service.clone();
+        let store = self.store.clone();
+        let best_effort_delivery = self.best_effort_delivery;
+        let receipts = self.receipts.clone();
+        let broadcast_id = self.next_broadcast_id.fetch_add(1, Ordering::Relaxed);
         async move {
             let mut fut = FuturesUnordered::new();
-            let send_message = |receiver, message| {
+            let mut attempts: HashMap<Author, u32> = HashMap::new();
+            let message: DAGMessage = message.into();
+            let kind = message.name();
+            let send_message = |receiver, message, delay: Option<Duration>| {
                 let network_sender = network_sender.clone();
+                let time_service = time_service.clone();
                 async move {
+                    if let Some(delay) = delay {
+                        time_service.sleep(delay).await;
+                    }
+                    counters::DAG_MESSAGES_SENT.with_label_values(&[kind]).inc();
                     (
                         receiver,
                         network_sender
@@ -58,26 +279,125 @@ impl ReliableBroadcast {
                     )
                 }
             };
-            let network_message = message.into().into_network_message();
+            let digest = broadcast_digest(&message);
+            let mut pending = match store.get_broadcast(digest) {
+                Ok(maybe_pending) => maybe_pending,
+                Err(e) => {
+                    error!(error = ?e, "failed to read pending broadcast");
+                    None
+                },
+            }
+            .unwrap_or_else(|| PendingBroadcast::new(message.clone()));
+            {
+                let mut receipts = receipts.lock();
+                receipts.insert(broadcast_id, (digest, HashMap::new()));
+                counters::DAG_OUTSTANDING_BROADCASTS.set(receipts.len() as i64);
+            }
+            let _receipts_guard = DeliveryReceiptsGuard {
+                receipts: receipts.clone(),
+                broadcast_id,
+            };
+            let record_receipt = |peer| {
+                if let Some((_, receipts)) = receipts.lock().get_mut(&broadcast_id) {
+                    receipts.insert(peer, time_service.get_current_timestamp());
+                }
+            };
+            // replay the acks received before a restart
+            for (peer, ack) in &pending.acks {
+                if let Ok(ack) = S::Ack::try_from(ack.clone()) {
+                    record_receipt(*peer);
+                    if let Ok(Some(aggregated)) = aggregating.add(*peer, ack) {
+                        if let Err(e) = store.delete_broadcast(digest) {
+                            error!(error = ?e, "failed to delete pending broadcast");
+                        }
+                        return Ok(Some(aggregated));
+                    }
+                }
+            }
+            if let Err(e) = store.save_broadcast(digest, &pending) {
+                error!(error = ?e, "failed to save pending broadcast");
+            }
+            let network_message = message.into_network_message();
             for receiver in receivers {
-                fut.push(send_message(receiver, network_message.clone()));
+                if !pending.acks.contains_key(&receiver) {
+                    fut.push(send_message(receiver, network_message.clone(), None));
+                }
             }
-            while let Some((receiver, result)) = fut.next().await {
-                match result {
-                    Ok(msg) => {
-                        if let Ok(dag_msg) = msg.try_into() {
-                            if let Ok(ack) = S::Ack::try_from(dag_msg) {
-                                if let Ok(Some(aggregated)) = aggregating.add(receiver, ack) {
-                                    return aggregated;
-                                }
-                            }
+            loop {
+                let (receiver, result) = tokio::select! {
+                    Some(next) = fut.next() => next,
+                    _ = Self::expired(&mut current_round, expiry_round) => {
+                        if let Err(e) = store.delete_broadcast(digest) {
+                            error!(error = ?e, "failed to delete expired broadcast");
                         }
+                        return Ok(None);
                     },
-                    Err(_) => fut.push(send_message(receiver, network_message.clone())),
+                    else => break,
+                };
+                let maybe_ack = result
+                    .ok()
+                    .and_then(|msg| DAGMessage::try_from(msg).ok())
+                    .and_then(|dag_msg| {
+                        counters::DAG_MESSAGES_RECEIVED
+                            .with_label_values(&[dag_msg.name()])
+                            .inc();
+                        S::Ack::try_from(dag_msg.clone())
+                            .ok()
+                            .map(|ack| (dag_msg, ack))
+                    });
+                if let Some((dag_msg, ack)) = maybe_ack {
+                    match aggregating.add(receiver, ack) {
+                        Ok(Some(aggregated)) => {
+                            record_receipt(receiver);
+                            if let Err(e) = store.delete_broadcast(digest) {
+                                error!(error = ?e, "failed to delete pending broadcast");
+                            }
+                            if let Some(duration) = best_effort_delivery {
+                                let remaining = std::mem::take(&mut fut);
+                                tokio::spawn(tokio::time::timeout(
+                                    duration,
+                                    remaining.for_each(|_| async {}),
+                                ));
+                            }
+                            return Ok(Some(aggregated));
+                        },
+                        Ok(None) => {
+                            record_receipt(receiver);
+                            pending.acks.insert(receiver, dag_msg);
+                            if let Err(e) = store.save_broadcast(digest, &pending) {
+                                error!(error = ?e, "failed to save pending broadcast");
+                            }
+                            continue;
+                        },
+                        Err(e) => error!(error = ?e, "rejected broadcast ack from {}", receiver),
+                    }
+                }
+                // the rpc failed or the ack was invalid, retry the receiver
+                let attempt = attempts.entry(receiver).or_insert(0);
+                *attempt += 1;
+                let delay = backoff.delay(*attempt, &mut rand::thread_rng());
+                fut.push(send_message(receiver, network_message.clone(), Some(delay)));
+            }
+            bail!(
+                "{} broadcast ran out of receivers without aggregating",
+                kind
+            )
+        }
+    }
+
+    /// Resolves once the current round reaches the expiry round, never without one.
+    async fn expired(current_round: &mut watch::Receiver<Round>, expiry_round: Option<Round>) {
+        if let Some(expiry_round) = expiry_round {
+            while *current_round.borrow() < expiry_round {
+                if current_round.changed().await.is_err() {
+                    break;
                 }
             }
-            unreachable!("Should aggregate with all responses");
+            if *current_round.borrow() >= expiry_round {
+                return;
+            }
         }
+        pending().await
     }
 }
 
@@ -144,12 +464,7 @@ impl NodeBroadcastHandler {
         if !missing_parents.is_empty() {
             // For each missing parent, verify their signatures and voting power
             ensure!(
-                missing_parents.iter().all(|parent| {
-                    let node_digest = NodeDigest::new(*parent.metadata().digest());
-                    self.verifier
-                        .verify_multi_signatures(&node_digest, parent.signatures())
-                        .is_ok()
-                }),
+                verify_certificates(&missing_parents, &self.verifier).is_ok(),
                 NodeBroadcastHandleError::InvalidParent
             );
             // TODO: notify dag fetcher to fetch missing node and drop this node
@@ -160,11 +475,12 @@ impl NodeBroadcastHandler {
     }
 }
 
+#[async_trait]
 impl RpcHandler for NodeBroadcastHandler {
     type Request = Node;
     type Response = NodeDigestSignature;
 
-    fn process(&mut self, node: Self::Request) -> anyhow::Result<Self::Response> {
+    async fn process(&mut self, node: Self::Request) -> anyhow::Result<Self::Response> {
         self.validate(&node)?;
 
         let signatures_by_peer = self
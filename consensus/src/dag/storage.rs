@@ -0,0 +1,164 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    consensusdb::ConsensusDB,
+    dag::types::{CertifiedNode, DAGMessage},
+};
+use aptos_consensus_types::common::{Author, Round};
+use aptos_crypto::HashValue;
+use aptos_infallible::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// A reliable broadcast that has not aggregated yet, together with the acks received so far.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PendingBroadcast {
+    pub message: DAGMessage,
+    pub acks: BTreeMap<Author, DAGMessage>,
+}
+
+impl PendingBroadcast {
+    pub fn new(message: DAGMessage) -> Self {
+        Self {
+            message,
+            acks: BTreeMap::new(),
+        }
+    }
+}
+
+/// Identifies a broadcast across restarts by the hash of the message being broadcast.
+pub fn broadcast_digest(message: &DAGMessage) -> HashValue {
+    HashValue::sha3_256_of(&bcs::to_bytes(message).expect("Unable to serialize message"))
+}
+
+/// Durable record of in-flight reliable broadcasts, so that a restarted node can resume them
+/// instead of stalling the DAG.
+pub trait BroadcastStore: Send + Sync {
+    fn save_broadcast(&self, digest: HashValue, broadcast: &PendingBroadcast)
+        -> anyhow::Result<()>;
+
+    fn delete_broadcast(&self, digest: HashValue) -> anyhow::Result<()>;
+
+    fn get_broadcast(&self, digest: HashValue) -> anyhow::Result<Option<PendingBroadcast>>;
+
+    fn get_broadcasts(&self) -> anyhow::Result<HashMap<HashValue, PendingBroadcast>>;
+}
+
+/// Keeps pending broadcasts in memory only, i.e. they are lost on restart.
+#[derive(Default)]
+pub struct InMemBroadcastStore {
+    broadcasts: Mutex<HashMap<HashValue, PendingBroadcast>>,
+}
+
+impl BroadcastStore for InMemBroadcastStore {
+    fn save_broadcast(
+        &self,
+        digest: HashValue,
+        broadcast: &PendingBroadcast,
+    ) -> anyhow::Result<()> {
+        self.broadcasts.lock().insert(digest, broadcast.clone());
+        Ok(())
+    }
+
+    fn delete_broadcast(&self, digest: HashValue) -> anyhow::Result<()> {
+        self.broadcasts.lock().remove(&digest);
+        Ok(())
+    }
+
+    fn get_broadcast(&self, digest: HashValue) -> anyhow::Result<Option<PendingBroadcast>> {
+        Ok(self.broadcasts.lock().get(&digest).cloned())
+    }
+
+    fn get_broadcasts(&self) -> anyhow::Result<HashMap<HashValue, PendingBroadcast>> {
+        Ok(self.broadcasts.lock().clone())
+    }
+}
+
+impl BroadcastStore for ConsensusDB {
+    fn save_broadcast(
+        &self,
+        digest: HashValue,
+        broadcast: &PendingBroadcast,
+    ) -> anyhow::Result<()> {
+        Ok(self.save_dag_broadcast(digest, bcs::to_bytes(broadcast)?)?)
+    }
+
+    fn delete_broadcast(&self, digest: HashValue) -> anyhow::Result<()> {
+        Ok(self.delete_dag_broadcast(digest)?)
+    }
+
+    fn get_broadcast(&self, digest: HashValue) -> anyhow::Result<Option<PendingBroadcast>> {
+        self.get_dag_broadcast(digest)?
+            .map(|bytes| bcs::from_bytes(&bytes))
+            .transpose()
+            .map_err(Into::into)
+    }
+
+    fn get_broadcasts(&self) -> anyhow::Result<HashMap<HashValue, PendingBroadcast>> {
+        let mut broadcasts = HashMap::new();
+        for (digest, bytes) in self.get_dag_broadcasts()? {
+            broadcasts.insert(digest, bcs::from_bytes(&bytes)?);
+        }
+        Ok(broadcasts)
+    }
+}
+
+/// Durable record of the certified nodes in the DAG, so that a restarted node can rebuild its
+/// DAG instead of fetching it again from its peers.
+pub trait NodeStore: Send + Sync {
+    fn save_node(&self, node: &CertifiedNode) -> anyhow::Result<()>;
+
+    fn delete_nodes(&self, keys: Vec<(Round, Author)>) -> anyhow::Result<()>;
+
+    /// Returns all nodes in round order.
+    fn get_nodes(&self) -> anyhow::Result<Vec<CertifiedNode>>;
+}
+
+#[derive(Default)]
+pub struct InMemNodeStore {
+    nodes: Mutex<BTreeMap<(Round, Author), CertifiedNode>>,
+}
+
+impl NodeStore for InMemNodeStore {
+    fn save_node(&self, node: &CertifiedNode) -> anyhow::Result<()> {
+        self.nodes.lock().insert(
+            (node.metadata().round(), *node.metadata().author()),
+            node.clone(),
+        );
+        Ok(())
+    }
+
+    fn delete_nodes(&self, keys: Vec<(Round, Author)>) -> anyhow::Result<()> {
+        let mut nodes = self.nodes.lock();
+        for key in keys {
+            nodes.remove(&key);
+        }
+        Ok(())
+    }
+
+    fn get_nodes(&self) -> anyhow::Result<Vec<CertifiedNode>> {
+        Ok(self.nodes.lock().values().cloned().collect())
+    }
+}
+
+impl NodeStore for ConsensusDB {
+    fn save_node(&self, node: &CertifiedNode) -> anyhow::Result<()> {
+        Ok(self.save_dag_node(
+            (node.metadata().round(), *node.metadata().author()),
+            bcs::to_bytes(node)?,
+        )?)
+    }
+
+    fn delete_nodes(&self, keys: Vec<(Round, Author)>) -> anyhow::Result<()> {
+        Ok(self.delete_dag_nodes(keys)?)
+    }
+
+    fn get_nodes(&self) -> anyhow::Result<Vec<CertifiedNode>> {
+        let mut nodes = vec![];
+        for (_, bytes) in self.get_dag_nodes()? {
+            nodes.push(bcs::from_bytes(&bytes)?);
+        }
+        Ok(nodes)
+    }
+}
@@ -0,0 +1,861 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    dag::{
+        anchor_selection::{AnchorSelector, RoundRobinAnchorSelector},
+        commit_rule::{CausalOrderCommitRule, CommitRule},
+        dag_driver::{
+            AdaptiveBatchConfig, AdaptiveBatchSize, AdaptiveRoundTimeout, AdaptiveTimeoutConfig,
+            BackpressureLevel, DagDriver, DagHealth,
+        },
+        dag_fetcher::{DagFetcher, FetchCallback},
+        dag_handler::{NetworkHandler, PeerRateLimiter, RateLimitConfig},
+        dag_network::DAGNetworkSender,
+        dag_store::Dag,
+        reliable_broadcast::{BackoffConfig, ReliableBroadcast},
+        storage::{broadcast_digest, BroadcastStore, InMemBroadcastStore, PendingBroadcast},
+        tests::dag_test::new_certified_node,
+        types::{
+            CertifiedNode, DAGMessage, FetchRequest, Node, NodeCertificate, NodeDigestSignature,
+            RoundTimeout, RoundTimeoutAck, TDAGMessage,
+        },
+    },
+    error::QuorumStoreError,
+    network::{IncomingDAGRequest, TConsensusMsg},
+    network_interface::ConsensusMsg,
+    state_replication::PayloadClient,
+    test_utils::MockPayloadManager,
+    util::mock_time_service::SimulatedTimeService,
+};
+use aptos_channels::{aptos_channel, message_queues::QueueStyle};
+use aptos_consensus_types::common::{Author, Payload, PayloadFilter, Round};
+use aptos_infallible::{Mutex, RwLock};
+use aptos_network::ProtocolId;
+use aptos_types::{
+    epoch_state::EpochState,
+    validator_signer::ValidatorSigner,
+    validator_verifier::{random_validator_verifier, ValidatorVerifier},
+};
+use async_trait::async_trait;
+use futures::{
+    channel::oneshot,
+    future::{pending, BoxFuture},
+    FutureExt, StreamExt,
+};
+use std::{sync::Arc, time::Duration};
+
+/// Never responds, so broadcasts started by the driver stay pending.
+struct MockDAGSender;
+
+#[async_trait]
+impl DAGNetworkSender for MockDAGSender {
+    async fn send_rpc(
+        &self,
+        _receiver: Author,
+        _message: ConsensusMsg,
+        _timeout: Duration,
+    ) -> anyhow::Result<ConsensusMsg> {
+        pending().await
+    }
+
+    async fn send_rpc_with_fallbacks(
+        &self,
+        _responders: Vec<Author>,
+        _message: ConsensusMsg,
+        _timeout: Duration,
+    ) -> anyhow::Result<ConsensusMsg> {
+        unimplemented!();
+    }
+}
+
+/// The driver under test along with the ends of its channels.
+struct TestDriver {
+    driver: DagDriver,
+    time_service: SimulatedTimeService,
+    timeout_rx: aptos_channels::Receiver<Round>,
+    ordered_nodes_rx: aptos_channels::Receiver<Vec<Arc<CertifiedNode>>>,
+}
+
+/// Creates the driver of the given validator at round 1 of epoch 1, with a 1s round timeout and
+/// a reliable broadcast whose peers never respond.
+fn new_driver(
+    signer: &ValidatorSigner,
+    validator_verifier: &ValidatorVerifier,
+    dag: Arc<RwLock<Dag>>,
+    anchor_selector: Arc<dyn AnchorSelector>,
+    commit_rule: Box<dyn CommitRule>,
+) -> TestDriver {
+    new_driver_with_store(
+        signer,
+        validator_verifier,
+        dag,
+        anchor_selector,
+        commit_rule,
+        Arc::new(InMemBroadcastStore::default()),
+        Arc::new(MockPayloadManager::new(None)),
+    )
+}
+
+/// Like `new_driver`, with the pending broadcasts of the given store and the given payload client.
+fn new_driver_with_store(
+    signer: &ValidatorSigner,
+    validator_verifier: &ValidatorVerifier,
+    dag: Arc<RwLock<Dag>>,
+    anchor_selector: Arc<dyn AnchorSelector>,
+    commit_rule: Box<dyn CommitRule>,
+    store: Arc<dyn BroadcastStore>,
+    payload_client: Arc<dyn PayloadClient>,
+) -> TestDriver {
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier.clone(),
+    });
+    let time_service = SimulatedTimeService::new();
+    let rb = Arc::new(ReliableBroadcast::new(
+        validator_verifier.get_ordered_account_addresses(),
+        Arc::new(MockDAGSender),
+        BackoffConfig::default(),
+        Arc::new(time_service.clone()),
+        store,
+    ));
+    let (timeout_tx, timeout_rx) = aptos_channels::new_test(10);
+    let (ordered_nodes_tx, ordered_nodes_rx) = aptos_channels::new_test(10);
+    let driver = DagDriver::new(
+        Arc::new(signer.clone()),
+        epoch_state,
+        dag,
+        payload_client,
+        rb,
+        1,
+        Arc::new(time_service.clone()),
+        Duration::from_secs(1),
+        timeout_tx,
+        anchor_selector,
+        commit_rule,
+        ordered_nodes_tx,
+    );
+    TestDriver {
+        driver,
+        time_service,
+        timeout_rx,
+        ordered_nodes_rx,
+    }
+}
+
+fn round_robin(validator_verifier: &ValidatorVerifier) -> Arc<dyn AnchorSelector> {
+    Arc::new(RoundRobinAnchorSelector::new(
+        validator_verifier.get_ordered_account_addresses(),
+    ))
+}
+
+#[tokio::test]
+async fn test_round_timeout_certificate() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let author_to_index = validator_verifier.address_to_validator_index().clone();
+    let dag = Arc::new(RwLock::new(Dag::new(author_to_index, 0)));
+    let store = Arc::new(InMemBroadcastStore::default());
+    let TestDriver {
+        mut driver,
+        mut time_service,
+        mut timeout_rx,
+        ..
+    } = new_driver_with_store(
+        &signers[0],
+        &validator_verifier,
+        dag,
+        round_robin(&validator_verifier),
+        Box::new(CausalOrderCommitRule::new(validator_verifier.clone())),
+        store.clone(),
+        Arc::new(MockPayloadManager::new(None)),
+    );
+
+    // only one node arrives in round 1, which is not enough to advance
+    assert!(driver
+        .add_node(new_certified_node(1, signers[0].author(), vec![]))
+        .is_ok());
+    assert!(driver
+        .add_round_timeout(RoundTimeout::new(1, 1, &signers[1]).unwrap())
+        .unwrap()
+        .is_none());
+    // timeouts more than a round ahead are rejected
+    assert!(driver
+        .add_round_timeout(RoundTimeout::new(1, 3, &signers[1]).unwrap())
+        .is_err());
+    assert!(timeout_rx.next().now_or_never().is_none());
+
+    // the deadline passes and the driver times out on the round as well, still short of a quorum
+    time_service.update_auto_advance_limit(Duration::from_secs(1));
+    assert_eq!(timeout_rx.next().await, Some(1));
+    assert!(driver.process_round_timeout(1).unwrap().is_none());
+    assert_eq!(driver.dag_health().current_round, 1);
+
+    // the next timeout completes the quorum and the driver moves on
+    let certificate = driver
+        .add_round_timeout(RoundTimeout::new(1, 1, &signers[2]).unwrap())
+        .unwrap()
+        .unwrap();
+    assert_eq!(certificate.round(), 1);
+    assert!(certificate.verify(&validator_verifier).is_ok());
+    assert_eq!(driver.dag_health().current_round, 2);
+
+    // the node of round 2 has the single node of round 1 as parent, and carries the certificate
+    // for the peers to accept it
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let node = store
+        .get_broadcasts()
+        .unwrap()
+        .into_values()
+        .find_map(|pending| match pending.message {
+            DAGMessage::NodeMsg(node) if node.metadata().round() == 2 => Some(node),
+            _ => None,
+        })
+        .unwrap();
+    assert_eq!(node.parents().len(), 1);
+    assert_eq!(
+        node.timeout_certificate()
+            .map(|certificate| certificate.round()),
+        Some(1)
+    );
+    assert!(node.verify(&validator_verifier).is_ok());
+
+    // late timeouts of the round are ignored
+    assert!(driver
+        .add_round_timeout(RoundTimeout::new(1, 1, &signers[3]).unwrap())
+        .unwrap()
+        .is_none());
+}
+
+/// Hands the timeout to the handler as an rpc from its author, and returns the response.
+async fn send_round_timeout(
+    handler: &mut NetworkHandler,
+    timeout: RoundTimeout,
+) -> anyhow::Result<ConsensusMsg> {
+    let (response_tx, response_rx) = oneshot::channel();
+    handler
+        .process_rpc(IncomingDAGRequest {
+            sender: *timeout.author(),
+            req: DAGMessage::from(timeout).into_network_message(),
+            protocol: ProtocolId::ConsensusRpcBcs,
+            response_sender: response_tx,
+        })
+        .await?;
+    let response = response_rx.await??;
+    ProtocolId::ConsensusRpcBcs.from_bytes(&response)
+}
+
+#[tokio::test]
+async fn test_round_timeouts_from_network() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let author_to_index = validator_verifier.address_to_validator_index().clone();
+    let dag = Arc::new(RwLock::new(Dag::new(author_to_index, 0)));
+    let mut driver = new_driver(
+        &signers[0],
+        &validator_verifier,
+        dag.clone(),
+        round_robin(&validator_verifier),
+        Box::new(CausalOrderCommitRule::new(validator_verifier.clone())),
+    )
+    .driver;
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier.clone(),
+    });
+    let (_rpc_tx, rpc_rx) = aptos_channel::new(QueueStyle::FIFO, 10, None);
+    let (round_timeout_tx, mut round_timeout_rx) = aptos_channels::new_test(10);
+    let mut handler = NetworkHandler::new(
+        dag,
+        rpc_rx,
+        signers[0].clone(),
+        epoch_state,
+        PeerRateLimiter::new(RateLimitConfig::default()),
+    )
+    .with_round_timeout_sender(round_timeout_tx);
+
+    // a timeout of another epoch is rejected without reaching the driver
+    let timeout = RoundTimeout::new(2, 1, &signers[1]).unwrap();
+    assert!(send_round_timeout(&mut handler, timeout).await.is_err());
+    assert!(round_timeout_rx.next().now_or_never().is_none());
+
+    // the peers time out on round 1, the handler acks their timeouts and passes them on
+    for signer in &signers[1..] {
+        let timeout = RoundTimeout::new(1, 1, signer).unwrap();
+        let response = send_round_timeout(&mut handler, timeout).await.unwrap();
+        assert!(RoundTimeoutAck::try_from(DAGMessage::try_from(response).unwrap()).is_ok());
+        let timeout = round_timeout_rx.next().await.unwrap();
+        assert_eq!(timeout.author(), &signer.author());
+        assert!(driver.add_round_timeout(timeout).is_ok());
+    }
+    assert_eq!(driver.dag_health().current_round, 2);
+}
+
+/// Picks the node of the same validator in every round.
+struct FixedAnchorSelector(Author);
+
+impl AnchorSelector for FixedAnchorSelector {
+    fn anchor_author(&self, _round: Round) -> Option<Author> {
+        Some(self.0)
+    }
+}
+
+/// Keeps every anchor uncommitted.
+struct NoCommitRule;
+
+impl CommitRule for NoCommitRule {
+    fn is_committed(&self, _anchor: &CertifiedNode, _dag: &Dag) -> bool {
+        false
+    }
+
+    fn order_history(
+        &mut self,
+        _anchor: &Arc<CertifiedNode>,
+        _dag: &Dag,
+    ) -> Vec<Arc<CertifiedNode>> {
+        unreachable!("no anchor is committed")
+    }
+}
+
+#[tokio::test]
+async fn test_custom_anchor_selector() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let author_to_index = validator_verifier.address_to_validator_index().clone();
+    let dag = Arc::new(RwLock::new(Dag::new(author_to_index, 0)));
+    let anchor_author = signers[2].author();
+    let TestDriver { mut driver, .. } = new_driver(
+        &signers[0],
+        &validator_verifier,
+        dag.clone(),
+        Arc::new(FixedAnchorSelector(anchor_author)),
+        Box::new(NoCommitRule),
+    );
+
+    let mut parents = vec![];
+    for round in 1..=3 {
+        for signer in &signers[..3] {
+            assert!(driver
+                .add_node(new_certified_node(round, signer.author(), parents.clone()))
+                .is_ok());
+        }
+        parents = dag
+            .read()
+            .get_strong_links_for_round(round, &validator_verifier)
+            .unwrap();
+    }
+
+    // the round robin selector would have picked anchors in even rounds only
+    let anchors: Vec<_> = driver
+        .uncommitted_anchors()
+        .map(|anchor| (anchor.metadata().round(), *anchor.metadata().author()))
+        .collect();
+    assert_eq!(anchors, vec![
+        (1, anchor_author),
+        (2, anchor_author),
+        (3, anchor_author)
+    ]);
+}
+
+/// Commits every anchor right away, ordering its parents by descending author before it.
+struct ReverseParentsCommitRule;
+
+impl CommitRule for ReverseParentsCommitRule {
+    fn is_committed(&self, _anchor: &CertifiedNode, _dag: &Dag) -> bool {
+        true
+    }
+
+    fn order_history(&mut self, anchor: &Arc<CertifiedNode>, dag: &Dag) -> Vec<Arc<CertifiedNode>> {
+        let mut ordered_nodes: Vec<_> = anchor
+            .parents()
+            .iter()
+            .filter_map(|parent| dag.get_node(parent.metadata().digest()))
+            .collect();
+        ordered_nodes.sort_by_key(|node| std::cmp::Reverse(*node.metadata().author()));
+        ordered_nodes.push(anchor.clone());
+        ordered_nodes
+    }
+}
+
+#[tokio::test]
+async fn test_custom_commit_rule() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let author_to_index = validator_verifier.address_to_validator_index().clone();
+    let dag = Arc::new(RwLock::new(Dag::new(author_to_index, 0)));
+    let anchor_author = signers[0].author();
+    let TestDriver {
+        mut driver,
+        mut ordered_nodes_rx,
+        ..
+    } = new_driver(
+        &signers[0],
+        &validator_verifier,
+        dag.clone(),
+        Arc::new(FixedAnchorSelector(anchor_author)),
+        Box::new(ReverseParentsCommitRule),
+    );
+
+    let mut parents = vec![];
+    for round in 1..=2 {
+        for signer in &signers[..3] {
+            assert!(driver
+                .add_node(new_certified_node(round, signer.author(), parents.clone()))
+                .is_ok());
+        }
+        parents = dag
+            .read()
+            .get_strong_links_for_round(round, &validator_verifier)
+            .unwrap();
+    }
+
+    let ordered = |nodes: Vec<Arc<CertifiedNode>>| -> Vec<(Round, Author)> {
+        nodes
+            .iter()
+            .map(|node| (node.metadata().round(), *node.metadata().author()))
+            .collect()
+    };
+    assert_eq!(ordered(ordered_nodes_rx.next().await.unwrap()), vec![(
+        1,
+        anchor_author
+    )]);
+    let mut round_one_authors: Vec<_> = signers[..3].iter().map(|signer| signer.author()).collect();
+    round_one_authors.sort_by(|a, b| b.cmp(a));
+    let mut expected: Vec<_> = round_one_authors
+        .into_iter()
+        .map(|author| (1, author))
+        .collect();
+    expected.push((2, anchor_author));
+    assert_eq!(ordered(ordered_nodes_rx.next().await.unwrap()), expected);
+    assert_eq!(driver.uncommitted_anchors().count(), 0);
+}
+
+#[tokio::test]
+async fn test_indirect_commit() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let author_to_index = validator_verifier.address_to_validator_index().clone();
+    let dag = Arc::new(RwLock::new(Dag::new(author_to_index, 0)));
+    // every even round has an anchor of the first validator
+    let anchor_author = signers[0].author();
+    let TestDriver {
+        mut driver,
+        mut ordered_nodes_rx,
+        ..
+    } = new_driver(
+        &signers[0],
+        &validator_verifier,
+        dag.clone(),
+        Arc::new(RoundRobinAnchorSelector::new(vec![anchor_author])),
+        Box::new(CausalOrderCommitRule::new(validator_verifier.clone())),
+    );
+    let add_round = |driver: &mut DagDriver, round, parents: &[NodeCertificate]| {
+        signers
+            .iter()
+            .map(|signer| {
+                let node = new_certified_node(round, signer.author(), parents.to_vec());
+                let certificate = node.certificate().clone();
+                assert!(driver.add_node(node).is_ok());
+                certificate
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let round_one = add_round(&mut driver, 1, &[]);
+    let round_two = add_round(&mut driver, 2, &round_one);
+    // only the anchor's own node links to the anchor of round 2, too few to commit it directly
+    let round_three: Vec<_> = signers
+        .iter()
+        .map(|signer| {
+            let parents = if signer.author() == anchor_author {
+                round_two[..3].to_vec()
+            } else {
+                round_two[1..].to_vec()
+            };
+            let node = new_certified_node(3, signer.author(), parents);
+            let certificate = node.certificate().clone();
+            assert!(driver.add_node(node).is_ok());
+            certificate
+        })
+        .collect();
+    let round_four = add_round(&mut driver, 4, &round_three);
+    assert!(ordered_nodes_rx.next().now_or_never().is_none());
+
+    // the anchor of round 4 commits directly, and has a path to the anchor of round 2
+    add_round(&mut driver, 5, &round_four);
+    let ordered = |nodes: Vec<Arc<CertifiedNode>>| -> Vec<(Round, Author)> {
+        nodes
+            .iter()
+            .map(|node| (node.metadata().round(), *node.metadata().author()))
+            .collect()
+    };
+    let mut authors: Vec<_> = signers.iter().map(|signer| signer.author()).collect();
+    authors.sort();
+    let mut expected: Vec<_> = authors.iter().map(|author| (1, *author)).collect();
+    expected.push((2, anchor_author));
+    assert_eq!(ordered(ordered_nodes_rx.next().await.unwrap()), expected);
+    let mut expected: Vec<_> = authors
+        .iter()
+        .filter(|author| **author != anchor_author)
+        .map(|author| (2, *author))
+        .collect();
+    expected.extend(authors.iter().map(|author| (3, *author)));
+    expected.push((4, anchor_author));
+    assert_eq!(ordered(ordered_nodes_rx.next().await.unwrap()), expected);
+    assert_eq!(driver.uncommitted_anchors().count(), 0);
+}
+
+#[tokio::test]
+async fn test_prune_on_commit() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let author_to_index = validator_verifier.address_to_validator_index().clone();
+    let dag = Arc::new(RwLock::new(
+        Dag::new(author_to_index, 0).with_retention_rounds(2),
+    ));
+    let TestDriver { mut driver, .. } = new_driver(
+        &signers[0],
+        &validator_verifier,
+        dag.clone(),
+        Arc::new(FixedAnchorSelector(signers[0].author())),
+        Box::new(CausalOrderCommitRule::new(validator_verifier.clone())),
+    );
+
+    let mut parents = vec![];
+    let mut round_one = vec![];
+    for round in 1..=6 {
+        for signer in &signers[..3] {
+            assert!(driver
+                .add_node(new_certified_node(round, signer.author(), parents.clone()))
+                .is_ok());
+        }
+        parents = dag
+            .read()
+            .get_strong_links_for_round(round, &validator_verifier)
+            .unwrap();
+        if round == 1 {
+            round_one = parents.clone();
+        }
+    }
+
+    // completing round 6 commits the anchor of round 5, rounds below 3 fall out of the window
+    let dag_reader = dag.read();
+    assert_eq!(dag_reader.rounds_range(), (3, 6));
+    assert_eq!(dag_reader.num_nodes(), 12);
+    assert!(round_one
+        .iter()
+        .all(|certificate| !dag_reader.exists(certificate.metadata().digest())));
+}
+
+#[tokio::test]
+async fn test_backpressure_level() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let author_to_index = validator_verifier.address_to_validator_index().clone();
+    let node_size =
+        bcs::serialized_size(&new_certified_node(1, signers[0].author(), vec![])).unwrap();
+    // room for a little more than one round of three nodes
+    let dag = Arc::new(RwLock::new(
+        Dag::new(author_to_index, 0).with_max_bytes(3 * node_size + 1),
+    ));
+    let TestDriver { mut driver, .. } = new_driver(
+        &signers[0],
+        &validator_verifier,
+        dag,
+        round_robin(&validator_verifier),
+        Box::new(CausalOrderCommitRule::new(validator_verifier.clone())),
+    );
+    assert_eq!(driver.backpressure_level(), BackpressureLevel::None);
+
+    // a node a few rounds ahead shows the DAG is behind
+    let missing_parent = new_certified_node(5, signers[1].author(), vec![])
+        .certificate()
+        .clone();
+    assert!(driver
+        .add_node(new_certified_node(6, signers[1].author(), vec![
+            missing_parent
+        ]))
+        .is_ok());
+    assert_eq!(driver.backpressure_level(), BackpressureLevel::Moderate);
+
+    // the uncommitted nodes of round 1 fill the store
+    for signer in &signers[..3] {
+        assert!(driver
+            .add_node(new_certified_node(1, signer.author(), vec![]))
+            .is_ok());
+    }
+    assert_eq!(driver.backpressure_level(), BackpressureLevel::High);
+}
+
+#[test]
+fn test_adaptive_round_timeout() {
+    let config = AdaptiveTimeoutConfig {
+        smoothing: 0.2,
+        multiplier: 2.0,
+        min_timeout: Duration::from_millis(100),
+        max_timeout: Duration::from_secs(5),
+    };
+    let mut round_timeout = AdaptiveRoundTimeout::new(Duration::from_secs(1), config);
+    assert_eq!(round_timeout.timeout(), Duration::from_secs(1));
+
+    // a slow start, then rounds settle around 300ms
+    round_timeout.record_round_latency(Duration::from_secs(2));
+    assert_eq!(round_timeout.timeout(), Duration::from_secs(4));
+    for latency in [280, 320].iter().cycle().take(40) {
+        round_timeout.record_round_latency(Duration::from_millis(*latency));
+    }
+    let timeout = round_timeout.timeout().as_millis();
+    assert!((580..=620).contains(&timeout), "timeout {}ms", timeout);
+
+    // bounded on both ends
+    for _ in 0..40 {
+        round_timeout.record_round_latency(Duration::from_millis(10));
+    }
+    assert_eq!(round_timeout.timeout(), Duration::from_millis(100));
+    round_timeout.record_round_latency(Duration::from_secs(60));
+    assert_eq!(round_timeout.timeout(), Duration::from_secs(5));
+}
+
+#[test]
+fn test_adaptive_batch_size() {
+    let config = AdaptiveBatchConfig {
+        min_batch_size: 100,
+        max_batch_size: 1000,
+        max_batch_bytes: 1024,
+        target_round_latency: Duration::from_millis(500),
+        increase_step: 200,
+        decrease_factor: 0.5,
+    };
+    let mut batch_size = AdaptiveBatchSize::new(config);
+    assert_eq!(batch_size.batch_size(), 100);
+    let fast = Duration::from_millis(200);
+    let slow = Duration::from_secs(1);
+
+    // idle, the batch grows up to the max
+    let mut sizes = vec![];
+    for _ in 0..6 {
+        batch_size.record_round(BackpressureLevel::None, fast);
+        sizes.push(batch_size.batch_size());
+    }
+    assert_eq!(sizes, vec![300, 500, 700, 900, 1000, 1000]);
+
+    // moderate backpressure holds it, high backpressure or slow rounds shrink it
+    batch_size.record_round(BackpressureLevel::Moderate, fast);
+    assert_eq!(batch_size.batch_size(), 1000);
+    batch_size.record_round(BackpressureLevel::High, fast);
+    assert_eq!(batch_size.batch_size(), 500);
+    batch_size.record_round(BackpressureLevel::None, slow);
+    assert_eq!(batch_size.batch_size(), 250);
+    batch_size.record_round(BackpressureLevel::Moderate, slow);
+    assert_eq!(batch_size.batch_size(), 125);
+    for _ in 0..5 {
+        batch_size.record_round(BackpressureLevel::High, slow);
+    }
+    assert_eq!(batch_size.batch_size(), 100);
+
+    // and it recovers once the load is gone
+    batch_size.record_round(BackpressureLevel::None, fast);
+    assert_eq!(batch_size.batch_size(), 300);
+}
+
+/// Records the limits of every payload pull, and returns an empty payload.
+#[derive(Default)]
+struct RecordingPayloadClient {
+    pulls: Mutex<Vec<(u64, u64)>>,
+}
+
+#[async_trait]
+impl PayloadClient for RecordingPayloadClient {
+    async fn pull_payload(
+        &self,
+        _max_poll_time: Duration,
+        max_items: u64,
+        max_bytes: u64,
+        _exclude: PayloadFilter,
+        _wait_callback: BoxFuture<'static, ()>,
+        _pending_ordering: bool,
+        _pending_uncommitted_blocks: usize,
+        _recent_max_fill_fraction: f32,
+    ) -> Result<Payload, QuorumStoreError> {
+        self.pulls.lock().push((max_items, max_bytes));
+        Ok(Payload::empty(false))
+    }
+}
+
+#[tokio::test]
+async fn test_pull_payload_with_batch_size() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let author_to_index = validator_verifier.address_to_validator_index().clone();
+    let dag = Arc::new(RwLock::new(Dag::new(author_to_index, 0)));
+    let payload_client = Arc::new(RecordingPayloadClient::default());
+    let mut driver = new_driver_with_store(
+        &signers[0],
+        &validator_verifier,
+        dag,
+        round_robin(&validator_verifier),
+        Box::new(CausalOrderCommitRule::new(validator_verifier.clone())),
+        Arc::new(InMemBroadcastStore::default()),
+        payload_client.clone(),
+    )
+    .driver
+    .with_adaptive_batch_size(AdaptiveBatchConfig {
+        min_batch_size: 100,
+        max_batch_size: 1000,
+        max_batch_bytes: 1024,
+        target_round_latency: Duration::from_millis(500),
+        increase_step: 200,
+        decrease_factor: 0.5,
+    });
+
+    // round 1 completes right away, the node of round 2 asks for the grown batch
+    for signer in &signers[..3] {
+        assert!(driver
+            .add_node(new_certified_node(1, signer.author(), vec![]))
+            .is_ok());
+    }
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(driver.dag_health().current_round, 2);
+    assert_eq!(*payload_client.pulls.lock(), vec![(300, 1024)]);
+}
+
+#[tokio::test]
+async fn test_dag_health() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let author_to_index = validator_verifier.address_to_validator_index().clone();
+    let dag = Arc::new(RwLock::new(Dag::new(author_to_index, 0)));
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier.clone(),
+    });
+    let (fetcher, fetch_tx) = DagFetcher::new(
+        epoch_state,
+        Arc::new(MockDAGSender),
+        dag.clone(),
+        Arc::new(SimulatedTimeService::new()),
+        1,
+    );
+    let pending_fetches = fetcher.pending_fetches();
+    let fetch_queue_depth = fetcher.queue_depth();
+    tokio::spawn(fetcher.start());
+    let mut driver = new_driver(
+        &signers[0],
+        &validator_verifier,
+        dag,
+        round_robin(&validator_verifier),
+        Box::new(CausalOrderCommitRule::new(validator_verifier.clone())),
+    )
+    .driver
+    .with_pending_fetches(pending_fetches.clone())
+    .with_fetch_queue_depth(fetch_queue_depth);
+
+    // round 1 completes and the node of round 2 is broadcast, the peers never ack it
+    for signer in &signers[..3] {
+        assert!(driver
+            .add_node(new_certified_node(1, signer.author(), vec![]))
+            .is_ok());
+    }
+    // nodes of later rounds wait on their parents, their authors never answer the fetches and
+    // the second fetch queues behind the first
+    for signer in &signers[1..3] {
+        let node = Node::new(1, 5, signer.author(), 0, Payload::empty(false), vec![]);
+        let request = FetchRequest::new(node.metadata().clone(), 1, vec![]);
+        let (callback_tx, _callback_rx) = tokio::sync::oneshot::channel();
+        assert!(fetch_tx
+            .send((request, FetchCallback::Node(node, callback_tx)))
+            .await
+            .is_ok());
+    }
+    while pending_fetches.len() < 2 {
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+    tokio::time::sleep(Duration::from_millis(10)).await;
+
+    assert_eq!(driver.dag_health(), DagHealth {
+        current_round: 2,
+        lowest_round: 0,
+        highest_round: 1,
+        num_nodes: 3,
+        pending_fetches: 2,
+        backpressure_level: BackpressureLevel::None,
+        broadcast_backlog: 1,
+        handler_queue_depth: 0,
+        fetch_queue_depth: 1,
+        outstanding_broadcasts: 1,
+    });
+}
+
+#[tokio::test]
+async fn test_wait_for_round_progress() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let author_to_index = validator_verifier.address_to_validator_index().clone();
+    let dag = Arc::new(RwLock::new(Dag::new(author_to_index, 0)));
+    let TestDriver { mut driver, .. } = new_driver(
+        &signers[0],
+        &validator_verifier,
+        dag.clone(),
+        round_robin(&validator_verifier),
+        Box::new(CausalOrderCommitRule::new(validator_verifier.clone())),
+    );
+    for signer in &signers[..2] {
+        assert!(driver
+            .add_node(new_certified_node(1, signer.author(), vec![]))
+            .is_ok());
+    }
+
+    // the node completing the quorum reaches the DAG without going through the driver, e.g.
+    // from the fetcher
+    let add_node = async {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(dag
+            .write()
+            .add_node(new_certified_node(1, signers[2].author(), vec![]))
+            .is_ok());
+    };
+    assert!(tokio::time::timeout(
+        Duration::from_millis(500),
+        futures::future::join(driver.wait_for_round_progress(), add_node),
+    )
+    .await
+    .is_ok());
+    assert_eq!(driver.dag_health().current_round, 2);
+}
+
+#[tokio::test]
+async fn test_resume_pending_broadcasts() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let author_to_index = validator_verifier.address_to_validator_index().clone();
+    let dag = Arc::new(RwLock::new(Dag::new(author_to_index, 0)));
+    let store = Arc::new(InMemBroadcastStore::default());
+    let save_node = |round, signer: &ValidatorSigner, acks: &[ValidatorSigner]| {
+        let node = Node::new(1, round, signer.author(), 0, Payload::empty(false), vec![]);
+        let mut pending = PendingBroadcast::new(DAGMessage::from(node.clone()));
+        for peer in acks {
+            let signature =
+                NodeDigestSignature::new(1, *node.metadata().digest(), node.sign(peer).unwrap());
+            pending
+                .acks
+                .insert(peer.author(), DAGMessage::from(signature));
+        }
+        let digest = broadcast_digest(&pending.message);
+        assert!(store.save_broadcast(digest, &pending).is_ok());
+        digest
+    };
+    // the node of the round before the restart, the node of this round with one ack, and the
+    // node of a peer in this round
+    let stale = save_node(0, &signers[0], &[]);
+    let resumed = save_node(1, &signers[0], &signers[1..2]);
+    let other = save_node(1, &signers[2], &[]);
+
+    let TestDriver { driver, .. } = new_driver_with_store(
+        &signers[0],
+        &validator_verifier,
+        dag,
+        round_robin(&validator_verifier),
+        Box::new(CausalOrderCommitRule::new(validator_verifier.clone())),
+        store.clone(),
+        Arc::new(MockPayloadManager::new(None)),
+    );
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // only the node of this validator in the current round is broadcast again, from its ack
+    assert_eq!(driver.dag_health().outstanding_broadcasts, 1);
+    assert!(store.get_broadcast(stale).unwrap().is_none());
+    assert!(store.get_broadcast(other).unwrap().is_some());
+    let pending = store.get_broadcast(resumed).unwrap().unwrap();
+    assert_eq!(pending.acks.keys().cloned().collect::<Vec<_>>(), vec![
+        signers[1].author()
+    ]);
+}
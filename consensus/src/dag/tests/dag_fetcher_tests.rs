@@ -0,0 +1,670 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    dag::{
+        dag_fetcher::{DagFetcher, FetchCallback, FetchError},
+        dag_network::DAGNetworkSender,
+        dag_store::Dag,
+        types::{
+            BatchFetchRequest, BatchFetchResponse, BatchFetchTarget, CertifiedNode, DAGMessage,
+            FetchRequest, Node, NodeCertificate,
+        },
+    },
+    network::TConsensusMsg,
+    network_interface::ConsensusMsg,
+    util::{mock_time_service::SimulatedTimeService, time_service::TimeService},
+};
+use anyhow::bail;
+use aptos_consensus_types::common::{Author, Payload, Round};
+use aptos_infallible::{Mutex, RwLock};
+use aptos_types::{
+    aggregate_signature::PartialSignatures, epoch_state::EpochState,
+    validator_verifier::random_validator_verifier,
+};
+use async_trait::async_trait;
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::sync::oneshot;
+
+#[derive(Default)]
+struct SlowDAGSender {
+    in_flight: AtomicUsize,
+    max_in_flight: AtomicUsize,
+    num_calls: AtomicUsize,
+}
+
+#[async_trait]
+impl DAGNetworkSender for SlowDAGSender {
+    async fn send_rpc(
+        &self,
+        _receiver: Author,
+        _message: ConsensusMsg,
+        _timeout: Duration,
+    ) -> anyhow::Result<ConsensusMsg> {
+        let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+        self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        self.in_flight.fetch_sub(1, Ordering::SeqCst);
+        self.num_calls.fetch_add(1, Ordering::SeqCst);
+        bail!("simulated failure");
+    }
+
+    async fn send_rpc_with_fallbacks(
+        &self,
+        _responders: Vec<Author>,
+        _message: ConsensusMsg,
+        _timeout: Duration,
+    ) -> anyhow::Result<ConsensusMsg> {
+        unimplemented!();
+    }
+}
+
+#[tokio::test]
+async fn test_fetcher_concurrency_limit() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let author_to_index = validator_verifier.address_to_validator_index().clone();
+    let dag = Arc::new(RwLock::new(Dag::new(author_to_index, 0)));
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let network = Arc::new(SlowDAGSender::default());
+    let (fetcher, request_tx) = DagFetcher::new(
+        epoch_state,
+        network.clone(),
+        dag,
+        Arc::new(SimulatedTimeService::new()),
+        2,
+    );
+    tokio::spawn(fetcher.start());
+
+    let num_requests = 10;
+    for timestamp in 0..num_requests {
+        let node = Node::new(
+            1,
+            1,
+            signers[0].author(),
+            timestamp,
+            Payload::empty(false),
+            vec![],
+        );
+        let request = FetchRequest::new(node.metadata().clone(), 0, vec![]);
+        let (callback_tx, _callback_rx) = oneshot::channel();
+        assert!(request_tx
+            .send((request, FetchCallback::Node(node, callback_tx)))
+            .await
+            .is_ok());
+    }
+    while network.num_calls.load(Ordering::SeqCst) < num_requests as usize {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(network.max_in_flight.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn test_fetcher_dedup_in_flight() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let author_to_index = validator_verifier.address_to_validator_index().clone();
+    let dag = Arc::new(RwLock::new(Dag::new(author_to_index, 0)));
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let network = Arc::new(SlowDAGSender::default());
+    let (fetcher, request_tx) = DagFetcher::new(
+        epoch_state,
+        network.clone(),
+        dag,
+        Arc::new(SimulatedTimeService::new()),
+        2,
+    );
+    tokio::spawn(fetcher.start());
+
+    let node = Node::new(1, 1, signers[0].author(), 0, Payload::empty(false), vec![]);
+    let request = FetchRequest::new(node.metadata().clone(), 0, vec![]);
+    // both requests for the same node arrive before the first fetch completes
+    for _ in 0..2 {
+        let (callback_tx, _callback_rx) = oneshot::channel();
+        assert!(request_tx
+            .send((
+                request.clone(),
+                FetchCallback::Node(node.clone(), callback_tx)
+            ))
+            .await
+            .is_ok());
+    }
+    while network.num_calls.load(Ordering::SeqCst) < 1 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(network.num_calls.load(Ordering::SeqCst), 1);
+
+    // once the fetch is done, a new request for the node issues a new rpc
+    let (callback_tx, _callback_rx) = oneshot::channel();
+    assert!(request_tx
+        .send((request, FetchCallback::Node(node, callback_tx)))
+        .await
+        .is_ok());
+    while network.num_calls.load(Ordering::SeqCst) < 2 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(network.max_in_flight.load(Ordering::SeqCst), 1);
+}
+
+/// Answers every fetch, the slow peer only after a delay on the simulated clock.
+struct SlowPeerDAGSender {
+    slow_peer: Author,
+    calls: Mutex<Vec<Author>>,
+    time_service: Arc<SimulatedTimeService>,
+}
+
+#[async_trait]
+impl DAGNetworkSender for SlowPeerDAGSender {
+    async fn send_rpc(
+        &self,
+        receiver: Author,
+        _message: ConsensusMsg,
+        _timeout: Duration,
+    ) -> anyhow::Result<ConsensusMsg> {
+        if receiver == self.slow_peer {
+            self.time_service.sleep(Duration::from_millis(200)).await;
+        }
+        self.calls.lock().push(receiver);
+        Ok(DAGMessage::from(BatchFetchResponse::new(1, vec![], true)).into_network_message())
+    }
+
+    async fn send_rpc_with_fallbacks(
+        &self,
+        _responders: Vec<Author>,
+        _message: ConsensusMsg,
+        _timeout: Duration,
+    ) -> anyhow::Result<ConsensusMsg> {
+        unimplemented!();
+    }
+}
+
+#[tokio::test]
+async fn test_fetcher_prefers_fast_peer() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let validators = validator_verifier.get_ordered_account_addresses();
+    let author_to_index = validator_verifier.address_to_validator_index().clone();
+    let dag = Arc::new(RwLock::new(Dag::new(author_to_index, 0)));
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier.clone(),
+    });
+
+    let new_certified_node = |timestamp| {
+        let node = Node::new(
+            1,
+            1,
+            signers[0].author(),
+            timestamp,
+            Payload::empty(false),
+            vec![],
+        );
+        let mut partial_sigs = PartialSignatures::empty();
+        for signer in &signers[..2] {
+            partial_sigs.add_signature(signer.author(), node.sign(signer).unwrap());
+        }
+        let certificate = NodeCertificate::new(
+            node.metadata().clone(),
+            validator_verifier
+                .aggregate_signatures(&partial_sigs)
+                .unwrap(),
+        );
+        CertifiedNode::new(node, certificate)
+    };
+    // the slow peer is the one tried first before anything is known about the peers
+    let responders = new_certified_node(0).certificate().signers(&validators);
+    let (slow_peer, fast_peer) = (responders[0], responders[1]);
+
+    let time_service = Arc::new(SimulatedTimeService::new());
+    let network = Arc::new(SlowPeerDAGSender {
+        slow_peer,
+        calls: Mutex::new(vec![]),
+        time_service: time_service.clone(),
+    });
+    let (fetcher, request_tx) = DagFetcher::new(epoch_state, network.clone(), dag, time_service, 1);
+    tokio::spawn(fetcher.start());
+
+    let num_requests = 30;
+    for timestamp in 0..num_requests {
+        let node = new_certified_node(timestamp);
+        let request = FetchRequest::new(node.metadata().clone(), 0, vec![]);
+        let (callback_tx, _callback_rx) = oneshot::channel();
+        assert!(request_tx
+            .send((request, FetchCallback::CertifiedNode(node, callback_tx)))
+            .await
+            .is_ok());
+        // one fetch at a time so that each one sees the scores of the previous ones
+        while network.calls.lock().len() <= timestamp as usize {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+
+    let calls = network.calls.lock().clone();
+    let num_fast = calls[10..]
+        .iter()
+        .filter(|peer| **peer == fast_peer)
+        .count();
+    assert!(
+        num_fast >= 15,
+        "only {} of the last 20 fetches went to the fast peer",
+        num_fast
+    );
+}
+
+/// Records the last round requested by every fetch, each one takes a while and fails.
+struct RecordingDAGSender {
+    rounds: Mutex<Vec<Round>>,
+}
+
+#[async_trait]
+impl DAGNetworkSender for RecordingDAGSender {
+    async fn send_rpc(
+        &self,
+        _receiver: Author,
+        message: ConsensusMsg,
+        _timeout: Duration,
+    ) -> anyhow::Result<ConsensusMsg> {
+        let request: BatchFetchRequest = (TConsensusMsg::from_network_message(message)
+            as anyhow::Result<DAGMessage>)?
+            .try_into()?;
+        match request.target() {
+            BatchFetchTarget::Rounds(_, end_round) => self.rounds.lock().push(*end_round),
+            BatchFetchTarget::Digests(_) => bail!("unexpected target"),
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        bail!("simulated failure");
+    }
+
+    async fn send_rpc_with_fallbacks(
+        &self,
+        _responders: Vec<Author>,
+        _message: ConsensusMsg,
+        _timeout: Duration,
+    ) -> anyhow::Result<ConsensusMsg> {
+        unimplemented!();
+    }
+}
+
+#[tokio::test]
+async fn test_fetcher_lowest_round_first() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let author_to_index = validator_verifier.address_to_validator_index().clone();
+    let dag = Arc::new(RwLock::new(Dag::new(author_to_index, 0)));
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let network = Arc::new(RecordingDAGSender {
+        rounds: Mutex::new(vec![]),
+    });
+    let (fetcher, request_tx) = DagFetcher::new(
+        epoch_state,
+        network.clone(),
+        dag,
+        Arc::new(SimulatedTimeService::new()),
+        1,
+    );
+    tokio::spawn(fetcher.start());
+
+    let new_request = |round| {
+        let node = Node::new(
+            1,
+            round,
+            signers[0].author(),
+            0,
+            Payload::empty(false),
+            vec![],
+        );
+        let request = FetchRequest::new(node.metadata().clone(), 0, vec![]);
+        let (callback_tx, _callback_rx) = oneshot::channel();
+        (request, FetchCallback::Node(node, callback_tx))
+    };
+    // the first fetch takes the only slot, the others queue up behind it
+    assert!(request_tx.send(new_request(5)).await.is_ok());
+    while network.rounds.lock().is_empty() {
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+    for round in [7, 3, 6, 2, 3] {
+        assert!(request_tx.send(new_request(round)).await.is_ok());
+    }
+    while network.rounds.lock().len() < 5 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    // the two requests for round 3 target the same node and share a single fetch, each fetch
+    // asks for the rounds below its target
+    assert_eq!(*network.rounds.lock(), vec![4, 1, 2, 5, 6]);
+}
+
+/// Fails the first fetch, then answers with the given nodes.
+struct FailFirstDAGSender {
+    response: BatchFetchResponse,
+    calls: Mutex<Vec<Author>>,
+}
+
+#[async_trait]
+impl DAGNetworkSender for FailFirstDAGSender {
+    async fn send_rpc(
+        &self,
+        receiver: Author,
+        _message: ConsensusMsg,
+        _timeout: Duration,
+    ) -> anyhow::Result<ConsensusMsg> {
+        let mut calls = self.calls.lock();
+        calls.push(receiver);
+        if calls.len() == 1 {
+            bail!("simulated failure");
+        }
+        Ok(DAGMessage::from(self.response.clone()).into_network_message())
+    }
+
+    async fn send_rpc_with_fallbacks(
+        &self,
+        _responders: Vec<Author>,
+        _message: ConsensusMsg,
+        _timeout: Duration,
+    ) -> anyhow::Result<ConsensusMsg> {
+        unimplemented!();
+    }
+}
+
+#[tokio::test]
+async fn test_fetcher_falls_back_to_next_peer() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let author_to_index = validator_verifier.address_to_validator_index().clone();
+    let dag = Arc::new(RwLock::new(Dag::new(author_to_index, 0)));
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier.clone(),
+    });
+
+    let certify = |node: Node| {
+        let mut partial_sigs = PartialSignatures::empty();
+        for signer in &signers[..3] {
+            partial_sigs.add_signature(signer.author(), node.sign(signer).unwrap());
+        }
+        let certificate = NodeCertificate::new(
+            node.metadata().clone(),
+            validator_verifier
+                .aggregate_signatures(&partial_sigs)
+                .unwrap(),
+        );
+        CertifiedNode::new(node, certificate)
+    };
+    let parents: Vec<_> = signers[..3]
+        .iter()
+        .map(|signer| {
+            certify(Node::new(
+                1,
+                1,
+                signer.author(),
+                0,
+                Payload::empty(false),
+                vec![],
+            ))
+        })
+        .collect();
+    let target = certify(Node::new(
+        1,
+        2,
+        signers[0].author(),
+        0,
+        Payload::empty(false),
+        parents
+            .iter()
+            .map(|parent| parent.certificate().clone())
+            .collect(),
+    ));
+
+    let network = Arc::new(FailFirstDAGSender {
+        response: BatchFetchResponse::new(1, parents.clone(), true),
+        calls: Mutex::new(vec![]),
+    });
+    let (fetcher, request_tx) = DagFetcher::new(
+        epoch_state,
+        network.clone(),
+        dag.clone(),
+        Arc::new(SimulatedTimeService::new()),
+        1,
+    );
+    tokio::spawn(fetcher.start());
+
+    let request = FetchRequest::new(target.metadata().clone(), 1, vec![]);
+    let (callback_tx, callback_rx) = oneshot::channel();
+    assert!(request_tx
+        .send((request, FetchCallback::CertifiedNode(target, callback_tx)))
+        .await
+        .is_ok());
+    assert!(tokio::time::timeout(Duration::from_secs(1), callback_rx)
+        .await
+        .unwrap()
+        .unwrap()
+        .is_ok());
+
+    // the failed peer isn't asked again, the next one serves the fetch
+    let calls = network.calls.lock().clone();
+    assert_eq!(calls.len(), 2);
+    assert_ne!(calls[0], calls[1]);
+    let dag_reader = dag.read();
+    assert!(parents
+        .iter()
+        .all(|parent| dag_reader.exists(&parent.digest())));
+}
+
+/// Serves the requested rounds of its nodes, at most max_nodes of them per response.
+struct PagingDAGSender {
+    nodes: Vec<CertifiedNode>,
+    max_nodes: usize,
+    requests: Mutex<Vec<(Round, Round)>>,
+}
+
+#[async_trait]
+impl DAGNetworkSender for PagingDAGSender {
+    async fn send_rpc(
+        &self,
+        _receiver: Author,
+        message: ConsensusMsg,
+        _timeout: Duration,
+    ) -> anyhow::Result<ConsensusMsg> {
+        let request: BatchFetchRequest = (TConsensusMsg::from_network_message(message)
+            as anyhow::Result<DAGMessage>)?
+            .try_into()?;
+        let (start_round, end_round) = match request.target() {
+            BatchFetchTarget::Rounds(start_round, end_round) => (*start_round, *end_round),
+            BatchFetchTarget::Digests(_) => bail!("unexpected target"),
+        };
+        self.requests.lock().push((start_round, end_round));
+        let nodes: Vec<_> = self
+            .nodes
+            .iter()
+            .filter(|node| (start_round..=end_round).contains(&node.metadata().round()))
+            .cloned()
+            .collect();
+        let complete = nodes.len() <= self.max_nodes;
+        let nodes = nodes.into_iter().take(self.max_nodes).collect();
+        Ok(DAGMessage::from(BatchFetchResponse::new(1, nodes, complete)).into_network_message())
+    }
+
+    async fn send_rpc_with_fallbacks(
+        &self,
+        _responders: Vec<Author>,
+        _message: ConsensusMsg,
+        _timeout: Duration,
+    ) -> anyhow::Result<ConsensusMsg> {
+        unimplemented!();
+    }
+}
+
+#[tokio::test]
+async fn test_fetcher_requests_rest_of_batch() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier.clone(),
+    });
+
+    let certify = |node: Node| {
+        let mut partial_sigs = PartialSignatures::empty();
+        for signer in &signers[..3] {
+            partial_sigs.add_signature(signer.author(), node.sign(signer).unwrap());
+        }
+        let certificate = NodeCertificate::new(
+            node.metadata().clone(),
+            validator_verifier
+                .aggregate_signatures(&partial_sigs)
+                .unwrap(),
+        );
+        CertifiedNode::new(node, certificate)
+    };
+    // every validator has a node in rounds 1 to 3, the target at round 4 links to them
+    let mut nodes = vec![];
+    let mut parents = vec![];
+    for round in 1..=3 {
+        let round_nodes: Vec<_> = signers
+            .iter()
+            .map(|signer| {
+                certify(Node::new(
+                    1,
+                    round,
+                    signer.author(),
+                    0,
+                    Payload::empty(false),
+                    parents.clone(),
+                ))
+            })
+            .collect();
+        parents = round_nodes
+            .iter()
+            .map(|node| node.certificate().clone())
+            .collect();
+        nodes.extend(round_nodes);
+    }
+    let target = certify(Node::new(
+        1,
+        4,
+        signers[0].author(),
+        0,
+        Payload::empty(false),
+        parents,
+    ));
+
+    let fetch = |max_nodes| {
+        let dag = Arc::new(RwLock::new(Dag::new(
+            validator_verifier.address_to_validator_index().clone(),
+            0,
+        )));
+        let network = Arc::new(PagingDAGSender {
+            nodes: nodes.clone(),
+            max_nodes,
+            requests: Mutex::new(vec![]),
+        });
+        let (fetcher, request_tx) = DagFetcher::new(
+            epoch_state.clone(),
+            network.clone(),
+            dag.clone(),
+            Arc::new(SimulatedTimeService::new()),
+            1,
+        );
+        let request = FetchRequest::new(target.metadata().clone(), 1, vec![]);
+        let (callback_tx, callback_rx) = oneshot::channel();
+        let callback = FetchCallback::CertifiedNode(target.clone(), callback_tx);
+        async move {
+            tokio::spawn(fetcher.start());
+            assert!(request_tx.send((request, callback)).await.is_ok());
+            let result = tokio::time::timeout(Duration::from_secs(1), callback_rx)
+                .await
+                .unwrap();
+            let requests = network.requests.lock().clone();
+            (result, requests, dag)
+        }
+    };
+
+    // each response is cut in the middle of a round, the rest is asked for from that round on
+    let (result, requests, dag) = fetch(6).await;
+    assert!(result.unwrap().is_ok());
+    assert_eq!(requests, vec![(1, 3), (2, 3), (3, 3)]);
+    let dag_reader = dag.read();
+    assert!(nodes.iter().all(|node| dag_reader.exists(&node.digest())));
+    drop(dag_reader);
+
+    // a round that never fits fails the fetch from the peer instead of asking forever
+    let (result, requests, dag) = fetch(2).await;
+    assert!(result.is_err());
+    assert_eq!(requests.len(), 6);
+    assert!(requests.iter().all(|request| *request == (1, 3)));
+    assert!(!dag.read().exists(&nodes[2].digest()));
+}
+
+#[tokio::test]
+async fn test_fetcher_retry_budget() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let author_to_index = validator_verifier.address_to_validator_index().clone();
+    let dag = Arc::new(RwLock::new(Dag::new(author_to_index, 0)));
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier.clone(),
+    });
+    let node = Node::new(1, 1, signers[0].author(), 0, Payload::empty(false), vec![]);
+    let mut partial_sigs = PartialSignatures::empty();
+    for signer in &signers[..3] {
+        partial_sigs.add_signature(signer.author(), node.sign(signer).unwrap());
+    }
+    let certificate = NodeCertificate::new(
+        node.metadata().clone(),
+        validator_verifier
+            .aggregate_signatures(&partial_sigs)
+            .unwrap(),
+    );
+    let node = CertifiedNode::new(node, certificate);
+    let digest = node.digest();
+
+    // no peer has the node
+    let network = Arc::new(SlowDAGSender::default());
+    let (fetcher, request_tx) = DagFetcher::new(
+        epoch_state,
+        network.clone(),
+        dag,
+        Arc::new(SimulatedTimeService::new()),
+        1,
+    );
+    tokio::spawn(fetcher.with_retry_budget(5).start());
+
+    let fetch = || {
+        let request = FetchRequest::new(node.metadata().clone(), 0, vec![]);
+        let (callback_tx, callback_rx) = oneshot::channel();
+        let request_tx = request_tx.clone();
+        let callback = FetchCallback::CertifiedNode(node.clone(), callback_tx);
+        async move {
+            assert!(request_tx.send((request, callback)).await.is_ok());
+            tokio::time::timeout(Duration::from_secs(1), callback_rx)
+                .await
+                .unwrap()
+        }
+    };
+    // each of the 3 signers is asked once, the budget isn't spent yet
+    assert!(fetch().await.is_err());
+    assert_eq!(network.num_calls.load(Ordering::SeqCst), 3);
+    // the remaining 2 attempts fail as well
+    assert_eq!(
+        fetch().await.unwrap().unwrap_err(),
+        FetchError::RetryBudgetExhausted(digest)
+    );
+    assert_eq!(network.num_calls.load(Ordering::SeqCst), 5);
+    // later requests fail right away
+    assert_eq!(
+        fetch().await.unwrap().unwrap_err(),
+        FetchError::RetryBudgetExhausted(digest)
+    );
+    assert_eq!(network.num_calls.load(Ordering::SeqCst), 5);
+}
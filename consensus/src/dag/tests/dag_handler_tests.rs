@@ -0,0 +1,463 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    dag::{
+        anchor_selection::RoundRobinAnchorSelector,
+        counters::{DAG_MESSAGES_RECEIVED, DAG_MESSAGES_SENT},
+        dag_handler::{AnchorPriorityQueue, NetworkHandler, PeerRateLimiter, RateLimitConfig},
+        dag_network::SenderMismatchError,
+        dag_store::Dag,
+        tests::dag_test::new_certified_node,
+        types::{
+            BatchFetchRequest, BatchFetchResponse, BatchFetchTarget, DAGMessage,
+            DAGMessageSizeError, DAGNetworkMessage, Node, NodeDigestSignature,
+        },
+    },
+    network::{IncomingDAGRequest, TConsensusMsg},
+    network_interface::ConsensusMsg,
+};
+use aptos_channels::{aptos_channel, message_queues::QueueStyle};
+use aptos_consensus_types::common::{Author, Payload};
+use aptos_infallible::RwLock;
+use aptos_network::ProtocolId;
+use aptos_types::{epoch_state::EpochState, validator_verifier::random_validator_verifier};
+use futures::channel::oneshot;
+use std::sync::Arc;
+
+#[test]
+fn test_peer_rate_limiter() {
+    let mut limiter = PeerRateLimiter::new(RateLimitConfig {
+        bucket_size: 3,
+        fill_rate: 1,
+    })
+    .with_config("FetchRequest", RateLimitConfig {
+        bucket_size: 1,
+        fill_rate: 1,
+    });
+    let spammer = Author::random();
+    let honest = Author::random();
+
+    let allowed = (0..10)
+        .filter(|_| limiter.allow(spammer, "NodeMsg"))
+        .count();
+    assert_eq!(allowed, 3);
+    assert_eq!(limiter.peer_score(&spammer), -7);
+
+    // the well-behaved peer has its own bucket
+    assert!((0..3).all(|_| limiter.allow(honest, "NodeMsg")));
+    assert_eq!(limiter.peer_score(&honest), 0);
+
+    // limits are tracked per message kind
+    assert!(limiter.allow(spammer, "FetchRequest"));
+    assert!(!limiter.allow(spammer, "FetchRequest"));
+    assert_eq!(limiter.peer_score(&spammer), -8);
+}
+
+#[test]
+fn test_anchor_priority_queue() {
+    let mut queue = AnchorPriorityQueue::new(2);
+    for i in 0..3 {
+        queue.push(format!("anchor {}", i), true);
+        queue.push(format!("node {}", i), false);
+    }
+    queue.push("anchor 3".to_string(), true);
+    assert_eq!(queue.len(), 7);
+
+    let mut order = vec![];
+    while let Some(item) = queue.pop() {
+        order.push(item);
+        // an anchor arriving late still goes ahead of the waiting nodes
+        if order.len() == 3 {
+            queue.push("anchor 4".to_string(), true);
+        }
+    }
+    // anchors go first, but a node gets through after every two of them
+    assert_eq!(order, vec![
+        "anchor 0", "anchor 1", "node 0", "anchor 2", "anchor 3", "node 1", "anchor 4", "node 2",
+    ]);
+    assert!(queue.is_empty());
+}
+
+#[tokio::test]
+async fn test_process_anchors_first() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let dag = Arc::new(RwLock::new(Dag::new(
+        validator_verifier.address_to_validator_index().clone(),
+        0,
+    )));
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let sender = signers[0].author();
+    // a single node per sender is let through, so only the first one processed gets a response
+    let (rpc_tx, rpc_rx) = aptos_channel::new(QueueStyle::FIFO, 10, None);
+    let handler = NetworkHandler::new(
+        dag,
+        rpc_rx,
+        signers[1].clone(),
+        epoch_state,
+        PeerRateLimiter::new(RateLimitConfig::default()).with_config("NodeMsg", RateLimitConfig {
+            bucket_size: 1,
+            fill_rate: 1,
+        }),
+    )
+    .with_anchor_selector(Arc::new(RoundRobinAnchorSelector::new(vec![sender])));
+    let push_node = |round| {
+        let (response_tx, response_rx) = oneshot::channel();
+        let node = Node::new(1, round, sender, 0, Payload::empty(false), vec![]);
+        rpc_tx
+            .push(sender, IncomingDAGRequest {
+                req: DAGMessage::from(node).into_network_message(),
+                sender,
+                protocol: ProtocolId::ConsensusRpcBcs,
+                response_sender: response_tx,
+            })
+            .unwrap();
+        response_rx
+    };
+
+    // the node of the odd round arrives first, but the anchor of the even round goes ahead
+    let regular_rx = push_node(1);
+    let anchor_rx = push_node(2);
+    drop(rpc_tx);
+    handler.start().await;
+    assert!(anchor_rx.await.is_ok());
+    assert!(regular_rx.await.is_err());
+}
+
+#[tokio::test]
+async fn test_dag_message_counters() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let dag = Arc::new(RwLock::new(Dag::new(
+        validator_verifier.address_to_validator_index().clone(),
+        0,
+    )));
+    let epoch_state = Arc::new(EpochState {
+        epoch: 0,
+        verifier: validator_verifier,
+    });
+    let (rpc_tx, rpc_rx) = aptos_channel::new(QueueStyle::FIFO, 10, None);
+    let handler = NetworkHandler::new(
+        dag,
+        rpc_rx,
+        signers[1].clone(),
+        epoch_state,
+        PeerRateLimiter::new(RateLimitConfig::default()),
+    );
+    tokio::spawn(handler.start());
+
+    let received = DAG_MESSAGES_RECEIVED.with_label_values(&["NodeMsg"]);
+    let sent = DAG_MESSAGES_SENT.with_label_values(&["NodeDigestSignatureMsg"]);
+    let (received_before, sent_before) = (received.get(), sent.get());
+
+    let node = Node::new(0, 0, signers[0].author(), 0, Payload::empty(false), vec![]);
+    let (response_tx, response_rx) = oneshot::channel();
+    rpc_tx
+        .push(signers[0].author(), IncomingDAGRequest {
+            req: DAGMessage::from(node).into_network_message(),
+            sender: signers[0].author(),
+            protocol: ProtocolId::ConsensusRpcBcs,
+            response_sender: response_tx,
+        })
+        .unwrap();
+    let response = response_rx.await.unwrap().unwrap();
+    let response: ConsensusMsg = ProtocolId::ConsensusRpcBcs.from_bytes(&response).unwrap();
+    assert!(NodeDigestSignature::try_from(DAGMessage::try_from(response).unwrap()).is_ok());
+
+    // other tests share the registry and may bump the same counters concurrently
+    assert!(received.get() > received_before);
+    assert!(sent.get() > sent_before);
+}
+
+#[tokio::test]
+async fn test_reject_oversized_message() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let dag = Arc::new(RwLock::new(Dag::new(
+        validator_verifier.address_to_validator_index().clone(),
+        0,
+    )));
+    let epoch_state = Arc::new(EpochState {
+        epoch: 0,
+        verifier: validator_verifier,
+    });
+    let (_rpc_tx, rpc_rx) = aptos_channel::new(QueueStyle::FIFO, 10, None);
+    let mut handler = NetworkHandler::new(
+        dag,
+        rpc_rx,
+        signers[1].clone(),
+        epoch_state,
+        PeerRateLimiter::new(RateLimitConfig::default()),
+    )
+    .with_max_message_bytes(1024);
+
+    // garbage that would fail to deserialize, it's rejected on its size alone
+    let sender = signers[0].author();
+    let (response_tx, _response_rx) = oneshot::channel();
+    let err = handler
+        .process_rpc(IncomingDAGRequest {
+            req: ConsensusMsg::DAGMessage(DAGNetworkMessage {
+                version: DAGNetworkMessage::CURRENT_VERSION,
+                epoch: 0,
+                data: vec![0xFF; 4096],
+            }),
+            sender,
+            protocol: ProtocolId::ConsensusRpcBcs,
+            response_sender: response_tx,
+        })
+        .await
+        .unwrap_err();
+    assert!(err.is::<DAGMessageSizeError>());
+    assert!(handler.rate_limiter().peer_score(&sender) < 0);
+
+    // messages within the limit are still processed
+    let node = Node::new(0, 0, sender, 0, Payload::empty(false), vec![]);
+    let (response_tx, response_rx) = oneshot::channel();
+    assert!(handler
+        .process_rpc(IncomingDAGRequest {
+            req: DAGMessage::from(node).into_network_message(),
+            sender,
+            protocol: ProtocolId::ConsensusRpcBcs,
+            response_sender: response_tx,
+        })
+        .await
+        .is_ok());
+    assert!(response_rx.await.unwrap().is_ok());
+}
+
+#[tokio::test]
+async fn test_dedup_incoming_messages() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let dag = Arc::new(RwLock::new(Dag::new(
+        validator_verifier.address_to_validator_index().clone(),
+        0,
+    )));
+    let mut parents = vec![];
+    for signer in &signers[..3] {
+        let parent = new_certified_node(0, signer.author(), vec![]);
+        parents.push(parent.certificate().clone());
+        assert!(dag.write().add_node(parent).is_ok());
+    }
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let (_rpc_tx, rpc_rx) = aptos_channel::new(QueueStyle::FIFO, 10, None);
+    let mut handler = NetworkHandler::new(
+        dag.clone(),
+        rpc_rx,
+        signers[1].clone(),
+        epoch_state,
+        PeerRateLimiter::new(RateLimitConfig::default()),
+    );
+    let new_request = |node: Node| {
+        let (response_tx, response_rx) = oneshot::channel();
+        let request = IncomingDAGRequest {
+            req: DAGMessage::from(node).into_network_message(),
+            sender: signers[0].author(),
+            protocol: ProtocolId::ConsensusRpcBcs,
+            response_sender: response_tx,
+        };
+        (request, response_rx)
+    };
+
+    let node = Node::new(1, 1, signers[0].author(), 0, Payload::empty(false), parents);
+    let (request, response_rx) = new_request(node.clone());
+    assert!(handler.process_rpc(request).await.is_ok());
+    let first_response = response_rx.await.unwrap().unwrap();
+
+    // without its parents the node would no longer pass verification
+    dag.write().prune_below(1);
+    let other_node = Node::new(
+        1,
+        1,
+        signers[0].author(),
+        1,
+        Payload::empty(false),
+        node.parents().to_vec(),
+    );
+    let (request, response_rx) = new_request(other_node);
+    assert!(handler.process_rpc(request).await.is_ok());
+    assert!(response_rx.await.unwrap().is_err());
+
+    // the duplicate is answered from the cache without being verified again
+    let (request, response_rx) = new_request(node);
+    assert!(handler.process_rpc(request).await.is_ok());
+    assert_eq!(response_rx.await.unwrap().unwrap(), first_response);
+}
+
+#[tokio::test]
+async fn test_rate_limit_cached_messages() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let dag = Arc::new(RwLock::new(Dag::new(
+        validator_verifier.address_to_validator_index().clone(),
+        0,
+    )));
+    let mut parents = vec![];
+    for signer in &signers[..3] {
+        let parent = new_certified_node(0, signer.author(), vec![]);
+        parents.push(parent.certificate().clone());
+        assert!(dag.write().add_node(parent).is_ok());
+    }
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let (_rpc_tx, rpc_rx) = aptos_channel::new(QueueStyle::FIFO, 10, None);
+    let mut handler = NetworkHandler::new(
+        dag,
+        rpc_rx,
+        signers[1].clone(),
+        epoch_state,
+        PeerRateLimiter::new(RateLimitConfig::default()).with_config("NodeMsg", RateLimitConfig {
+            bucket_size: 1,
+            fill_rate: 1,
+        }),
+    );
+    let node = Node::new(1, 1, signers[0].author(), 0, Payload::empty(false), parents);
+    let new_request = || {
+        let (response_tx, response_rx) = oneshot::channel();
+        let request = IncomingDAGRequest {
+            req: DAGMessage::from(node.clone()).into_network_message(),
+            sender: signers[0].author(),
+            protocol: ProtocolId::ConsensusRpcBcs,
+            response_sender: response_tx,
+        };
+        (request, response_rx)
+    };
+
+    let (request, response_rx) = new_request();
+    assert!(handler.process_rpc(request).await.is_ok());
+    assert!(response_rx.await.unwrap().is_ok());
+
+    // the duplicate would be answered from the cache, but the sender is out of tokens
+    let (request, response_rx) = new_request();
+    assert!(handler.process_rpc(request).await.is_ok());
+    assert!(response_rx.await.is_err());
+    assert_eq!(handler.rate_limiter().peer_score(&signers[0].author()), -1);
+}
+
+#[tokio::test]
+async fn test_batch_fetch_round_range() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let dag = Arc::new(RwLock::new(Dag::new(
+        validator_verifier.address_to_validator_index().clone(),
+        0,
+    )));
+    let mut parents = vec![];
+    for round in 1..=3 {
+        for signer in &signers {
+            let node = new_certified_node(round, signer.author(), parents.clone());
+            assert!(dag.write().add_node(node).is_ok());
+        }
+        parents = dag
+            .read()
+            .get_strong_links_for_round(round, &validator_verifier)
+            .unwrap();
+    }
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier,
+    });
+    let (_rpc_tx, rpc_rx) = aptos_channel::new(QueueStyle::FIFO, 10, None);
+    let mut handler = NetworkHandler::new(
+        dag,
+        rpc_rx,
+        signers[1].clone(),
+        epoch_state,
+        PeerRateLimiter::new(RateLimitConfig::default()),
+    );
+    let fetch_rounds = |start_round, end_round| {
+        let (response_tx, response_rx) = oneshot::channel();
+        let request = IncomingDAGRequest {
+            req: DAGMessage::from(BatchFetchRequest::new(
+                1,
+                BatchFetchTarget::Rounds(start_round, end_round),
+            ))
+            .into_network_message(),
+            sender: signers[0].author(),
+            protocol: ProtocolId::ConsensusRpcBcs,
+            response_sender: response_tx,
+        };
+        (request, response_rx)
+    };
+
+    // a single response carries every node of the range, in round order
+    let (request, response_rx) = fetch_rounds(1, 10);
+    assert!(handler.process_rpc(request).await.is_ok());
+    let response = response_rx.await.unwrap().unwrap();
+    let response: ConsensusMsg = ProtocolId::ConsensusRpcBcs.from_bytes(&response).unwrap();
+    let response = BatchFetchResponse::try_from(DAGMessage::try_from(response).unwrap()).unwrap();
+    assert!(response.is_complete());
+    let rounds: Vec<_> = response
+        .certified_nodes()
+        .iter()
+        .map(|node| node.metadata().round())
+        .collect();
+    assert_eq!(rounds, vec![1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3]);
+
+    // the response stays within the message size limit
+    let mut handler = handler.with_max_message_bytes(4096);
+    let (request, response_rx) = fetch_rounds(1, 3);
+    assert!(handler.process_rpc(request).await.is_ok());
+    let response = response_rx.await.unwrap().unwrap();
+    assert!(response.len() <= 4096);
+    let response: ConsensusMsg = ProtocolId::ConsensusRpcBcs.from_bytes(&response).unwrap();
+    let response = BatchFetchResponse::try_from(DAGMessage::try_from(response).unwrap()).unwrap();
+    assert!(!response.is_complete());
+    assert!(!response.certified_nodes().is_empty());
+
+    // a budget smaller than a node fails the fetch instead of exceeding the limit
+    let mut handler = handler.with_max_message_bytes(1100);
+    let (request, response_rx) = fetch_rounds(1, 3);
+    assert!(handler.process_rpc(request).await.is_ok());
+    assert!(response_rx.await.unwrap().is_err());
+}
+
+#[tokio::test]
+async fn test_reject_spoofed_sender() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let dag = Arc::new(RwLock::new(Dag::new(
+        validator_verifier.address_to_validator_index().clone(),
+        0,
+    )));
+    let epoch_state = Arc::new(EpochState {
+        epoch: 0,
+        verifier: validator_verifier,
+    });
+    let (_rpc_tx, rpc_rx) = aptos_channel::new(QueueStyle::FIFO, 10, None);
+    let mut handler = NetworkHandler::new(
+        dag,
+        rpc_rx,
+        signers[1].clone(),
+        epoch_state,
+        PeerRateLimiter::new(RateLimitConfig::default()),
+    );
+    let (author, spoofer) = (signers[0].author(), signers[2].author());
+    let node = Node::new(0, 0, author, 0, Payload::empty(false), vec![]);
+    let new_request = |sender| {
+        let (response_tx, response_rx) = oneshot::channel();
+        let request = IncomingDAGRequest {
+            req: DAGMessage::from(node.clone()).into_network_message(),
+            sender,
+            protocol: ProtocolId::ConsensusRpcBcs,
+            response_sender: response_tx,
+        };
+        (request, response_rx)
+    };
+
+    let (request, _response_rx) = new_request(spoofer);
+    let err = handler.process_rpc(request).await.unwrap_err();
+    assert!(err.is::<SenderMismatchError>());
+    assert!(handler.rate_limiter().peer_score(&spoofer) < 0);
+
+    // the author itself is answered, replaying its message from another peer still fails
+    let (request, response_rx) = new_request(author);
+    assert!(handler.process_rpc(request).await.is_ok());
+    assert!(response_rx.await.unwrap().is_ok());
+    let (request, _response_rx) = new_request(spoofer);
+    let err = handler.process_rpc(request).await.unwrap_err();
+    assert!(err.is::<SenderMismatchError>());
+    assert_eq!(handler.rate_limiter().peer_score(&author), 0);
+}
@@ -0,0 +1,207 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    dag::{
+        dag_network::{
+            BandwidthLimitConfig, BandwidthLimitedSender, DAGNetworkSender, RpcHandler,
+            RpcRetryPolicy,
+        },
+        types::{DAGMessage, RoundTimeoutAck, TestAck, TestMessage},
+    },
+    network::TConsensusMsg,
+    network_interface::ConsensusMsg,
+};
+use aptos_consensus_types::common::Author;
+use aptos_infallible::Mutex;
+use aptos_types::validator_verifier::random_validator_verifier;
+use async_trait::async_trait;
+use futures::future::pending;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+/// Never responds to the unresponsive peer.
+struct MockDAGSender {
+    unresponsive: Author,
+    calls: Mutex<Vec<Author>>,
+    cancelled: AtomicBool,
+}
+
+/// Records that the rpc future was dropped before completing.
+struct CancelGuard<'a>(&'a AtomicBool);
+
+impl Drop for CancelGuard<'_> {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+#[async_trait]
+impl DAGNetworkSender for MockDAGSender {
+    async fn send_rpc(
+        &self,
+        receiver: Author,
+        _message: ConsensusMsg,
+        _timeout: Duration,
+    ) -> anyhow::Result<ConsensusMsg> {
+        self.calls.lock().push(receiver);
+        if receiver == self.unresponsive {
+            let _guard = CancelGuard(&self.cancelled);
+            return pending().await;
+        }
+        Ok(DAGMessage::from(TestAck(vec![])).into_network_message())
+    }
+
+    async fn send_rpc_with_fallbacks(
+        &self,
+        _responders: Vec<Author>,
+        _message: ConsensusMsg,
+        _timeout: Duration,
+    ) -> anyhow::Result<ConsensusMsg> {
+        unimplemented!();
+    }
+}
+
+#[tokio::test]
+async fn test_rpc_retry_on_timeout() {
+    let (_, validator_verifier) = random_validator_verifier(2, None, false);
+    let validators = validator_verifier.get_ordered_account_addresses();
+    let sender = MockDAGSender {
+        unresponsive: validators[0],
+        calls: Mutex::new(vec![]),
+        cancelled: AtomicBool::new(false),
+    };
+    let message = DAGMessage::from(TestMessage(vec![42])).into_network_message();
+
+    let policy = RpcRetryPolicy {
+        timeout: Duration::from_millis(50),
+        max_attempts: 2,
+    };
+    let response = sender
+        .send_rpc_with_policy(validators.clone(), message.clone(), policy)
+        .await
+        .unwrap();
+    let ack = TestAck::try_from(DAGMessage::try_from(response).unwrap()).unwrap();
+    assert_eq!(ack, TestAck(vec![]));
+    assert_eq!(*sender.calls.lock(), validators);
+
+    // out of attempts after the unresponsive peer times out
+    sender.calls.lock().clear();
+    let policy = RpcRetryPolicy {
+        timeout: Duration::from_millis(50),
+        max_attempts: 1,
+    };
+    let err = sender
+        .send_rpc_with_policy(validators.clone(), message, policy)
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("timed out"));
+    assert_eq!(*sender.calls.lock(), validators[..1]);
+}
+
+/// Forwards every request to a single peer.
+struct ForwardingHandler {
+    network: Arc<MockDAGSender>,
+    peer: Author,
+}
+
+#[async_trait]
+impl RpcHandler for ForwardingHandler {
+    type Request = TestMessage;
+    type Response = TestAck;
+
+    async fn process(&mut self, message: Self::Request) -> anyhow::Result<Self::Response> {
+        let response = self
+            .network
+            .send_rpc(
+                self.peer,
+                DAGMessage::from(message).into_network_message(),
+                Duration::from_secs(1),
+            )
+            .await?;
+        TestAck::try_from(DAGMessage::try_from(response)?)
+    }
+}
+
+#[tokio::test]
+async fn test_rpc_handler_cancellation() {
+    let (_, validator_verifier) = random_validator_verifier(1, None, false);
+    let validators = validator_verifier.get_ordered_account_addresses();
+    let network = Arc::new(MockDAGSender {
+        unresponsive: validators[0],
+        calls: Mutex::new(vec![]),
+        cancelled: AtomicBool::new(false),
+    });
+    let mut handler = ForwardingHandler {
+        network: network.clone(),
+        peer: validators[0],
+    };
+
+    // the round moved on, the pending response is dropped
+    let result = tokio::time::timeout(
+        Duration::from_millis(50),
+        handler.process(TestMessage(vec![42])),
+    )
+    .await;
+    assert!(result.is_err());
+    assert_eq!(*network.calls.lock(), validators);
+    assert!(network.cancelled.load(Ordering::SeqCst));
+}
+
+#[tokio::test]
+async fn test_bandwidth_limit() {
+    let (_, validator_verifier) = random_validator_verifier(2, None, false);
+    let validators = validator_verifier.get_ordered_account_addresses();
+    let inner = Arc::new(MockDAGSender {
+        unresponsive: validators[1],
+        calls: Mutex::new(vec![]),
+        cancelled: AtomicBool::new(false),
+    });
+    let config = BandwidthLimitConfig {
+        bytes_per_sec: 1000,
+        burst_bytes: 1000,
+    };
+    let sender = BandwidthLimitedSender::new(inner.clone(), config);
+
+    let message = DAGMessage::from(TestMessage(vec![7; 400])).into_network_message();
+    let message_size = bcs::serialized_size(&message).unwrap();
+    let num_messages = 6;
+    let start = Instant::now();
+    for _ in 0..num_messages {
+        assert!(sender
+            .send_rpc(validators[0], message.clone(), Duration::from_secs(1))
+            .await
+            .is_ok());
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+    let sent_bytes = (num_messages * message_size) as f64;
+    assert!(
+        sent_bytes <= config.burst_bytes as f64 + config.bytes_per_sec as f64 * elapsed,
+        "sent {} bytes in {}s",
+        sent_bytes,
+        elapsed
+    );
+    assert_eq!(inner.calls.lock().len(), num_messages);
+
+    // high priority messages go out right away even though the budget is used up
+    let start = Instant::now();
+    let ack = DAGMessage::RoundTimeoutAckMsg(RoundTimeoutAck::new(1, 1)).into_network_message();
+    assert!(sender
+        .send_rpc(validators[0], ack, Duration::from_secs(1))
+        .await
+        .is_ok());
+    assert!(start.elapsed() < Duration::from_millis(100));
+
+    // a message larger than the burst waits for a full bucket instead of failing
+    let large = DAGMessage::from(TestMessage(vec![7; 1500])).into_network_message();
+    assert!(sender
+        .send_rpc(validators[0], large, Duration::from_secs(1))
+        .await
+        .is_ok());
+    assert_eq!(inner.calls.lock().len(), num_messages + 2);
+}
@@ -1,14 +1,23 @@
 // Copyright © Aptos Foundation
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::dag::{
-    dag_store::Dag,
-    types::{CertifiedNode, Node, NodeCertificate},
+use crate::{
+    consensusdb::ConsensusDB,
+    dag::{
+        dag_store::{Dag, DagSnapshot, DagStoreError},
+        storage::{InMemNodeStore, NodeStore},
+        types::{CertifiedNode, Node, NodeCertificate},
+    },
 };
 use aptos_consensus_types::common::{Author, Payload, Round};
+use aptos_temppath::TempPath;
 use aptos_types::{
-    aggregate_signature::AggregateSignature, validator_verifier::random_validator_verifier,
+    aggregate_signature::AggregateSignature,
+    validator_signer::ValidatorSigner,
+    validator_verifier::{random_validator_verifier, ValidatorVerifier},
 };
+use futures::{FutureExt, StreamExt};
+use std::{collections::HashSet, sync::Arc};
 
 #[test]
 fn test_dag_insertion_succeed() {
@@ -82,7 +91,496 @@ fn test_dag_insertion_failure() {
     assert!(dag.add_node(node).is_err());
 }
 
-fn new_certified_node(
+#[test]
+fn test_dag_equivocation() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let author_to_index = validator_verifier.address_to_validator_index().clone();
+    let mut dag = Dag::new(author_to_index, 0);
+
+    let node = new_certified_node(1, signers[0].author(), vec![]);
+    assert!(dag.add_node(node.clone()).is_ok());
+    let conflicting_node = CertifiedNode::new(
+        Node::new(1, 1, signers[0].author(), 1, Payload::empty(false), vec![]),
+        node.certificate().clone(),
+    );
+
+    let equivocation = match dag.add_node(conflicting_node.clone()) {
+        Err(DagStoreError::Equivocation(equivocation)) => equivocation,
+        result => panic!("expected an equivocation, got {:?}", result),
+    };
+    assert_eq!(equivocation.existing.digest(), node.digest());
+    assert_eq!(equivocation.conflicting.digest(), conflicting_node.digest());
+    // the first node is kept
+    assert!(dag.exists(&node.digest()));
+    assert!(!dag.exists(&conflicting_node.digest()));
+}
+
+#[test]
+fn test_orphan_equivocation() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let author_to_index = validator_verifier.address_to_validator_index().clone();
+    let (equivocation_tx, mut equivocation_rx) = aptos_channels::new_test(10);
+    let mut dag = Dag::new(author_to_index, 0).with_equivocation_tx(equivocation_tx);
+
+    let round_one: Vec<_> = signers
+        .iter()
+        .map(|signer| new_certified_node(1, signer.author(), vec![]))
+        .collect();
+    for node in &round_one[..3] {
+        assert!(dag.add_node(node.clone()).is_ok());
+    }
+    let parents: Vec<_> = round_one[..3]
+        .iter()
+        .map(|node| node.certificate().clone())
+        .collect();
+    let node = new_certified_node(2, signers[0].author(), parents);
+    assert!(dag.add_node(node.clone()).is_ok());
+    // a conflicting node waits for its missing parent
+    let conflicting_node = new_certified_node(2, signers[0].author(), vec![round_one[3]
+        .certificate()
+        .clone()]);
+    assert!(dag.add_orphan(conflicting_node.clone()).is_ok());
+    assert!(equivocation_rx.next().now_or_never().is_none());
+
+    // adopting it once the parent arrives reports the equivocation
+    assert!(dag.add_node(round_one[3].clone()).is_ok());
+    let equivocation = equivocation_rx.next().now_or_never().unwrap().unwrap();
+    assert_eq!(equivocation.existing.digest(), node.digest());
+    assert_eq!(equivocation.conflicting.digest(), conflicting_node.digest());
+    assert!(!dag.exists(&conflicting_node.digest()));
+    assert_eq!(dag.num_orphans(), 0);
+}
+
+#[test]
+fn test_dag_prune_below() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let mut dag = new_dag_with_rounds(&signers, &validator_verifier, 10, usize::MAX);
+    assert_eq!(dag.num_nodes(), 40);
+    let size_before = dag.size_bytes();
+    let pruned_node = dag
+        .get_strong_links_for_round(4, &validator_verifier)
+        .unwrap()[0]
+        .clone();
+
+    dag.prune_below(5);
+    assert_eq!(dag.num_nodes(), 24);
+    assert!(dag.size_bytes() < size_before);
+    assert_eq!(dag.lowest_round(), 5);
+    assert!(!dag.exists(pruned_node.metadata().digest()));
+
+    // pruning everything leaves the DAG ready to accept the next round
+    dag.prune_below(11);
+    assert_eq!(dag.num_nodes(), 0);
+    assert_eq!(dag.size_bytes(), 0);
+    assert!(dag
+        .add_node(new_certified_node(11, signers[0].author(), vec![]))
+        .is_ok());
+}
+
+#[test]
+fn test_dag_lru_eviction() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let full_size = new_dag_with_rounds(&signers, &validator_verifier, 4, usize::MAX).size_bytes();
+
+    // nothing is committed yet, so the DAG is allowed to grow over the cap
+    let mut dag = new_dag_with_rounds(&signers, &validator_verifier, 4, full_size - 1);
+    assert_eq!(dag.num_nodes(), 16);
+    let round_one = dag
+        .get_strong_links_for_round(1, &validator_verifier)
+        .unwrap();
+    for (i, certificate) in round_one.iter().enumerate() {
+        if i != 1 {
+            assert!(dag.get_node(certificate.metadata().digest()).is_some());
+        }
+    }
+
+    // the least recently used committed node goes first
+    dag.set_committed_round(3);
+    assert_eq!(dag.num_nodes(), 15);
+    assert!(dag.size_bytes() < full_size);
+    assert!(dag.exists(round_one[0].metadata().digest()));
+    assert!(!dag.exists(round_one[1].metadata().digest()));
+
+    // uncommitted rounds are never evicted
+    let mut dag = new_dag_with_rounds(&signers, &validator_verifier, 4, 0);
+    dag.set_committed_round(3);
+    assert_eq!(dag.num_nodes(), 8);
+    assert!(dag
+        .get_strong_links_for_round(3, &validator_verifier)
+        .is_some());
+    assert!(dag
+        .get_strong_links_for_round(4, &validator_verifier)
+        .is_some());
+}
+
+#[test]
+fn test_dag_eviction_deletes_from_storage() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let author_to_index = validator_verifier.address_to_validator_index().clone();
+    let storage = Arc::new(InMemNodeStore::default());
+    let mut dag = Dag::new(author_to_index, 0)
+        .with_max_bytes(0)
+        .with_storage(storage.clone());
+    let mut parents = vec![];
+    for round in 1..=4 {
+        for signer in &signers {
+            let node = new_certified_node(round, signer.author(), parents.clone());
+            assert!(dag.add_node(node).is_ok());
+        }
+        parents = dag
+            .get_strong_links_for_round(round, &validator_verifier)
+            .unwrap();
+    }
+
+    // the evicted committed rounds are deleted from storage too
+    dag.set_committed_round(3);
+    assert_eq!(dag.num_nodes(), 8);
+    let stored_rounds: HashSet<_> = storage
+        .get_nodes()
+        .unwrap()
+        .iter()
+        .map(|node| node.metadata().round())
+        .collect();
+    assert_eq!(stored_rounds, HashSet::from([3, 4]));
+}
+
+#[test]
+fn test_dag_nodes_at_round() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let author_to_index = validator_verifier.address_to_validator_index().clone();
+    let mut dag = Dag::new(author_to_index, 0);
+    assert_eq!(dag.rounds_range(), (0, 0));
+
+    // Round 1 - all nodes, Round 2 - nodes 0, 1, 2, Round 3 - nodes 1, 2
+    let mut parents = vec![];
+    for (round, authors) in [
+        (1, &signers[0..4]),
+        (2, &signers[0..3]),
+        (3, &signers[1..3]),
+    ] {
+        for signer in authors {
+            let node = new_certified_node(round, signer.author(), parents.clone());
+            assert!(dag.add_node(node).is_ok());
+        }
+        if let Some(strong_links) = dag.get_strong_links_for_round(round, &validator_verifier) {
+            parents = strong_links;
+        }
+    }
+
+    assert_eq!(dag.rounds_range(), (0, 3));
+    assert!(dag.nodes_at_round(0).is_empty());
+    for (round, authors) in [
+        (1, &signers[0..4]),
+        (2, &signers[0..3]),
+        (3, &signers[1..3]),
+    ] {
+        let expected: HashSet<_> = authors.iter().map(|signer| signer.author()).collect();
+        let actual: HashSet<_> = dag
+            .nodes_at_round(round)
+            .iter()
+            .map(|node| {
+                assert_eq!(node.metadata().round(), round);
+                *node.metadata().author()
+            })
+            .collect();
+        assert_eq!(actual, expected);
+    }
+    assert!(dag.nodes_at_round(4).is_empty());
+}
+
+/// Builds a DAG with all validators' nodes from round 1 to num_rounds.
+#[test]
+fn test_dag_recovery() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let author_to_index = validator_verifier.address_to_validator_index().clone();
+    let tmp_dir = TempPath::new();
+    let storage: Arc<dyn NodeStore> = Arc::new(ConsensusDB::new(&tmp_dir));
+    let mut dag = Dag::new(author_to_index.clone(), 0).with_storage(storage.clone());
+
+    let mut parents = vec![];
+    for round in 1..=3 {
+        for signer in &signers {
+            let node = new_certified_node(round, signer.author(), parents.clone());
+            assert!(dag.add_node(node).is_ok());
+        }
+        parents = dag
+            .get_strong_links_for_round(round, &validator_verifier)
+            .unwrap();
+    }
+    dag.prune_below(2);
+    let digests_by_round = |dag: &Dag| {
+        (0..=3)
+            .map(|round| {
+                dag.nodes_at_round(round)
+                    .iter()
+                    .map(|node| node.digest())
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>()
+    };
+    let expected = digests_by_round(&dag);
+
+    // restart with a fresh handle to the same db
+    drop(dag);
+    drop(storage);
+    let storage = Arc::new(ConsensusDB::new(&tmp_dir));
+    let mut recovered = Dag::recover(author_to_index, 0, storage).unwrap();
+    assert_eq!(digests_by_round(&recovered), expected);
+    assert_eq!(recovered.num_nodes(), 8);
+    assert_eq!(recovered.rounds_range(), (2, 3));
+
+    // the recovered DAG keeps accepting nodes on top of the reloaded ones
+    let node = new_certified_node(4, signers[0].author(), parents);
+    assert!(recovered.add_node(node).is_ok());
+}
+
+#[test]
+fn test_dag_latest_node_by_author() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let mut dag = new_dag_with_rounds(&signers, &validator_verifier, 3, usize::MAX);
+    let author = signers[0].author();
+    assert_eq!(
+        dag.latest_node_by_author(&author)
+            .unwrap()
+            .metadata()
+            .round(),
+        3
+    );
+
+    // only the author's own rounds count
+    let parents = dag
+        .get_strong_links_for_round(3, &validator_verifier)
+        .unwrap();
+    for round in 4..=5 {
+        let node = new_certified_node(round, author, parents.clone());
+        let digest = node.digest();
+        assert!(dag.add_node(node).is_ok());
+        assert_eq!(dag.latest_node_by_author(&author).unwrap().digest(), digest);
+    }
+    assert_eq!(
+        dag.latest_node_by_author(&signers[1].author())
+            .unwrap()
+            .metadata()
+            .round(),
+        3
+    );
+
+    // the index follows pruning
+    dag.prune_below(4);
+    assert!(dag.latest_node_by_author(&signers[1].author()).is_none());
+    assert_eq!(
+        dag.latest_node_by_author(&author)
+            .unwrap()
+            .metadata()
+            .round(),
+        5
+    );
+}
+
+#[test]
+fn test_dag_causal_history() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let author_to_index = validator_verifier.address_to_validator_index().clone();
+    let mut dag = Dag::new(author_to_index, 0);
+    let mut authors: Vec<_> = signers.iter().map(|signer| signer.author()).collect();
+    authors.sort();
+
+    // Round 1 - all nodes, Round 2 - nodes 0, 1, 2 link to 1, 2, 3 of round 1, Round 3 - node 0
+    // links to 0, 1, 2 of round 2
+    let round_one: Vec<_> = authors
+        .iter()
+        .map(|author| new_certified_node(1, *author, vec![]))
+        .collect();
+    let round_two: Vec<_> = authors[..3]
+        .iter()
+        .map(|author| {
+            let parents = round_one[1..]
+                .iter()
+                .map(|node| node.certificate().clone())
+                .collect();
+            new_certified_node(2, *author, parents)
+        })
+        .collect();
+    let node = new_certified_node(
+        3,
+        authors[0],
+        round_two
+            .iter()
+            .map(|node| node.certificate().clone())
+            .collect(),
+    );
+    for node in round_one.iter().chain(&round_two).chain([&node]) {
+        assert!(dag.add_node(node.clone()).is_ok());
+    }
+
+    let history: Vec<_> = dag
+        .causal_history(&node)
+        .iter()
+        .map(|node| node.digest())
+        .collect();
+    let expected: Vec<_> = round_one[1..]
+        .iter()
+        .chain(&round_two)
+        .map(|node| node.digest())
+        .collect();
+    assert_eq!(history, expected);
+
+    // the traversal stops at pruned rounds
+    dag.prune_below(2);
+    let history: Vec<_> = dag
+        .causal_history(&node)
+        .iter()
+        .map(|node| node.digest())
+        .collect();
+    let expected: Vec<_> = round_two.iter().map(|node| node.digest()).collect();
+    assert_eq!(history, expected);
+}
+
+#[test]
+fn test_dag_export_snapshot() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let mut dag = new_dag_with_rounds(&signers, &validator_verifier, 3, usize::MAX);
+    dag.set_committed_round(2);
+
+    let snapshot = dag.export_snapshot();
+    assert_eq!((snapshot.lowest_round, snapshot.highest_round), (0, 3));
+    assert_eq!(snapshot.nodes.len(), 12);
+    for (node, expected_round) in snapshot
+        .nodes
+        .iter()
+        .zip([1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3])
+    {
+        assert_eq!(node.round, expected_round);
+        assert_eq!(node.committed, node.round < 2);
+        assert_eq!(node.parents.len(), if node.round == 1 { 0 } else { 4 });
+        assert!(dag.exists(&node.digest));
+    }
+    let round_one: HashSet<_> = snapshot.nodes[..4].iter().map(|node| node.digest).collect();
+    assert!(snapshot.nodes[4..8].iter().all(|node| node
+        .parents
+        .iter()
+        .cloned()
+        .collect::<HashSet<_>>()
+        == round_one));
+
+    let json = serde_json::to_string(&snapshot).unwrap();
+    assert_eq!(
+        serde_json::from_str::<DagSnapshot>(&json).unwrap(),
+        snapshot
+    );
+    let bytes = bcs::to_bytes(&snapshot).unwrap();
+    assert_eq!(bcs::from_bytes::<DagSnapshot>(&bytes).unwrap(), snapshot);
+}
+
+#[test]
+fn test_dag_approx_memory_bytes() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let author_to_index = validator_verifier.address_to_validator_index().clone();
+    let mut dag = Dag::new(author_to_index, 0);
+    let empty = dag.approx_memory_bytes();
+
+    let mut parents = vec![];
+    let mut previous = empty;
+    for round in 1..=3 {
+        for signer in &signers {
+            let node = new_certified_node(round, signer.author(), parents.clone());
+            assert!(dag.add_node(node).is_ok());
+            assert!(dag.approx_memory_bytes() > previous);
+            previous = dag.approx_memory_bytes();
+        }
+        parents = dag
+            .get_strong_links_for_round(round, &validator_verifier)
+            .unwrap();
+    }
+    assert!(dag.approx_memory_bytes() > dag.size_bytes());
+
+    dag.prune_below(2);
+    assert!(dag.approx_memory_bytes() < previous);
+    dag.prune_below(4);
+    assert_eq!(dag.approx_memory_bytes(), empty);
+}
+
+#[test]
+fn test_dag_orphan_gc() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let mut dag = new_dag_with_rounds(&signers[..3], &validator_verifier, 1, usize::MAX)
+        .with_orphan_gc_rounds(2);
+
+    // the node of round 2 links to a node of round 1 that never arrives
+    let missing = new_certified_node(1, signers[3].author(), vec![]);
+    let parents = dag
+        .get_strong_links_for_round(1, &validator_verifier)
+        .unwrap();
+    let orphan = new_certified_node(2, signers[0].author(), {
+        let mut parents = parents.clone();
+        parents.push(missing.into());
+        parents
+    });
+    assert!(dag.add_orphan(orphan.clone()).is_ok());
+    assert!(dag.add_orphan(orphan.clone()).is_err());
+
+    // an orphan whose parents do arrive is added along with them
+    let round_2: Vec<_> = signers[1..]
+        .iter()
+        .map(|signer| new_certified_node(2, signer.author(), parents.clone()))
+        .collect();
+    let parents: Vec<NodeCertificate> = round_2
+        .iter()
+        .map(|node| node.certificate().clone())
+        .collect();
+    let adopted = new_certified_node(3, signers[1].author(), parents.clone());
+    assert!(dag.add_orphan(adopted.clone()).is_ok());
+    assert_eq!(dag.num_orphans(), 2);
+    for node in round_2 {
+        assert!(dag.add_node(node).is_ok());
+    }
+    assert!(dag.exists(&adopted.digest()));
+    assert_eq!(dag.num_orphans(), 1);
+
+    for signer in &signers[2..] {
+        let node = new_certified_node(3, signer.author(), parents.clone());
+        assert!(dag.add_node(node).is_ok());
+    }
+    // the orphan stays until fetching its parents fails, and then until it's 2 rounds behind
+    assert_eq!(dag.gc_orphans(), 0);
+    dag.orphan_fetch_failed(&orphan.digest());
+    assert_eq!(dag.gc_orphans(), 0);
+    assert_eq!(dag.num_orphans(), 1);
+
+    let parents = dag
+        .get_strong_links_for_round(3, &validator_verifier)
+        .unwrap();
+    for signer in &signers[1..] {
+        let node = new_certified_node(4, signer.author(), parents.clone());
+        assert!(dag.add_node(node).is_ok());
+    }
+    assert_eq!(dag.gc_orphans(), 1);
+    assert_eq!(dag.num_orphans(), 0);
+    assert!(!dag.exists(&orphan.digest()));
+}
+
+fn new_dag_with_rounds(
+    signers: &[ValidatorSigner],
+    validator_verifier: &ValidatorVerifier,
+    num_rounds: Round,
+    max_bytes: usize,
+) -> Dag {
+    let author_to_index = validator_verifier.address_to_validator_index().clone();
+    let mut dag = Dag::new(author_to_index, 0).with_max_bytes(max_bytes);
+    let mut parents = vec![];
+    for round in 1..=num_rounds {
+        for signer in signers {
+            let node = new_certified_node(round, signer.author(), parents.clone());
+            assert!(dag.add_node(node).is_ok());
+        }
+        parents = dag
+            .get_strong_links_for_round(round, validator_verifier)
+            .unwrap();
+    }
+    dag
+}
+
+pub(super) fn new_certified_node(
     round: Round,
     author: Author,
     parents: Vec<NodeCertificate>,
@@ -1,5 +1,10 @@
 // Copyright © Aptos Foundation
 // SPDX-License-Identifier: Apache-2.0
 
+mod dag_driver_tests;
+mod dag_fetcher_tests;
+mod dag_handler_tests;
+mod dag_network_tests;
 mod dag_test;
 mod reliable_broadcast_tests;
+mod types_tests;
@@ -6,26 +6,36 @@ use crate::{
         dag_network::DAGNetworkSender,
         dag_store::Dag,
         reliable_broadcast::{
-            BroadcastStatus, NodeBroadcastHandleError, NodeBroadcastHandler, ReliableBroadcast,
+            BackoffConfig, BroadcastStatus, NodeBroadcastHandleError, NodeBroadcastHandler,
+            ReliableBroadcast,
+        },
+        storage::{broadcast_digest, InMemBroadcastStore},
+        types::{
+            CertificateAckState, CertifiedAck, DAGMessage, Node, NodeCertificate,
+            NodeDigestSignature, TestAck, TestMessage,
         },
-        types::{DAGMessage, Node, NodeCertificate, NodeDigestSignature, TestAck, TestMessage},
         RpcHandler,
     },
     network::TConsensusMsg,
     network_interface::ConsensusMsg,
+    util::{mock_time_service::SimulatedTimeService, time_service::TimeService},
 };
 use anyhow::bail;
 use aptos_consensus_types::common::{Author, Payload, Round};
 use aptos_infallible::{Mutex, RwLock};
 use aptos_types::{
-    aggregate_signature::PartialSignatures, validator_verifier::random_validator_verifier,
+    aggregate_signature::{AggregateSignature, PartialSignatures},
+    epoch_state::EpochState,
+    validator_signer::ValidatorSigner,
+    validator_verifier::{random_validator_verifier, ValidatorConsensusInfo, ValidatorVerifier},
 };
 use async_trait::async_trait;
 use claims::assert_ok_eq;
 use futures::{
-    future::{AbortHandle, Abortable},
+    future::{pending, AbortHandle, Abortable},
     FutureExt,
 };
+use rand::{rngs::StdRng, SeedableRng};
 use std::{
     collections::{hash_map::Entry, BTreeMap, HashMap, HashSet},
     sync::Arc,
@@ -103,20 +113,56 @@ impl DAGNetworkSender for TestDAGSender {
     }
 }
 
+/// Never responds to the unreachable peers.
+struct PartitionedDAGSender {
+    unreachable: HashSet<Author>,
+    inner: TestDAGSender,
+}
+
+#[async_trait]
+impl DAGNetworkSender for PartitionedDAGSender {
+    async fn send_rpc(
+        &self,
+        receiver: Author,
+        message: ConsensusMsg,
+        timeout: Duration,
+    ) -> anyhow::Result<ConsensusMsg> {
+        if self.unreachable.contains(&receiver) {
+            return pending().await;
+        }
+        self.inner.send_rpc(receiver, message, timeout).await
+    }
+
+    async fn send_rpc_with_fallbacks(
+        &self,
+        _responders: Vec<Author>,
+        _message: ConsensusMsg,
+        _timeout: Duration,
+    ) -> anyhow::Result<ConsensusMsg> {
+        unimplemented!();
+    }
+}
+
 #[tokio::test]
 async fn test_reliable_broadcast() {
     let (_, validator_verifier) = random_validator_verifier(5, None, false);
     let validators = validator_verifier.get_ordered_account_addresses();
     let failures = HashMap::from([(validators[0], 1), (validators[2], 3)]);
     let sender = Arc::new(TestDAGSender::new(failures));
-    let rb = ReliableBroadcast::new(validators.clone(), sender);
+    let rb = ReliableBroadcast::new(
+        validators.clone(),
+        sender,
+        BackoffConfig::default(),
+        Arc::new(SimulatedTimeService::new()),
+        Arc::new(InMemBroadcastStore::default()),
+    );
     let message = TestMessage(vec![42; validators.len() - 1]);
     let aggregating = TestBroadcastStatus {
         threshold: validators.len(),
         received: HashSet::new(),
     };
     let fut = rb.broadcast::<TestBroadcastStatus>(message, aggregating);
-    assert_eq!(fut.await, validators.into_iter().collect());
+    assert_eq!(fut.await.unwrap(), validators.into_iter().collect());
 }
 
 #[tokio::test]
@@ -125,7 +171,13 @@ async fn test_chaining_reliable_broadcast() {
     let validators = validator_verifier.get_ordered_account_addresses();
     let failures = HashMap::from([(validators[0], 1), (validators[2], 3)]);
     let sender = Arc::new(TestDAGSender::new(failures));
-    let rb = ReliableBroadcast::new(validators.clone(), sender);
+    let rb = ReliableBroadcast::new(
+        validators.clone(),
+        sender,
+        BackoffConfig::default(),
+        Arc::new(SimulatedTimeService::new()),
+        Arc::new(InMemBroadcastStore::default()),
+    );
     let message = TestMessage(vec![42; validators.len()]);
     let expected = validators.iter().cloned().collect();
     let aggregating = TestBroadcastStatus {
@@ -135,7 +187,7 @@ async fn test_chaining_reliable_broadcast() {
     let fut = rb
         .broadcast::<TestBroadcastStatus>(message.clone(), aggregating)
         .then(|aggregated| async move {
-            assert_eq!(aggregated, expected);
+            assert_eq!(aggregated.unwrap(), expected);
             let aggregating = TestBroadcastStatus {
                 threshold: validator_verifier.len(),
                 received: HashSet::new(),
@@ -143,7 +195,7 @@ async fn test_chaining_reliable_broadcast() {
             rb.broadcast::<TestBroadcastStatus>(message, aggregating)
                 .await
         });
-    assert_eq!(fut.await, validators.into_iter().collect());
+    assert_eq!(fut.await.unwrap(), validators.into_iter().collect());
 }
 
 #[tokio::test]
@@ -152,7 +204,13 @@ async fn test_abort_reliable_broadcast() {
     let validators = validator_verifier.get_ordered_account_addresses();
     let failures = HashMap::from([(validators[0], 1), (validators[2], 3)]);
     let sender = Arc::new(TestDAGSender::new(failures));
-    let rb = ReliableBroadcast::new(validators.clone(), sender);
+    let rb = ReliableBroadcast::new(
+        validators.clone(),
+        sender,
+        BackoffConfig::default(),
+        Arc::new(SimulatedTimeService::new()),
+        Arc::new(InMemBroadcastStore::default()),
+    );
     let message = TestMessage(vec![42; validators.len()]);
     let (tx, rx) = oneshot::channel();
     let (abort_handle, abort_registration) = AbortHandle::new_pair();
@@ -179,6 +237,392 @@ async fn test_abort_reliable_broadcast() {
     assert!(rx.await.is_err());
 }
 
+#[test]
+fn test_backoff_delay() {
+    let backoff = BackoffConfig {
+        base: Duration::from_millis(10),
+        max: Duration::from_millis(100),
+        jitter: Duration::ZERO,
+    };
+    let mut rng = StdRng::seed_from_u64(0);
+    let delays: Vec<_> = (1..=6)
+        .map(|attempt| backoff.delay(attempt, &mut rng))
+        .collect();
+    assert_eq!(
+        delays,
+        [10, 20, 40, 80, 100, 100].map(Duration::from_millis)
+    );
+
+    let backoff = BackoffConfig {
+        jitter: Duration::from_millis(5),
+        ..backoff
+    };
+    for attempt in 1..=4 {
+        let delay = backoff.delay(attempt, &mut rng);
+        let expected = Duration::from_millis(10 << (attempt - 1));
+        assert!(delay >= expected && delay < expected + Duration::from_millis(5));
+    }
+    for attempt in 5..=64 {
+        assert!(backoff.delay(attempt, &mut rng) <= backoff.max);
+    }
+}
+
+#[tokio::test]
+async fn test_reliable_broadcast_backoff() {
+    let (_, validator_verifier) = random_validator_verifier(5, None, false);
+    let validators = validator_verifier.get_ordered_account_addresses();
+    let failures = HashMap::from([(validators[0], 5)]);
+    let sender = Arc::new(TestDAGSender::new(failures));
+    let time_service = Arc::new(SimulatedTimeService::new());
+    let backoff = BackoffConfig {
+        base: Duration::from_millis(10),
+        max: Duration::from_millis(100),
+        jitter: Duration::ZERO,
+    };
+    let rb = ReliableBroadcast::new(
+        validators.clone(),
+        sender,
+        backoff,
+        time_service.clone(),
+        Arc::new(InMemBroadcastStore::default()),
+    );
+    let message = TestMessage(vec![42; validators.len()]);
+    let aggregating = TestBroadcastStatus {
+        threshold: validators.len(),
+        received: HashSet::new(),
+    };
+    let fut = rb.broadcast::<TestBroadcastStatus>(message, aggregating);
+    assert_eq!(fut.await.unwrap(), validators.into_iter().collect());
+    // 10 + 20 + 40 + 80 + 100 (capped)
+    assert_eq!(
+        time_service.get_current_timestamp(),
+        Duration::from_millis(250)
+    );
+}
+
+/// Answers the given number of rpcs to a peer with the message itself instead of an ack.
+struct InvalidAckDAGSender {
+    invalid: Mutex<HashMap<Author, u8>>,
+    inner: TestDAGSender,
+}
+
+#[async_trait]
+impl DAGNetworkSender for InvalidAckDAGSender {
+    async fn send_rpc(
+        &self,
+        receiver: Author,
+        message: ConsensusMsg,
+        timeout: Duration,
+    ) -> anyhow::Result<ConsensusMsg> {
+        if let Entry::Occupied(mut entry) = self.invalid.lock().entry(receiver) {
+            let count = entry.get_mut();
+            *count -= 1;
+            if *count == 0 {
+                entry.remove();
+            }
+            return Ok(message);
+        }
+        self.inner.send_rpc(receiver, message, timeout).await
+    }
+
+    async fn send_rpc_with_fallbacks(
+        &self,
+        _responders: Vec<Author>,
+        _message: ConsensusMsg,
+        _timeout: Duration,
+    ) -> anyhow::Result<ConsensusMsg> {
+        unimplemented!();
+    }
+}
+
+/// Rejects the first ack of each of the given peers.
+struct RejectingBroadcastStatus {
+    rejecting: HashSet<Author>,
+    inner: TestBroadcastStatus,
+}
+
+impl BroadcastStatus for RejectingBroadcastStatus {
+    type Ack = TestAck;
+    type Aggregated = HashSet<Author>;
+    type Message = TestMessage;
+
+    fn add(&mut self, peer: Author, ack: Self::Ack) -> anyhow::Result<Option<Self::Aggregated>> {
+        if self.rejecting.remove(&peer) {
+            bail!("rejected ack");
+        }
+        self.inner.add(peer, ack)
+    }
+}
+
+#[tokio::test]
+async fn test_reliable_broadcast_retries_invalid_acks() {
+    let (_, validator_verifier) = random_validator_verifier(5, None, false);
+    let validators = validator_verifier.get_ordered_account_addresses();
+    let sender = Arc::new(InvalidAckDAGSender {
+        invalid: Mutex::new(HashMap::from([(validators[0], 2)])),
+        inner: TestDAGSender::new(HashMap::new()),
+    });
+    let time_service = Arc::new(SimulatedTimeService::new());
+    let backoff = BackoffConfig {
+        base: Duration::from_millis(10),
+        max: Duration::from_millis(100),
+        jitter: Duration::ZERO,
+    };
+    let rb = ReliableBroadcast::new(
+        validators.clone(),
+        sender,
+        backoff,
+        time_service.clone(),
+        Arc::new(InMemBroadcastStore::default()),
+    );
+    let message = TestMessage(vec![42; validators.len()]);
+    let aggregating = RejectingBroadcastStatus {
+        rejecting: HashSet::from([validators[1]]),
+        inner: TestBroadcastStatus {
+            threshold: validators.len(),
+            received: HashSet::new(),
+        },
+    };
+    let aggregated = tokio::time::timeout(
+        Duration::from_secs(1),
+        rb.broadcast::<RejectingBroadcastStatus>(message, aggregating),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+    assert_eq!(aggregated, validators.into_iter().collect());
+    // both peers are retried, 10 + 20 for the one answering with the message twice and 10 for
+    // the one whose ack was rejected
+    assert_eq!(
+        time_service.get_current_timestamp(),
+        Duration::from_millis(40)
+    );
+}
+
+#[tokio::test]
+async fn test_reliable_broadcast_without_receivers() {
+    let rb = ReliableBroadcast::new(
+        vec![],
+        Arc::new(TestDAGSender::new(HashMap::new())),
+        BackoffConfig::default(),
+        Arc::new(SimulatedTimeService::new()),
+        Arc::new(InMemBroadcastStore::default()),
+    );
+    let aggregating = TestBroadcastStatus {
+        threshold: 1,
+        received: HashSet::new(),
+    };
+    assert!(rb
+        .broadcast::<TestBroadcastStatus>(TestMessage(vec![42]), aggregating)
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn test_reliable_broadcast_resume() {
+    let (_, validator_verifier) = random_validator_verifier(5, None, false);
+    let validators = validator_verifier.get_ordered_account_addresses();
+    let store = Arc::new(InMemBroadcastStore::default());
+    let sender = Arc::new(PartitionedDAGSender {
+        unreachable: validators[3..].iter().cloned().collect(),
+        inner: TestDAGSender::new(HashMap::new()),
+    });
+    let rb = ReliableBroadcast::new(
+        validators.clone(),
+        sender,
+        BackoffConfig::default(),
+        Arc::new(SimulatedTimeService::new()),
+        store.clone(),
+    );
+    let message = TestMessage(vec![42; validators.len()]);
+    let aggregating = TestBroadcastStatus {
+        threshold: validators.len(),
+        received: HashSet::new(),
+    };
+    // the node restarts while waiting on the unreachable peers
+    assert!(rb
+        .broadcast::<TestBroadcastStatus>(message, aggregating)
+        .now_or_never()
+        .is_none());
+    drop(rb);
+
+    let sender = Arc::new(TestDAGSender::new(HashMap::new()));
+    let rb = ReliableBroadcast::new(
+        validators.clone(),
+        sender.clone(),
+        BackoffConfig::default(),
+        Arc::new(SimulatedTimeService::new()),
+        store,
+    );
+    let pending_messages = rb.pending_broadcasts().unwrap();
+    assert_eq!(pending_messages.len(), 1);
+    let message = TestMessage::try_from(pending_messages[0].clone()).unwrap();
+    let aggregating = TestBroadcastStatus {
+        threshold: validators.len(),
+        received: HashSet::new(),
+    };
+    let aggregated = rb
+        .broadcast::<TestBroadcastStatus>(message, aggregating)
+        .await
+        .unwrap();
+    assert_eq!(aggregated, validators.iter().cloned().collect());
+    // only the peers that had not acked before the restart are contacted again
+    assert_eq!(
+        sender
+            .received
+            .lock()
+            .keys()
+            .cloned()
+            .collect::<HashSet<_>>(),
+        validators[3..].iter().cloned().collect()
+    );
+    assert!(rb.pending_broadcasts().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_reliable_broadcast_expiry() {
+    let (_, validator_verifier) = random_validator_verifier(5, None, false);
+    let validators = validator_verifier.get_ordered_account_addresses();
+    let sender = Arc::new(PartitionedDAGSender {
+        unreachable: validators[3..].iter().cloned().collect(),
+        inner: TestDAGSender::new(HashMap::new()),
+    });
+    let rb = ReliableBroadcast::new(
+        validators.clone(),
+        sender,
+        BackoffConfig::default(),
+        Arc::new(SimulatedTimeService::new()),
+        Arc::new(InMemBroadcastStore::default()),
+    )
+    .with_round_ttl(2);
+    rb.set_current_round(1);
+    let message = TestMessage(vec![42; validators.len()]);
+    let aggregating = TestBroadcastStatus {
+        threshold: validators.len(),
+        received: HashSet::new(),
+    };
+    let handle =
+        tokio::spawn(rb.broadcast_with_expiry::<TestBroadcastStatus>(message, aggregating));
+
+    // still within the ttl, the broadcast keeps waiting on the unreachable peers
+    rb.set_current_round(2);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(!handle.is_finished());
+    assert_eq!(rb.pending_broadcasts().unwrap().len(), 1);
+
+    // two rounds later it's abandoned without a quorum
+    rb.set_current_round(3);
+    assert!(handle.await.unwrap().unwrap().is_none());
+    assert!(rb.pending_broadcasts().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_reliable_broadcast_delivery_status() {
+    let (_, validator_verifier) = random_validator_verifier(5, None, false);
+    let validators = validator_verifier.get_ordered_account_addresses();
+    let sender = Arc::new(PartitionedDAGSender {
+        unreachable: validators[3..].iter().cloned().collect(),
+        inner: TestDAGSender::new(HashMap::new()),
+    });
+    let time_service = Arc::new(SimulatedTimeService::new());
+    let rb = ReliableBroadcast::new(
+        validators.clone(),
+        sender,
+        BackoffConfig::default(),
+        time_service.clone(),
+        Arc::new(InMemBroadcastStore::default()),
+    );
+    let message = TestMessage(vec![42; validators.len()]);
+    let digest = broadcast_digest(&DAGMessage::from(message.clone()));
+    let aggregating = TestBroadcastStatus {
+        threshold: validators.len(),
+        received: HashSet::new(),
+    };
+    time_service.sleep(Duration::from_secs(10)).await;
+    let start = time_service.get_current_timestamp();
+    let handle = tokio::spawn(rb.broadcast::<TestBroadcastStatus>(message, aggregating));
+
+    let num_acked = |rb: &ReliableBroadcast| {
+        rb.delivery_status(digest)
+            .iter()
+            .filter(|(_, acked_at)| acked_at.is_some())
+            .count()
+    };
+    while num_acked(&rb) < 3 {
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    // only the reachable peers acked, the broadcast is stuck on the others
+    let status = rb.delivery_status(digest);
+    assert_eq!(
+        status.iter().map(|(peer, _)| *peer).collect::<Vec<_>>(),
+        validators
+    );
+    // stamped by the time service, which only moves when the broadcast backs off
+    let now = time_service.get_current_timestamp();
+    assert!(status[..3].iter().all(
+        |(_, acked_at)| matches!(acked_at, Some(acked_at) if (start..=now).contains(acked_at))
+    ));
+    assert!(status[3..].iter().all(|(_, acked_at)| acked_at.is_none()));
+
+    // the receipts go away with the broadcast
+    handle.abort();
+    assert!(handle.await.unwrap_err().is_cancelled());
+    assert_eq!(num_acked(&rb), 0);
+}
+
+#[tokio::test]
+async fn test_concurrent_broadcasts_of_same_message() {
+    let (_, validator_verifier) = random_validator_verifier(5, None, false);
+    let validators = validator_verifier.get_ordered_account_addresses();
+    let sender = Arc::new(PartitionedDAGSender {
+        unreachable: validators[3..].iter().cloned().collect(),
+        inner: TestDAGSender::new(HashMap::new()),
+    });
+    let rb = ReliableBroadcast::new(
+        validators.clone(),
+        sender,
+        BackoffConfig::default(),
+        Arc::new(SimulatedTimeService::new()),
+        Arc::new(InMemBroadcastStore::default()),
+    );
+    let message = TestMessage(vec![42; validators.len()]);
+    let digest = broadcast_digest(&DAGMessage::from(message.clone()));
+    let new_aggregating = || TestBroadcastStatus {
+        threshold: validators.len(),
+        received: HashSet::new(),
+    };
+    let first =
+        tokio::spawn(rb.broadcast::<TestBroadcastStatus>(message.clone(), new_aggregating()));
+    let second = tokio::spawn(rb.broadcast::<TestBroadcastStatus>(message, new_aggregating()));
+    let num_acked = |rb: &ReliableBroadcast| {
+        rb.delivery_status(digest)
+            .iter()
+            .filter(|(_, acked_at)| acked_at.is_some())
+            .count()
+    };
+    while num_acked(&rb) < 3 {
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+    assert_eq!(rb.num_outstanding_broadcasts(), 2);
+
+    // the broadcast that's still in progress keeps its receipts
+    first.abort();
+    assert!(first.await.unwrap_err().is_cancelled());
+    assert_eq!(rb.num_outstanding_broadcasts(), 1);
+    tokio::time::timeout(Duration::from_secs(1), async {
+        while num_acked(&rb) < 3 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+    })
+    .await
+    .unwrap();
+    second.abort();
+    assert!(second.await.unwrap_err().is_cancelled());
+    assert_eq!(rb.num_outstanding_broadcasts(), 0);
+    assert_eq!(num_acked(&rb), 0);
+}
+
 #[tokio::test]
 async fn test_node_broadcast_receiver_succeed() {
     let (signers, validator_verifier) = random_validator_verifier(4, None, false);
@@ -198,9 +642,12 @@ async fn test_node_broadcast_receiver_succeed() {
         wellformed_node.sign(&signers[3]).unwrap(),
     );
     // expect an ack for a valid message
-    assert_ok_eq!(rb_receiver.process(wellformed_node), expected_result);
+    assert_ok_eq!(rb_receiver.process(wellformed_node).await, expected_result);
     // expect the original ack for any future message from same author
-    assert_ok_eq!(rb_receiver.process(equivocating_node), expected_result);
+    assert_ok_eq!(
+        rb_receiver.process(equivocating_node).await,
+        expected_result
+    );
 }
 
 #[tokio::test]
@@ -219,7 +666,7 @@ async fn test_node_broadcast_receiver_failure() {
 
     // Round 0
     let node = new_node(0, 10, signers[0].author(), vec![]);
-    let node_sig = rb_receivers[1].process(node.clone()).unwrap();
+    let node_sig = rb_receivers[1].process(node.clone()).await.unwrap();
 
     // Round 1 without enough parents
     let partial_sigs = PartialSignatures::new(BTreeMap::from([(
@@ -234,36 +681,31 @@ async fn test_node_broadcast_receiver_failure() {
     );
     let node = new_node(1, 20, signers[0].author(), vec![node_cert]);
     assert_eq!(
-        rb_receivers[1].process(node).unwrap_err().to_string(),
+        rb_receivers[1].process(node).await.unwrap_err().to_string(),
         "not enough voting power"
     );
 
     // Round 0 - add all nodes
-    let node_certificates: Vec<_> = signers
-        .iter()
-        .map(|signer| {
-            let node = new_node(0, 10, signer.author(), vec![]);
-            let mut partial_sigs = PartialSignatures::empty();
-            rb_receivers
-                .iter_mut()
-                .zip(&signers)
-                .for_each(|(rb_receiver, signer)| {
-                    let sig = rb_receiver.process(node.clone()).unwrap();
-                    partial_sigs.add_signature(signer.author(), sig.signature().clone())
-                });
-            NodeCertificate::new(
-                node.metadata().clone(),
-                validator_verifier
-                    .aggregate_signatures(&partial_sigs)
-                    .unwrap(),
-            )
-        })
-        .collect();
+    let mut node_certificates = vec![];
+    for signer in &signers {
+        let node = new_node(0, 10, signer.author(), vec![]);
+        let mut partial_sigs = PartialSignatures::empty();
+        for (rb_receiver, signer) in rb_receivers.iter_mut().zip(&signers) {
+            let sig = rb_receiver.process(node.clone()).await.unwrap();
+            partial_sigs.add_signature(signer.author(), sig.signature().clone())
+        }
+        node_certificates.push(NodeCertificate::new(
+            node.metadata().clone(),
+            validator_verifier
+                .aggregate_signatures(&partial_sigs)
+                .unwrap(),
+        ));
+    }
 
     // Add Round 1 node with proper certificates
     let node = new_node(1, 20, signers[0].author(), node_certificates);
     assert_eq!(
-        rb_receivers[0].process(node).unwrap_err().to_string(),
+        rb_receivers[0].process(node).await.unwrap_err().to_string(),
         NodeBroadcastHandleError::MissingParents.to_string()
     );
 }
@@ -271,3 +713,104 @@ async fn test_node_broadcast_receiver_failure() {
 fn new_node(round: Round, timestamp: u64, author: Author, parents: Vec<NodeCertificate>) -> Node {
     Node::new(0, round, author, timestamp, Payload::empty(false), parents)
 }
+
+/// Acks certificates right away, after a delay for the slow peers and never for the unreachable
+/// ones.
+struct CertifiedAckDAGSender {
+    signers: HashMap<Author, ValidatorSigner>,
+    slow: HashSet<Author>,
+    unreachable: HashSet<Author>,
+    received: Mutex<HashSet<Author>>,
+}
+
+#[async_trait]
+impl DAGNetworkSender for CertifiedAckDAGSender {
+    async fn send_rpc(
+        &self,
+        receiver: Author,
+        message: ConsensusMsg,
+        _timeout: Duration,
+    ) -> anyhow::Result<ConsensusMsg> {
+        let certificate = NodeCertificate::try_from(DAGMessage::try_from(message)?)?;
+        if self.unreachable.contains(&receiver) {
+            return pending().await;
+        }
+        if self.slow.contains(&receiver) {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        self.received.lock().insert(receiver);
+        let ack = CertifiedAck::new(
+            1,
+            *certificate.metadata().digest(),
+            &self.signers[&receiver],
+        )?;
+        Ok(DAGMessage::from(ack).into_network_message())
+    }
+
+    async fn send_rpc_with_fallbacks(
+        &self,
+        _responders: Vec<Author>,
+        _message: ConsensusMsg,
+        _timeout: Duration,
+    ) -> anyhow::Result<ConsensusMsg> {
+        unimplemented!();
+    }
+}
+
+#[tokio::test]
+async fn test_certificate_broadcast_stops_at_quorum() {
+    let signers: Vec<_> = (0..4).map(|i| ValidatorSigner::random([i; 32])).collect();
+    // the first validator holds 5 of the 8 votes, the quorum is 6
+    let validator_verifier = ValidatorVerifier::new(
+        signers
+            .iter()
+            .zip([5, 1, 1, 1])
+            .map(|(signer, voting_power)| {
+                ValidatorConsensusInfo::new(signer.author(), signer.public_key(), voting_power)
+            })
+            .collect(),
+    );
+    let validators = validator_verifier.get_ordered_account_addresses();
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier.clone(),
+    });
+    let sender = Arc::new(CertifiedAckDAGSender {
+        signers: signers
+            .iter()
+            .map(|signer| (signer.author(), signer.clone()))
+            .collect(),
+        slow: HashSet::from([signers[2].author()]),
+        unreachable: HashSet::from([signers[3].author()]),
+        received: Mutex::new(HashSet::new()),
+    });
+    let rb = ReliableBroadcast::new(
+        validators,
+        sender.clone(),
+        BackoffConfig::default(),
+        Arc::new(SimulatedTimeService::new()),
+        Arc::new(InMemBroadcastStore::default()),
+    )
+    .with_best_effort_delivery(Duration::from_millis(200));
+    let node = Node::new(1, 1, signers[0].author(), 0, Payload::empty(false), vec![]);
+    let certificate = NodeCertificate::new(node.metadata().clone(), AggregateSignature::empty());
+    let digest = *certificate.metadata().digest();
+
+    // two acks carry 6 of the 8 votes, the broadcast doesn't wait for the other peers
+    let ack_certificate = tokio::time::timeout(
+        Duration::from_secs(1),
+        rb.broadcast(certificate, CertificateAckState::new(digest, epoch_state)),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+    assert!(ack_certificate.verify(&validator_verifier).is_ok());
+    assert_eq!(
+        *sender.received.lock(),
+        HashSet::from([signers[0].author(), signers[1].author()])
+    );
+
+    // the slow peer still gets the certificate on a best effort basis
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(sender.received.lock().contains(&signers[2].author()));
+}
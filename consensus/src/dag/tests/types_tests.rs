@@ -0,0 +1,511 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::dag::{
+    reliable_broadcast::BroadcastStatus,
+    types::{
+        verify_certificates, BatchFetchRequest, BatchFetchResponse, BatchFetchTarget,
+        CertificateAckState, CertifiedAck, CertifiedNode, DAGMessage, DAGMessageSizeError,
+        DAGNetworkMessage, DAGVersionError, DecodeError, FetchRequest, FetchResponse, Node,
+        NodeCertificate, NodeDigestSignature, RoundTimeout, RoundTimeoutAck,
+        RoundTimeoutCertificate, TDAGMessage, TestAck, TestMessage, DAG_MAJOR_VERSION,
+        DAG_MINOR_VERSION, DEFAULT_COMPRESSION_THRESHOLD, UNCOMPRESSED_FLAG,
+    },
+};
+use aptos_consensus_types::common::Payload;
+use aptos_crypto::HashValue;
+use aptos_types::{
+    aggregate_signature::{AggregateSignature, PartialSignatures},
+    epoch_state::EpochState,
+    validator_signer::ValidatorSigner,
+    validator_verifier::{random_validator_verifier, ValidatorConsensusInfo, ValidatorVerifier},
+};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, ops::Deref, sync::Arc};
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Message {
+    round: u64,
+    payload: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct MessageWithExtension {
+    round: u64,
+    payload: Vec<u8>,
+    extension: Option<String>,
+}
+
+fn versioned(major: u8, minor: u8, payload: Vec<u8>) -> DAGNetworkMessage {
+    let mut data = vec![UNCOMPRESSED_FLAG];
+    data.extend(payload);
+    DAGNetworkMessage {
+        version: ((major as u16) << 8) | minor as u16,
+        epoch: 1,
+        data,
+    }
+}
+
+#[test]
+fn test_decode_newer_minor_version() {
+    let newer = MessageWithExtension {
+        round: 10,
+        payload: vec![1, 2, 3],
+        extension: Some("unknown".to_string()),
+    };
+    let msg = versioned(
+        DAG_MAJOR_VERSION,
+        DAG_MINOR_VERSION + 1,
+        bcs::to_bytes(&newer).unwrap(),
+    );
+    let decoded: Message = msg.decode().unwrap();
+    assert_eq!(decoded, Message {
+        round: 10,
+        payload: vec![1, 2, 3],
+    });
+
+    // trailing bytes are still rejected within the same minor version
+    let msg = versioned(
+        DAG_MAJOR_VERSION,
+        DAG_MINOR_VERSION,
+        bcs::to_bytes(&newer).unwrap(),
+    );
+    assert!(msg.decode::<Message>().is_err());
+}
+
+#[test]
+fn test_decode_current_version() {
+    let message = Message {
+        round: 1,
+        payload: vec![],
+    };
+    let msg = DAGNetworkMessage::new(1, &message).unwrap();
+    assert_eq!(msg.major_version(), DAG_MAJOR_VERSION);
+    assert_eq!(msg.minor_version(), DAG_MINOR_VERSION);
+    assert_eq!(msg.decode::<Message>().unwrap(), message);
+}
+
+#[test]
+fn test_reject_incompatible_major_version() {
+    let message = Message {
+        round: 1,
+        payload: vec![],
+    };
+    let msg = versioned(
+        DAG_MAJOR_VERSION + 1,
+        DAG_MINOR_VERSION,
+        bcs::to_bytes(&message).unwrap(),
+    );
+    let err = msg.decode::<Message>().unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<DAGVersionError>(),
+        Some(DAGVersionError::IncompatibleMajorVersion(_))
+    ));
+}
+
+#[test]
+fn test_compression_round_trip() {
+    let message = Message {
+        round: 1,
+        payload: (0..64 * 1024).map(|i| (i % 16) as u8).collect(),
+    };
+    let raw_size = bcs::serialized_size(&message).unwrap();
+    let msg = DAGNetworkMessage::new(1, &message).unwrap();
+    assert!(msg.is_compressed());
+    assert!(msg.data.len() < raw_size / 2);
+    assert_eq!(msg.decode::<Message>().unwrap(), message);
+
+    // compression can be turned off, the receiver decodes either form
+    let msg = DAGNetworkMessage::with_compression_threshold(1, &message, usize::MAX).unwrap();
+    assert!(!msg.is_compressed());
+    assert_eq!(msg.decode::<Message>().unwrap(), message);
+}
+
+#[test]
+fn test_small_message_not_compressed() {
+    let message = Message {
+        round: 1,
+        payload: vec![0; DEFAULT_COMPRESSION_THRESHOLD / 2],
+    };
+    let msg = DAGNetworkMessage::new(1, &message).unwrap();
+    assert!(!msg.is_compressed());
+    assert_eq!(msg.data.len(), bcs::serialized_size(&message).unwrap() + 1);
+}
+
+#[test]
+fn test_high_priority_without_decoding() {
+    let signer = ValidatorSigner::random(None);
+    let node = Node::new(1, 1, signer.author(), 0, Payload::empty(false), vec![]);
+    let certificate = NodeCertificate::new(node.metadata().clone(), AggregateSignature::empty());
+    let certified_node = CertifiedNode::new(node.clone(), certificate.clone());
+    let messages = vec![
+        DAGMessage::NodeMsg(node.clone()),
+        DAGMessage::NodeDigestSignatureMsg(NodeDigestSignature::new(
+            1,
+            node.digest(),
+            node.sign(&signer).unwrap(),
+        )),
+        DAGMessage::NodeCertificateMsg(certificate),
+        DAGMessage::CertifiedAckMsg(CertifiedAck::new(1, HashValue::random(), &signer).unwrap()),
+        DAGMessage::FetchRequest(FetchRequest::new(node.metadata().clone(), 0, vec![])),
+        DAGMessage::FetchResponse(FetchResponse::new(1, vec![vec![certified_node.clone()]])),
+        DAGMessage::RoundTimeoutMsg(RoundTimeout::new(1, 2, &signer).unwrap()),
+        DAGMessage::BatchFetchRequest(BatchFetchRequest::new(1, BatchFetchTarget::Rounds(1, 2))),
+        DAGMessage::BatchFetchResponse(BatchFetchResponse::new(1, vec![certified_node], true)),
+        DAGMessage::RoundTimeoutAckMsg(RoundTimeoutAck::new(1, 2)),
+        DAGMessage::TestMessage(TestMessage(vec![7; 16])),
+        DAGMessage::TestAck(TestAck(vec![7; 16])),
+        DAGMessage::TestMessage(TestMessage(vec![7; 2 * DEFAULT_COMPRESSION_THRESHOLD])),
+    ];
+    let mut variants = HashSet::new();
+    for message in messages {
+        // the bcs tag is the index of the variant, the match fails to compile on a new variant
+        // until it's covered here
+        let variant = match &message {
+            DAGMessage::NodeMsg(_) => 0,
+            DAGMessage::NodeDigestSignatureMsg(_) => 1,
+            DAGMessage::NodeCertificateMsg(_) => 2,
+            DAGMessage::CertifiedAckMsg(_) => 3,
+            DAGMessage::FetchRequest(_) => 4,
+            DAGMessage::FetchResponse(_) => 5,
+            DAGMessage::RoundTimeoutMsg(_) => 6,
+            DAGMessage::BatchFetchRequest(_) => 7,
+            DAGMessage::BatchFetchResponse(_) => 8,
+            DAGMessage::RoundTimeoutAckMsg(_) => 9,
+            DAGMessage::TestMessage(_) => 10,
+            DAGMessage::TestAck(_) => 11,
+        };
+        assert_eq!(
+            bcs::to_bytes(&message).unwrap()[0],
+            variant,
+            "{}",
+            message.name()
+        );
+        variants.insert(variant);
+        let msg = DAGNetworkMessage::new(1, &message).unwrap();
+        assert_eq!(
+            msg.is_high_priority(),
+            message.is_high_priority(),
+            "{}",
+            message.name()
+        );
+    }
+    assert_eq!(variants.len(), 12);
+}
+
+#[test]
+fn test_decode_size_limit() {
+    let message = Message {
+        round: 1,
+        payload: vec![0; 2048],
+    };
+    let msg = DAGNetworkMessage::with_compression_threshold(1, &message, usize::MAX).unwrap();
+    let err = msg.decode_with_limit::<Message>(1024).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<DAGMessageSizeError>(),
+        Some(DAGMessageSizeError::TooLarge(_, 1024))
+    ));
+    assert_eq!(msg.decode_with_limit::<Message>(4096).unwrap(), message);
+
+    // a small compressed payload that would blow up is rejected by its declared size
+    let message = Message {
+        round: 1,
+        payload: vec![0; 64 * 1024],
+    };
+    let msg = DAGNetworkMessage::new(1, &message).unwrap();
+    assert!(msg.is_compressed());
+    assert!(msg.data.len() < 1024);
+    let err = msg.decode_with_limit::<Message>(1024).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<DAGMessageSizeError>(),
+        Some(DAGMessageSizeError::TooLarge(size, 1024)) if *size > 64 * 1024
+    ));
+}
+
+#[test]
+fn test_network_message_bytes_round_trip() {
+    for payload_len in [0, 1, 127, 128, 300, 64 * 1024] {
+        let message = Message {
+            round: 1,
+            payload: vec![7; payload_len],
+        };
+        let msg = DAGNetworkMessage::new(1, &message).unwrap();
+        let bytes = msg.to_bytes();
+        assert_eq!(bytes, bcs::to_bytes(&msg).unwrap());
+        let decoded = DAGNetworkMessage::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.version, msg.version);
+        assert_eq!(decoded.epoch, msg.epoch);
+        assert_eq!(decoded.decode::<Message>().unwrap(), message);
+    }
+}
+
+#[test]
+fn test_network_message_malformed_bytes() {
+    let message = Message {
+        round: 1,
+        payload: vec![7; 300],
+    };
+    let bytes = DAGNetworkMessage::new(1, &message).unwrap().to_bytes();
+
+    // every truncation is caught, in the header, the length prefix or the payload
+    for len in 0..bytes.len() {
+        assert!(matches!(
+            DAGNetworkMessage::from_bytes(&bytes[..len]),
+            Err(DecodeError::Truncated(_))
+        ));
+    }
+    let mut trailing = bytes.clone();
+    trailing.push(0);
+    assert_eq!(
+        DAGNetworkMessage::from_bytes(&trailing).unwrap_err(),
+        DecodeError::TrailingBytes(1)
+    );
+
+    let header = &bytes[..10];
+    let with_length = |prefix: &[u8]| [header, prefix].concat();
+    // a length beyond the message size limit is rejected before anything is allocated
+    assert!(matches!(
+        DAGNetworkMessage::from_bytes(&with_length(&[0xFF, 0xFF, 0xFF, 0xFF, 0x0F])),
+        Err(DecodeError::TooLarge(_, _))
+    ));
+    // lengths that overflow a u32, don't terminate or aren't minimally encoded
+    for prefix in [
+        &[0xFF, 0xFF, 0xFF, 0xFF, 0x1F][..],
+        &[0x80, 0x80, 0x80, 0x80, 0x80, 0x01],
+        &[0x80, 0x00],
+    ] {
+        assert_eq!(
+            DAGNetworkMessage::from_bytes(&with_length(prefix)).unwrap_err(),
+            DecodeError::InvalidLength
+        );
+    }
+}
+
+#[test]
+fn test_network_message_garbage_bytes() {
+    let mut rng = rand::thread_rng();
+    for _ in 0..10_000 {
+        let len = rng.gen_range(0, 64);
+        let bytes: Vec<u8> = (0..len).map(|_| rng.gen()).collect();
+        // garbage is rejected without panicking, unless it happens to be a valid encoding
+        if let Ok(msg) = DAGNetworkMessage::from_bytes(&bytes) {
+            assert_eq!(msg.to_bytes(), bytes);
+            let _ = msg.decode::<Message>();
+        }
+    }
+}
+
+fn new_node_certificate(
+    node: &Node,
+    signers: &[ValidatorSigner],
+    verifier: &ValidatorVerifier,
+) -> NodeCertificate {
+    let mut partial_sigs = PartialSignatures::empty();
+    for signer in signers {
+        partial_sigs.add_signature(signer.author(), node.sign(signer).unwrap());
+    }
+    NodeCertificate::new(
+        node.metadata().clone(),
+        verifier.aggregate_signatures(&partial_sigs).unwrap(),
+    )
+}
+
+#[test]
+fn test_batch_verify_certificates() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let nodes: Vec<_> = signers
+        .iter()
+        .map(|signer| Node::new(1, 0, signer.author(), 0, Payload::empty(false), vec![]))
+        .collect();
+    let mut certificates: Vec<_> = nodes
+        .iter()
+        .map(|node| new_node_certificate(node, &signers[..3], &validator_verifier))
+        .collect();
+    assert_eq!(
+        verify_certificates(&certificates, &validator_verifier),
+        Ok(())
+    );
+
+    // swapped signatures of the same signers, each is invalid but their sum is the sum of the
+    // valid ones
+    let swapped = vec![
+        NodeCertificate::new(
+            nodes[0].metadata().clone(),
+            certificates[1].signatures().clone(),
+        ),
+        NodeCertificate::new(
+            nodes[1].metadata().clone(),
+            certificates[0].signatures().clone(),
+        ),
+    ];
+    assert_eq!(
+        verify_certificates(&swapped, &validator_verifier),
+        Err(vec![0, 1])
+    );
+
+    // signatures over another node's digest
+    certificates[2] = NodeCertificate::new(
+        nodes[2].metadata().clone(),
+        certificates[1].signatures().clone(),
+    );
+    assert_eq!(
+        verify_certificates(&certificates, &validator_verifier),
+        Err(vec![2])
+    );
+
+    // not enough signers
+    certificates[0] = new_node_certificate(&nodes[0], &signers[..1], &validator_verifier);
+    assert_eq!(
+        verify_certificates(&certificates, &validator_verifier),
+        Err(vec![0, 2])
+    );
+}
+
+#[test]
+fn test_batch_fetch_response_verify() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let nodes: Vec<_> = signers
+        .iter()
+        .map(|signer| {
+            let node = Node::new(1, 1, signer.author(), 0, Payload::empty(false), vec![]);
+            let certificate = new_node_certificate(&node, &signers[..3], &validator_verifier);
+            CertifiedNode::new(node, certificate)
+        })
+        .collect();
+    let rounds_request = BatchFetchRequest::new(1, BatchFetchTarget::Rounds(1, 2));
+    let response = BatchFetchResponse::new(1, nodes.clone(), true);
+    assert!(response
+        .clone()
+        .verify(&rounds_request, &validator_verifier)
+        .is_ok());
+
+    // from another epoch
+    let request = BatchFetchRequest::new(2, BatchFetchTarget::Rounds(1, 2));
+    assert!(response
+        .clone()
+        .verify(&request, &validator_verifier)
+        .is_err());
+
+    // outside of the requested rounds or digests
+    let request = BatchFetchRequest::new(1, BatchFetchTarget::Rounds(2, 3));
+    assert!(response
+        .clone()
+        .verify(&request, &validator_verifier)
+        .is_err());
+    let request = BatchFetchRequest::new(1, BatchFetchTarget::Digests(vec![nodes[0].digest()]));
+    assert!(response.verify(&request, &validator_verifier).is_err());
+    let response = BatchFetchResponse::new(1, nodes[..1].to_vec(), true);
+    assert!(response.verify(&request, &validator_verifier).is_ok());
+
+    // the certificate of another node
+    let mismatched = CertifiedNode::new(nodes[0].deref().clone(), nodes[1].certificate().clone());
+    let response = BatchFetchResponse::new(1, vec![mismatched], true);
+    assert!(response
+        .verify(&rounds_request, &validator_verifier)
+        .is_err());
+
+    // the signatures of another node
+    let forged = CertifiedNode::new(
+        nodes[0].deref().clone(),
+        NodeCertificate::new(
+            nodes[0].metadata().clone(),
+            nodes[1].certificate().signatures().clone(),
+        ),
+    );
+    let response = BatchFetchResponse::new(1, vec![forged], true);
+    assert!(response
+        .verify(&rounds_request, &validator_verifier)
+        .is_err());
+}
+
+#[test]
+fn test_certified_ack_aggregation() {
+    let signers: Vec<_> = (0..4).map(|i| ValidatorSigner::random([i; 32])).collect();
+    // 10 votes in total, the quorum is 7
+    let validator_verifier = ValidatorVerifier::new(
+        signers
+            .iter()
+            .zip([4, 3, 2, 1])
+            .map(|(signer, voting_power)| {
+                ValidatorConsensusInfo::new(signer.author(), signer.public_key(), voting_power)
+            })
+            .collect(),
+    );
+    let epoch_state = Arc::new(EpochState {
+        epoch: 1,
+        verifier: validator_verifier.clone(),
+    });
+    let digest = HashValue::random();
+    let mut state = CertificateAckState::new(digest, epoch_state);
+    let ack = |signer: &ValidatorSigner| CertifiedAck::new(1, digest, signer).unwrap();
+
+    assert!(state
+        .add(signers[2].author(), ack(&signers[2]))
+        .unwrap()
+        .is_none());
+    // duplicates don't count twice, invalid acks are rejected
+    assert!(state
+        .add(signers[2].author(), ack(&signers[2]))
+        .unwrap()
+        .is_none());
+    assert!(state.add(signers[3].author(), ack(&signers[2])).is_err());
+    assert!(state
+        .add(
+            signers[3].author(),
+            CertifiedAck::new(1, HashValue::random(), &signers[3]).unwrap()
+        )
+        .is_err());
+    assert!(state
+        .add(signers[3].author(), ack(&signers[3]))
+        .unwrap()
+        .is_none());
+
+    // 2 + 1 + 4 votes reach the quorum
+    let certificate = state
+        .add(signers[0].author(), ack(&signers[0]))
+        .unwrap()
+        .unwrap();
+    assert_eq!(certificate.digest(), &digest);
+    assert_eq!(
+        certificate.signatures().get_signers_bitvec().count_ones(),
+        3
+    );
+    assert!(certificate.verify(&validator_verifier).is_ok());
+}
+
+#[test]
+fn test_node_with_timeout_certificate() {
+    let (signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let parent = Node::new(1, 1, signers[0].author(), 0, Payload::empty(false), vec![]);
+    let parents = vec![new_node_certificate(&parent, &signers, &validator_verifier)];
+    let node = Node::new(1, 2, signers[0].author(), 0, Payload::empty(false), parents);
+    let timeout_certificate = |round| {
+        let mut partial_sigs = PartialSignatures::empty();
+        for signer in &signers[..3] {
+            let timeout = RoundTimeout::new(1, round, signer).unwrap();
+            partial_sigs.add_signature(signer.author(), timeout.signature().clone());
+        }
+        RoundTimeoutCertificate::new(
+            1,
+            round,
+            validator_verifier
+                .aggregate_signatures(&partial_sigs)
+                .unwrap(),
+        )
+    };
+
+    // a single parent is short of a quorum
+    assert!(node.verify(&validator_verifier).is_err());
+
+    // the certificate of the previous round timing out lets it in, and is part of the digest
+    let with_certificate = node
+        .clone()
+        .with_timeout_certificate(timeout_certificate(1));
+    assert_ne!(with_certificate.digest(), node.digest());
+    assert!(with_certificate.verify(&validator_verifier).is_ok());
+
+    // the certificate has to be of the previous round
+    let with_certificate = node.with_timeout_certificate(timeout_certificate(2));
+    assert!(with_certificate.verify(&validator_verifier).is_err());
+}
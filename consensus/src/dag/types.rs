@@ -6,6 +6,8 @@ use crate::{
     network_interface::ConsensusMsg,
 };
 use anyhow::ensure;
+use aptos_bitvec::BitVec;
+use aptos_compression::metrics::CompressionClient;
 use aptos_consensus_types::common::{Author, Payload, Round};
 use aptos_crypto::{
     bls12381,
@@ -13,15 +15,20 @@ use aptos_crypto::{
     hash::{CryptoHash, CryptoHasher},
     CryptoMaterialError, HashValue,
 };
-use aptos_crypto_derive::CryptoHasher;
+use aptos_crypto_derive::{BCSCryptoHash, CryptoHasher};
+use aptos_network::constants::MAX_MESSAGE_SIZE;
 use aptos_types::{
     aggregate_signature::{AggregateSignature, PartialSignatures},
     epoch_state::EpochState,
     validator_signer::ValidatorSigner,
     validator_verifier::ValidatorVerifier,
 };
-use serde::{Deserialize, Serialize};
-use std::{collections::HashSet, ops::Deref, sync::Arc};
+use serde::{
+    de::{self, DeserializeOwned, DeserializeSeed, SeqAccess, Visitor},
+    Deserialize, Deserializer, Serialize,
+};
+use std::{collections::HashSet, fmt, marker::PhantomData, ops::Deref, sync::Arc};
+use thiserror::Error as ThisError;
 
 pub trait TDAGMessage: Into<DAGMessage> + TryFrom<DAGMessage> {
     fn verify(&self, verifier: &ValidatorVerifier) -> anyhow::Result<()>;
@@ -42,6 +49,16 @@ impl TDAGMessage for CertifiedAck {
         todo!()
     }
 }
+impl TDAGMessage for RoundTimeout {
+    fn verify(&self, verifier: &ValidatorVerifier) -> anyhow::Result<()> {
+        RoundTimeout::verify(self, verifier)
+    }
+}
+impl TDAGMessage for RoundTimeoutAck {
+    fn verify(&self, _verifier: &ValidatorVerifier) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
 
 #[derive(Serialize)]
 struct NodeWithoutDigest<'a> {
@@ -51,6 +68,7 @@ struct NodeWithoutDigest<'a> {
     timestamp: u64,
     payload: &'a Payload,
     parents: &'a Vec<NodeCertificate>,
+    timeout_certificate: &'a Option<RoundTimeoutCertificate>,
 }
 
 impl<'a> CryptoHash for NodeWithoutDigest<'a> {
@@ -73,6 +91,7 @@ impl<'a> From<&'a Node> for NodeWithoutDigest<'a> {
             timestamp: node.metadata.timestamp,
             payload: &node.payload,
             parents: &node.parents,
+            timeout_certificate: &node.timeout_certificate,
         }
     }
 }
@@ -124,12 +143,14 @@ impl CryptoHash for NodeDigest {
     }
 }
 
-/// Node representation in the DAG, parents contain 2f+1 strong links (links to previous round)
+/// Node representation in the DAG, parents contain 2f+1 strong links (links to previous round),
+/// or fewer when the node carries a timeout certificate of the previous round
 #[derive(Clone, Serialize, Deserialize, CryptoHasher, Debug)]
 pub struct Node {
     metadata: NodeMetadata,
     payload: Payload,
     parents: Vec<NodeCertificate>,
+    timeout_certificate: Option<RoundTimeoutCertificate>,
 }
 
 impl Node {
@@ -141,7 +162,8 @@ impl Node {
         payload: Payload,
         parents: Vec<NodeCertificate>,
     ) -> Self {
-        let digest = Self::calculate_digest(epoch, round, author, timestamp, &payload, &parents);
+        let digest =
+            Self::calculate_digest(epoch, round, author, timestamp, &payload, &parents, &None);
 
         Self {
             metadata: NodeMetadata {
@@ -153,9 +175,18 @@ impl Node {
             },
             payload,
             parents,
+            timeout_certificate: None,
         }
     }
 
+    /// Attaches the certificate of the previous round timing out, which justifies fewer than
+    /// 2f+1 parents.
+    pub fn with_timeout_certificate(mut self, certificate: RoundTimeoutCertificate) -> Self {
+        self.timeout_certificate = Some(certificate);
+        self.metadata.digest = NodeWithoutDigest::from(&self).hash();
+        self
+    }
+
     /// Calculate the node digest based on all fields in the node
     fn calculate_digest(
         epoch: u64,
@@ -164,6 +195,7 @@ impl Node {
         timestamp: u64,
         payload: &Payload,
         parents: &Vec<NodeCertificate>,
+        timeout_certificate: &Option<RoundTimeoutCertificate>,
     ) -> HashValue {
         let node_with_out_digest = NodeWithoutDigest {
             epoch,
@@ -172,6 +204,7 @@ impl Node {
             timestamp,
             payload,
             parents,
+            timeout_certificate,
         };
         node_with_out_digest.hash()
     }
@@ -184,10 +217,18 @@ impl Node {
         &self.metadata
     }
 
+    pub fn payload(&self) -> &Payload {
+        &self.payload
+    }
+
     pub fn parents(&self) -> &[NodeCertificate] {
         &self.parents
     }
 
+    pub fn timeout_certificate(&self) -> Option<&RoundTimeoutCertificate> {
+        self.timeout_certificate.as_ref()
+    }
+
     pub fn author(&self) -> &Author {
         self.metadata.author()
     }
@@ -211,16 +252,27 @@ impl TDAGMessage for Node {
             "invalid parent round"
         );
 
-        ensure!(
-            verifier
-                .check_voting_power(
-                    self.parents()
-                        .iter()
-                        .map(|parent| parent.metadata().author())
-                )
-                .is_ok(),
-            "not enough voting power"
-        );
+        match self.timeout_certificate() {
+            // a quorum timed out on the previous round, fewer parents made it in time
+            Some(certificate) => {
+                ensure!(
+                    certificate.epoch() == self.metadata().epoch()
+                        && certificate.round() == prev_round,
+                    "timeout certificate of another round"
+                );
+                certificate.verify(verifier)?;
+            },
+            None => ensure!(
+                verifier
+                    .check_voting_power(
+                        self.parents()
+                            .iter()
+                            .map(|parent| parent.metadata().author())
+                    )
+                    .is_ok(),
+                "not enough voting power"
+            ),
+        }
 
         Ok(())
     }
@@ -254,6 +306,33 @@ impl NodeCertificate {
     }
 }
 
+/// Verifies the signatures of all certificates and returns the indices of the invalid ones.
+///
+/// Each certificate is verified on its own. Summing the signatures of several certificates into a
+/// single aggregate check would accept invalid certificates whose errors cancel each other out,
+/// e.g. two certificates with swapped signatures.
+pub fn verify_certificates(
+    certificates: &[NodeCertificate],
+    verifier: &ValidatorVerifier,
+) -> Result<(), Vec<usize>> {
+    let invalid: Vec<_> = certificates
+        .iter()
+        .enumerate()
+        .filter(|(_, certificate)| {
+            let digest = NodeDigest::new(*certificate.metadata().digest());
+            verifier
+                .verify_multi_signatures(&digest, certificate.signatures())
+                .is_err()
+        })
+        .map(|(index, _)| index)
+        .collect();
+    if invalid.is_empty() {
+        Ok(())
+    } else {
+        Err(invalid)
+    }
+}
+
 impl From<CertifiedNode> for NodeCertificate {
     fn from(node: CertifiedNode) -> Self {
         Self {
@@ -287,6 +366,43 @@ impl Deref for CertifiedNode {
     }
 }
 
+/// Two different certified nodes from the same author in the same round, which proves the
+/// author equivocated.
+#[derive(ThisError, Clone, Debug, Serialize, Deserialize)]
+#[error(
+    "{} equivocated in round {}",
+    .existing.metadata().author(),
+    .existing.metadata().round()
+)]
+pub struct Equivocation {
+    pub existing: CertifiedNode,
+    pub conflicting: CertifiedNode,
+}
+
+impl Equivocation {
+    pub fn verify(&self, verifier: &ValidatorVerifier) -> anyhow::Result<()> {
+        ensure!(
+            self.existing.metadata().author() == self.conflicting.metadata().author(),
+            "nodes from different authors"
+        );
+        ensure!(
+            self.existing.metadata().round() == self.conflicting.metadata().round(),
+            "nodes from different rounds"
+        );
+        ensure!(
+            self.existing.digest() != self.conflicting.digest(),
+            "nodes are identical"
+        );
+        for node in [&self.existing, &self.conflicting] {
+            verifier.verify_multi_signatures(
+                &NodeDigest::new(node.digest()),
+                node.certificate().signatures(),
+            )?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct NodeDigestSignature {
     epoch: u64,
@@ -348,16 +464,32 @@ impl BroadcastStatus for SignatureBuilder {
     }
 }
 
+#[derive(Serialize, Deserialize, CryptoHasher, BCSCryptoHash)]
+struct CertifiedAckData {
+    epoch: u64,
+    digest: HashValue,
+}
+
+/// Completes once validators holding a quorum of the voting power acknowledged the certificate.
+/// Acks are folded into a signer bitmap and an aggregate signature as they arrive, so the state
+/// of a broadcast doesn't grow with the number of acks.
 pub struct CertificateAckState {
-    num_validators: usize,
-    received: HashSet<Author>,
+    digest: HashValue,
+    epoch_state: Arc<EpochState>,
+    signers: BitVec,
+    signature: Option<bls12381::Signature>,
+    voting_power: u128,
 }
 
 impl CertificateAckState {
-    pub fn new(num_validators: usize) -> Self {
+    pub fn new(digest: HashValue, epoch_state: Arc<EpochState>) -> Self {
+        let num_validators = epoch_state.verifier.len() as u16;
         Self {
-            num_validators,
-            received: HashSet::new(),
+            digest,
+            epoch_state,
+            signers: BitVec::with_num_bits(num_validators),
+            signature: None,
+            voting_power: 0,
         }
     }
 }
@@ -365,20 +497,89 @@ impl CertificateAckState {
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct CertifiedAck {
     epoch: u64,
+    digest: HashValue,
+    signature: bls12381::Signature,
+}
+
+impl CertifiedAck {
+    pub fn new(
+        epoch: u64,
+        digest: HashValue,
+        signer: &ValidatorSigner,
+    ) -> Result<Self, CryptoMaterialError> {
+        let signature = signer.sign(&CertifiedAckData { epoch, digest })?;
+        Ok(Self {
+            epoch,
+            digest,
+            signature,
+        })
+    }
+}
+
+/// Proof that validators holding a quorum of the voting power received the certificate of the
+/// node with this digest.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CertifiedAckCertificate {
+    epoch: u64,
+    digest: HashValue,
+    signatures: AggregateSignature,
+}
+
+impl CertifiedAckCertificate {
+    pub fn digest(&self) -> &HashValue {
+        &self.digest
+    }
+
+    pub fn signatures(&self) -> &AggregateSignature {
+        &self.signatures
+    }
+
+    pub fn verify(&self, verifier: &ValidatorVerifier) -> anyhow::Result<()> {
+        let data = CertifiedAckData {
+            epoch: self.epoch,
+            digest: self.digest,
+        };
+        Ok(verifier.verify_multi_signatures(&data, &self.signatures)?)
+    }
 }
 
 impl BroadcastStatus for CertificateAckState {
     type Ack = CertifiedAck;
-    type Aggregated = ();
+    type Aggregated = CertifiedAckCertificate;
     type Message = NodeCertificate;
 
-    fn add(&mut self, peer: Author, _ack: Self::Ack) -> anyhow::Result<Option<Self::Aggregated>> {
-        self.received.insert(peer);
-        if self.received.len() == self.num_validators {
-            Ok(Some(()))
-        } else {
-            Ok(None)
+    fn add(&mut self, peer: Author, ack: Self::Ack) -> anyhow::Result<Option<Self::Aggregated>> {
+        ensure!(
+            ack.epoch == self.epoch_state.epoch && ack.digest == self.digest,
+            "ack for a different certificate"
+        );
+        let verifier = &self.epoch_state.verifier;
+        let index = *verifier
+            .address_to_validator_index()
+            .get(&peer)
+            .ok_or_else(|| anyhow::anyhow!("ack from unknown peer"))? as u16;
+        if self.signers.is_set(index) {
+            return Ok(None);
+        }
+        let data = CertifiedAckData {
+            epoch: ack.epoch,
+            digest: ack.digest,
+        };
+        verifier.verify(peer, &data, &ack.signature)?;
+        self.signature = Some(match self.signature.take() {
+            Some(aggregated) => bls12381::Signature::aggregate(vec![aggregated, ack.signature])?,
+            None => ack.signature,
+        });
+        self.signers.set(index);
+        self.voting_power += verifier.get_voting_power(&peer).unwrap_or_default() as u128;
+        if self.voting_power < verifier.quorum_voting_power() {
+            return Ok(None);
         }
+        Ok(Some(CertifiedAckCertificate {
+            epoch: self.epoch_state.epoch,
+            digest: self.digest,
+            signatures: AggregateSignature::new(self.signers.clone(), self.signature.clone()),
+        }))
     }
 }
 
@@ -392,6 +593,24 @@ pub struct FetchRequest {
     exists_bitmask: Vec<Vec<bool>>,
 }
 
+impl FetchRequest {
+    pub fn new(target: NodeMetadata, start_round: Round, exists_bitmask: Vec<Vec<bool>>) -> Self {
+        Self {
+            target,
+            start_round,
+            exists_bitmask,
+        }
+    }
+
+    pub fn target(&self) -> &NodeMetadata {
+        &self.target
+    }
+
+    pub fn start_round(&self) -> Round {
+        self.start_round
+    }
+}
+
 /// Represents a response to FetchRequest, `certified_nodes` are indexed by [round][validator_index]
 /// It should fill in gaps from the `exists_bitmask` according to the parents from the `target_digest` node.
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -401,26 +620,580 @@ pub struct FetchResponse {
 }
 
 impl FetchResponse {
+    pub fn new(epoch: u64, certified_nodes: Vec<Vec<CertifiedNode>>) -> Self {
+        Self {
+            epoch,
+            certifies_nodes: certified_nodes,
+        }
+    }
+
     pub fn certified_nodes(self) -> Vec<Vec<CertifiedNode>> {
         self.certifies_nodes
     }
 
+    /// Checks that every node is below the target and from its epoch, matches its digest and
+    /// carries a valid certificate.
     pub fn verify(
         self,
-        _request: &FetchRequest,
-        _validator_verifier: &ValidatorVerifier,
+        request: &FetchRequest,
+        validator_verifier: &ValidatorVerifier,
     ) -> anyhow::Result<Self> {
-        todo!("verification");
+        let target = request.target();
+        ensure!(
+            self.epoch == target.epoch(),
+            "fetch response from a different epoch"
+        );
+        let mut certificates = vec![];
+        for node in self.certifies_nodes.iter().flatten() {
+            ensure!(
+                node.metadata().epoch() == target.epoch()
+                    && node.metadata().round() >= request.start_round
+                    && node.metadata().round() < target.round(),
+                "node outside of the requested rounds"
+            );
+            ensure!(
+                NodeWithoutDigest::from(node.deref()).hash() == node.digest()
+                    && node.certificate().metadata().digest() == node.metadata().digest(),
+                "node digest mismatch"
+            );
+            certificates.push(node.certificate().clone());
+        }
+        ensure!(
+            verify_certificates(&certificates, validator_verifier).is_ok(),
+            "invalid node certificate"
+        );
+        Ok(self)
+    }
+}
+
+/// Nodes requested in a batch, either by digest or all nodes within an inclusive round range.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum BatchFetchTarget {
+    Digests(Vec<HashValue>),
+    Rounds(Round, Round),
+}
+
+/// Requests all nodes matching the target in a single response, used to catch up on many nodes
+/// without a round-trip per node.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BatchFetchRequest {
+    epoch: u64,
+    target: BatchFetchTarget,
+}
+
+impl BatchFetchRequest {
+    pub fn new(epoch: u64, target: BatchFetchTarget) -> Self {
+        Self { epoch, target }
+    }
+
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    pub fn target(&self) -> &BatchFetchTarget {
+        &self.target
+    }
+}
+
+/// Represents a response to BatchFetchRequest, `certified_nodes` are ordered by round. The
+/// response is `complete` unless the matching nodes didn't fit in a single message, in which case
+/// the requester asks again for the rest.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BatchFetchResponse {
+    epoch: u64,
+    certified_nodes: Vec<CertifiedNode>,
+    complete: bool,
+}
+
+impl BatchFetchResponse {
+    pub fn new(epoch: u64, certified_nodes: Vec<CertifiedNode>, complete: bool) -> Self {
+        Self {
+            epoch,
+            certified_nodes,
+            complete,
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.complete
+    }
+
+    pub fn certified_nodes(self) -> Vec<CertifiedNode> {
+        self.certified_nodes
+    }
+
+    /// Checks that the nodes are in round order, and that every node matches the target and is
+    /// from its epoch, matches its digest and carries a valid certificate.
+    pub fn verify(
+        self,
+        request: &BatchFetchRequest,
+        validator_verifier: &ValidatorVerifier,
+    ) -> anyhow::Result<Self> {
+        ensure!(
+            self.epoch == request.epoch(),
+            "batch fetch response from a different epoch"
+        );
+        ensure!(
+            self.certified_nodes
+                .windows(2)
+                .all(|pair| pair[0].metadata().round() <= pair[1].metadata().round()),
+            "nodes out of round order"
+        );
+        let mut certificates = vec![];
+        for node in &self.certified_nodes {
+            let in_target = match request.target() {
+                BatchFetchTarget::Digests(digests) => digests.contains(&node.digest()),
+                BatchFetchTarget::Rounds(start_round, end_round) => {
+                    (*start_round..=*end_round).contains(&node.metadata().round())
+                },
+            };
+            ensure!(
+                node.metadata().epoch() == request.epoch() && in_target,
+                "node outside of the requested target"
+            );
+            ensure!(
+                NodeWithoutDigest::from(node.deref()).hash() == node.digest()
+                    && node.certificate().metadata().digest() == node.metadata().digest(),
+                "node digest mismatch"
+            );
+            certificates.push(node.certificate().clone());
+        }
+        ensure!(
+            verify_certificates(&certificates, validator_verifier).is_ok(),
+            "invalid node certificate"
+        );
+        Ok(self)
+    }
+}
+
+#[derive(Serialize, Deserialize, CryptoHasher, BCSCryptoHash)]
+struct RoundTimeoutData {
+    epoch: u64,
+    round: Round,
+}
+
+/// Signals that a validator timed out waiting for enough nodes to advance past the round.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RoundTimeout {
+    epoch: u64,
+    round: Round,
+    author: Author,
+    signature: bls12381::Signature,
+}
+
+impl RoundTimeout {
+    pub fn new(
+        epoch: u64,
+        round: Round,
+        signer: &ValidatorSigner,
+    ) -> Result<Self, CryptoMaterialError> {
+        let signature = signer.sign(&RoundTimeoutData { epoch, round })?;
+        Ok(Self {
+            epoch,
+            round,
+            author: signer.author(),
+            signature,
+        })
+    }
+
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    pub fn round(&self) -> Round {
+        self.round
+    }
+
+    pub fn author(&self) -> &Author {
+        &self.author
+    }
+
+    pub fn signature(&self) -> &bls12381::Signature {
+        &self.signature
+    }
+
+    pub fn verify(&self, verifier: &ValidatorVerifier) -> anyhow::Result<()> {
+        let data = RoundTimeoutData {
+            epoch: self.epoch,
+            round: self.round,
+        };
+        Ok(verifier.verify(self.author, &data, &self.signature)?)
+    }
+}
+
+/// Quorum of round timeouts, which lets the DAG move past a round that can't gather enough nodes.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RoundTimeoutCertificate {
+    epoch: u64,
+    round: Round,
+    signatures: AggregateSignature,
+}
+
+impl RoundTimeoutCertificate {
+    pub fn new(epoch: u64, round: Round, signatures: AggregateSignature) -> Self {
+        Self {
+            epoch,
+            round,
+            signatures,
+        }
+    }
+
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    pub fn round(&self) -> Round {
+        self.round
+    }
+
+    pub fn verify(&self, verifier: &ValidatorVerifier) -> anyhow::Result<()> {
+        let data = RoundTimeoutData {
+            epoch: self.epoch,
+            round: self.round,
+        };
+        Ok(verifier.verify_multi_signatures(&data, &self.signatures)?)
     }
 }
 
+/// Tells the author of a round timeout that it was received.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RoundTimeoutAck {
+    epoch: u64,
+    round: Round,
+}
+
+impl RoundTimeoutAck {
+    pub fn new(epoch: u64, round: Round) -> Self {
+        Self { epoch, round }
+    }
+}
+
+/// Delivers a round timeout to every validator, complete once all of them acked it.
+pub struct RoundTimeoutDelivery {
+    epoch_state: Arc<EpochState>,
+    round: Round,
+    acked: HashSet<Author>,
+}
+
+impl RoundTimeoutDelivery {
+    pub fn new(round: Round, epoch_state: Arc<EpochState>) -> Self {
+        Self {
+            epoch_state,
+            round,
+            acked: HashSet::new(),
+        }
+    }
+}
+
+impl BroadcastStatus for RoundTimeoutDelivery {
+    type Ack = RoundTimeoutAck;
+    type Aggregated = ();
+    type Message = RoundTimeout;
+
+    fn add(&mut self, peer: Author, ack: Self::Ack) -> anyhow::Result<Option<Self::Aggregated>> {
+        ensure!(
+            ack.epoch == self.epoch_state.epoch && ack.round == self.round,
+            "ack for a different round timeout"
+        );
+        self.acked.insert(peer);
+        Ok((self.acked.len() == self.epoch_state.verifier.len()).then_some(()))
+    }
+}
+
+#[derive(ThisError, Debug)]
+pub enum DAGVersionError {
+    #[error("incompatible major version {0}, expected {}", DAG_MAJOR_VERSION)]
+    IncompatibleMajorVersion(u8),
+}
+
+#[derive(ThisError, Debug)]
+pub enum DAGMessageSizeError {
+    #[error("dag message of {0} bytes exceeds the limit of {1} bytes")]
+    TooLarge(usize, usize),
+}
+
+#[derive(ThisError, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    #[error("dag network message truncated, {0} more bytes expected")]
+    Truncated(usize),
+    #[error("invalid length prefix")]
+    InvalidLength,
+    #[error("dag message of {0} bytes exceeds the limit of {1} bytes")]
+    TooLarge(usize, usize),
+    #[error("{0} trailing bytes after the dag network message")]
+    TrailingBytes(usize),
+}
+
+pub const DAG_MAJOR_VERSION: u8 = 1;
+pub const DAG_MINOR_VERSION: u8 = 0;
+
+/// Flag byte prefixed to the payload of a `DAGNetworkMessage`.
+pub const UNCOMPRESSED_FLAG: u8 = 0;
+pub const LZ4_COMPRESSED_FLAG: u8 = 1;
+
+/// Payloads smaller than this are not worth compressing.
+pub const DEFAULT_COMPRESSION_THRESHOLD: usize = 4 * 1024;
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct DAGNetworkMessage {
+    /// Major version in the high byte and minor version in the low byte. Peers of the
+    /// same major version are compatible, newer minor versions may only append fields.
+    pub version: u16,
     pub epoch: u64,
+    /// A compression flag byte followed by the BCS encoded message.
     #[serde(with = "serde_bytes")]
     pub data: Vec<u8>,
 }
 
+impl DAGNetworkMessage {
+    pub const CURRENT_VERSION: u16 = ((DAG_MAJOR_VERSION as u16) << 8) | DAG_MINOR_VERSION as u16;
+    /// Size of the version and epoch in front of the payload.
+    const HEADER_BYTES: usize = 2 + 8;
+
+    pub fn new<T: Serialize>(epoch: u64, message: &T) -> anyhow::Result<Self> {
+        Self::with_compression_threshold(epoch, message, DEFAULT_COMPRESSION_THRESHOLD)
+    }
+
+    /// Compresses the payload if it is at least `threshold` bytes and compression shrinks it,
+    /// `usize::MAX` disables compression. Every peer decodes both forms.
+    pub fn with_compression_threshold<T: Serialize>(
+        epoch: u64,
+        message: &T,
+        threshold: usize,
+    ) -> anyhow::Result<Self> {
+        let raw = bcs::to_bytes(message)?;
+        let mut data = vec![UNCOMPRESSED_FLAG];
+        if raw.len() >= threshold {
+            let compressed =
+                aptos_compression::compress(raw.clone(), CompressionClient::Consensus, usize::MAX)?;
+            if compressed.len() < raw.len() {
+                data[0] = LZ4_COMPRESSED_FLAG;
+                data.extend(compressed);
+            }
+        }
+        if data[0] == UNCOMPRESSED_FLAG {
+            data.extend(raw);
+        }
+        Ok(Self {
+            version: Self::CURRENT_VERSION,
+            epoch,
+            data,
+        })
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        self.data.first() == Some(&LZ4_COMPRESSED_FLAG)
+    }
+
+    /// Whether the payload is a high priority `DAGMessage`, see `DAGMessage::is_high_priority`.
+    /// Only the variant tag in front of the payload is read, nothing is decoded. High priority
+    /// messages are small, so a compressed payload is never one.
+    pub fn is_high_priority(&self) -> bool {
+        if self.major_version() != DAG_MAJOR_VERSION {
+            return false;
+        }
+        match self.data.split_first() {
+            Some((&UNCOMPRESSED_FLAG, payload)) => Self::read_uleb128(payload)
+                .map(|(tag, _)| DAGMessage::HIGH_PRIORITY_TAGS.contains(&tag))
+                .unwrap_or(false),
+            _ => false,
+        }
+    }
+
+    pub fn major_version(&self) -> u8 {
+        (self.version >> 8) as u8
+    }
+
+    pub fn minor_version(&self) -> u8 {
+        self.version as u8
+    }
+
+    /// Decodes the payload, skipping any trailing fields appended by a newer minor version.
+    pub fn decode<T: Serialize + DeserializeOwned>(&self) -> anyhow::Result<T> {
+        self.decode_with_limit(MAX_MESSAGE_SIZE)
+    }
+
+    /// Like `decode`, but fails with `DAGMessageSizeError` if the payload is larger than
+    /// max_bytes, compressed or not. The size is checked before anything is decompressed or
+    /// deserialized.
+    pub fn decode_with_limit<T: Serialize + DeserializeOwned>(
+        &self,
+        max_bytes: usize,
+    ) -> anyhow::Result<T> {
+        ensure!(
+            self.major_version() == DAG_MAJOR_VERSION,
+            DAGVersionError::IncompatibleMajorVersion(self.major_version())
+        );
+        ensure!(
+            self.data.len() <= max_bytes,
+            DAGMessageSizeError::TooLarge(self.data.len(), max_bytes)
+        );
+        let (flag, payload) = self
+            .data
+            .split_first()
+            .ok_or_else(|| anyhow::anyhow!("empty dag network message"))?;
+        let raw = match *flag {
+            UNCOMPRESSED_FLAG => payload.to_vec(),
+            LZ4_COMPRESSED_FLAG => {
+                // lz4 blocks start with their decompressed size as a little endian u32
+                let size = payload
+                    .get(..4)
+                    .map(|prefix| u32::from_le_bytes([prefix[0], prefix[1], prefix[2], prefix[3]]))
+                    .ok_or_else(|| anyhow::anyhow!("missing decompressed size"))?
+                    as usize;
+                ensure!(
+                    size <= max_bytes,
+                    DAGMessageSizeError::TooLarge(size, max_bytes)
+                );
+                aptos_compression::decompress(
+                    &payload.to_vec(),
+                    CompressionClient::Consensus,
+                    max_bytes,
+                )?
+            },
+            flag => anyhow::bail!("unknown compression flag {}", flag),
+        };
+        if self.minor_version() > DAG_MINOR_VERSION {
+            return Ok(bcs::from_bytes_seed(
+                SkipTrailing {
+                    len: raw.len(),
+                    _phantom: PhantomData,
+                },
+                &raw,
+            )?);
+        }
+        Ok(bcs::from_bytes(&raw)?)
+    }
+
+    /// Encodes the message as its BCS form: the version and epoch in little endian followed by
+    /// the ULEB128 length of the payload and the payload itself.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(Self::HEADER_BYTES + 5 + self.data.len());
+        bytes.extend(self.version.to_le_bytes());
+        bytes.extend(self.epoch.to_le_bytes());
+        let mut len = self.data.len();
+        while len >= 0x80 {
+            bytes.push((len & 0x7F) as u8 | 0x80);
+            len >>= 7;
+        }
+        bytes.push(len as u8);
+        bytes.extend(&self.data);
+        bytes
+    }
+
+    /// Decodes the output of `to_bytes`, checking every length against the input before it is
+    /// used. The payload is limited to `MAX_MESSAGE_SIZE` bytes and trailing bytes are rejected,
+    /// so that there is a single valid encoding of each message.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DecodeError> {
+        if bytes.len() < Self::HEADER_BYTES {
+            return Err(DecodeError::Truncated(Self::HEADER_BYTES - bytes.len()));
+        }
+        let (header, rest) = bytes.split_at(Self::HEADER_BYTES);
+        let (version, epoch) = header.split_at(2);
+        let version = u16::from_le_bytes([version[0], version[1]]);
+        let mut epoch_bytes = [0; 8];
+        epoch_bytes.copy_from_slice(epoch);
+        let epoch = u64::from_le_bytes(epoch_bytes);
+
+        let (len, rest) = Self::read_uleb128(rest)?;
+        if len > MAX_MESSAGE_SIZE {
+            return Err(DecodeError::TooLarge(len, MAX_MESSAGE_SIZE));
+        }
+        if rest.len() < len {
+            return Err(DecodeError::Truncated(len - rest.len()));
+        }
+        if rest.len() > len {
+            return Err(DecodeError::TrailingBytes(rest.len() - len));
+        }
+        Ok(Self {
+            version,
+            epoch,
+            data: rest.to_vec(),
+        })
+    }
+
+    /// Reads a canonical ULEB128 length that fits in a u32, as BCS encodes sequence lengths.
+    fn read_uleb128(bytes: &[u8]) -> Result<(usize, &[u8]), DecodeError> {
+        let mut value: u64 = 0;
+        for (i, byte) in bytes.iter().enumerate().take(5) {
+            value |= ((byte & 0x7F) as u64) << (7 * i);
+            if byte & 0x80 == 0 {
+                // a zero last byte means the length could have been encoded in fewer bytes
+                if (i > 0 && *byte == 0) || value > u32::MAX as u64 {
+                    return Err(DecodeError::InvalidLength);
+                }
+                return Ok((value as usize, &bytes[i + 1..]));
+            }
+        }
+        if bytes.len() < 5 {
+            Err(DecodeError::Truncated(1))
+        } else {
+            Err(DecodeError::InvalidLength)
+        }
+    }
+}
+
+/// BCS has no notion of remaining input, so the payload is read as a tuple of the known
+/// prefix followed by as many raw bytes as are left over.
+struct SkipTrailing<T> {
+    len: usize,
+    _phantom: PhantomData<T>,
+}
+
+impl<'de, T: Serialize + DeserializeOwned> DeserializeSeed<'de> for SkipTrailing<T> {
+    type Value = T;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<T, D::Error> {
+        deserializer.deserialize_tuple(2, self)
+    }
+}
+
+impl<'de, T: Serialize + DeserializeOwned> Visitor<'de> for SkipTrailing<T> {
+    type Value = T;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a message followed by unknown trailing fields")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<T, A::Error> {
+        let value: T = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+        let known = bcs::serialized_size(&value).map_err(de::Error::custom)?;
+        let trailing = self
+            .len
+            .checked_sub(known)
+            .ok_or_else(|| de::Error::invalid_length(known, &self))?;
+        seq.next_element_seed(SkipBytes(trailing))?;
+        Ok(value)
+    }
+}
+
+struct SkipBytes(usize);
+
+impl<'de> DeserializeSeed<'de> for SkipBytes {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_tuple(self.0, self)
+    }
+}
+
+impl<'de> Visitor<'de> for SkipBytes {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "{} trailing bytes", self.0)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        for _ in 0..self.0 {
+            seq.next_element::<u8>()?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub enum DAGMessage {
     NodeMsg(Node),
@@ -429,6 +1202,10 @@ pub enum DAGMessage {
     CertifiedAckMsg(CertifiedAck),
     FetchRequest(FetchRequest),
     FetchResponse(FetchResponse),
+    RoundTimeoutMsg(RoundTimeout),
+    BatchFetchRequest(BatchFetchRequest),
+    BatchFetchResponse(BatchFetchResponse),
+    RoundTimeoutAckMsg(RoundTimeoutAck),
 
     #[cfg(test)]
     TestMessage(TestMessage),
@@ -437,7 +1214,12 @@ pub enum DAGMessage {
 }
 
 impl DAGMessage {
-    pub fn name(&self) -> &str {
+    /// BCS variant tags of the high priority messages, in the order of the variants above, so
+    /// that `DAGNetworkMessage::is_high_priority` can tell them apart without decoding. Checked
+    /// against `is_high_priority` for every variant by `test_high_priority_without_decoding`.
+    const HIGH_PRIORITY_TAGS: [usize; 5] = [1, 2, 3, 6, 9];
+
+    pub fn name(&self) -> &'static str {
         match self {
             DAGMessage::NodeMsg(_) => "NodeMsg",
             DAGMessage::NodeDigestSignatureMsg(_) => "NodeDigestSignatureMsg",
@@ -445,12 +1227,48 @@ impl DAGMessage {
             DAGMessage::CertifiedAckMsg(_) => "CertifiedAckMsg",
             DAGMessage::FetchRequest(_) => "FetchRequest",
             DAGMessage::FetchResponse(_) => "FetchResponse",
+            DAGMessage::RoundTimeoutMsg(_) => "RoundTimeoutMsg",
+            DAGMessage::BatchFetchRequest(_) => "BatchFetchRequest",
+            DAGMessage::BatchFetchResponse(_) => "BatchFetchResponse",
+            DAGMessage::RoundTimeoutAckMsg(_) => "RoundTimeoutAckMsg",
             #[cfg(test)]
             DAGMessage::TestMessage(_) => "TestMessage",
             #[cfg(test)]
             DAGMessage::TestAck(_) => "TestAck",
         }
     }
+
+    /// Author of the messages that are only ever sent by their author, None for the others.
+    pub fn author(&self) -> Option<&Author> {
+        match self {
+            DAGMessage::NodeMsg(node) => Some(node.author()),
+            DAGMessage::RoundTimeoutMsg(timeout) => Some(timeout.author()),
+            _ => None,
+        }
+    }
+
+    /// Round of the node, certificate or timeout the message carries, None for the others.
+    pub fn round(&self) -> Option<Round> {
+        match self {
+            DAGMessage::NodeMsg(node) => Some(node.metadata.round),
+            DAGMessage::NodeCertificateMsg(certificate) => Some(certificate.metadata.round),
+            DAGMessage::RoundTimeoutMsg(timeout) => Some(timeout.round()),
+            _ => None,
+        }
+    }
+
+    /// Small messages on the critical path of advancing rounds, which bandwidth limits never
+    /// hold back.
+    pub fn is_high_priority(&self) -> bool {
+        matches!(
+            self,
+            DAGMessage::NodeDigestSignatureMsg(_)
+                | DAGMessage::NodeCertificateMsg(_)
+                | DAGMessage::CertifiedAckMsg(_)
+                | DAGMessage::RoundTimeoutMsg(_)
+                | DAGMessage::RoundTimeoutAckMsg(_)
+        )
+    }
 }
 
 impl TConsensusMsg for DAGMessage {
@@ -462,6 +1280,10 @@ impl TConsensusMsg for DAGMessage {
             DAGMessage::CertifiedAckMsg(ack) => ack.epoch,
             DAGMessage::FetchRequest(req) => req.target.epoch,
             DAGMessage::FetchResponse(res) => res.epoch,
+            DAGMessage::RoundTimeoutMsg(timeout) => timeout.epoch,
+            DAGMessage::BatchFetchRequest(req) => req.epoch,
+            DAGMessage::BatchFetchResponse(res) => res.epoch,
+            DAGMessage::RoundTimeoutAckMsg(ack) => ack.epoch,
             #[cfg(test)]
             DAGMessage::TestMessage(_) => 1,
             #[cfg(test)]
@@ -544,6 +1366,50 @@ impl TryFrom<DAGMessage> for FetchResponse {
     }
 }
 
+impl TryFrom<DAGMessage> for RoundTimeout {
+    type Error = anyhow::Error;
+
+    fn try_from(msg: DAGMessage) -> Result<Self, Self::Error> {
+        match msg {
+            DAGMessage::RoundTimeoutMsg(timeout) => Ok(timeout),
+            _ => Err(anyhow::anyhow!("invalid message type")),
+        }
+    }
+}
+
+impl TryFrom<DAGMessage> for BatchFetchRequest {
+    type Error = anyhow::Error;
+
+    fn try_from(msg: DAGMessage) -> Result<Self, Self::Error> {
+        match msg {
+            DAGMessage::BatchFetchRequest(req) => Ok(req),
+            _ => Err(anyhow::anyhow!("invalid message type")),
+        }
+    }
+}
+
+impl TryFrom<DAGMessage> for BatchFetchResponse {
+    type Error = anyhow::Error;
+
+    fn try_from(msg: DAGMessage) -> Result<Self, Self::Error> {
+        match msg {
+            DAGMessage::BatchFetchResponse(res) => Ok(res),
+            _ => Err(anyhow::anyhow!("invalid message type")),
+        }
+    }
+}
+
+impl TryFrom<DAGMessage> for RoundTimeoutAck {
+    type Error = anyhow::Error;
+
+    fn try_from(msg: DAGMessage) -> Result<Self, Self::Error> {
+        match msg {
+            DAGMessage::RoundTimeoutAckMsg(ack) => Ok(ack),
+            _ => Err(anyhow::anyhow!("invalid message type")),
+        }
+    }
+}
+
 impl From<Node> for DAGMessage {
     fn from(node: Node) -> Self {
         Self::NodeMsg(node)
@@ -580,6 +1446,30 @@ impl From<FetchResponse> for DAGMessage {
     }
 }
 
+impl From<RoundTimeout> for DAGMessage {
+    fn from(timeout: RoundTimeout) -> Self {
+        Self::RoundTimeoutMsg(timeout)
+    }
+}
+
+impl From<BatchFetchRequest> for DAGMessage {
+    fn from(req: BatchFetchRequest) -> Self {
+        Self::BatchFetchRequest(req)
+    }
+}
+
+impl From<BatchFetchResponse> for DAGMessage {
+    fn from(response: BatchFetchResponse) -> Self {
+        Self::BatchFetchResponse(response)
+    }
+}
+
+impl From<RoundTimeoutAck> for DAGMessage {
+    fn from(ack: RoundTimeoutAck) -> Self {
+        Self::RoundTimeoutAckMsg(ack)
+    }
+}
+
 #[cfg(test)]
 #[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Hash, Debug)]
 pub struct TestMessage(pub Vec<u8>);
@@ -51,16 +51,13 @@ pub trait TConsensusMsg: Sized + Clone + Serialize + DeserializeOwned {
 
     fn from_network_message(msg: ConsensusMsg) -> anyhow::Result<Self> {
         match msg {
-            ConsensusMsg::DAGMessage(msg) => Ok(bcs::from_bytes(&msg.data)?),
+            ConsensusMsg::DAGMessage(msg) => msg.decode(),
             _ => bail!("unexpected consensus message type {:?}", msg),
         }
     }
 
     fn into_network_message(self) -> ConsensusMsg {
-        ConsensusMsg::DAGMessage(DAGNetworkMessage {
-            epoch: self.epoch(),
-            data: bcs::to_bytes(&self).unwrap(),
-        })
+        ConsensusMsg::DAGMessage(DAGNetworkMessage::new(self.epoch(), &self).unwrap())
     }
 }
 
@@ -83,6 +80,7 @@ pub struct IncomingBatchRetrievalRequest {
 #[derive(Debug)]
 pub struct IncomingDAGRequest {
     pub req: ConsensusMsg,
+    pub sender: Author,
     pub protocol: ProtocolId,
     pub response_sender: oneshot::Sender<Result<Bytes, RpcError>>,
 }